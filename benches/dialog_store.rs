@@ -0,0 +1,132 @@
+//! Compares the dialog store backends, the Postgres one is only measured when `DATABASE_URL` is set
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use social_network::{
+  db::{
+    dialogs::{memory::MemoryDialogStore, postgres::PgDialogStore, DialogStore},
+    shards::ShardMap,
+  },
+  dto::dialog::DialogKey,
+};
+use sqlx::{
+  postgres::{PgConnectOptions, PgPoolOptions},
+  ConnectOptions, Executor,
+};
+use tokio::runtime::Runtime;
+
+const BENCH_DATABASE: &str = "sn_bench_dialog_store";
+const PEERS: i32 = 100;
+const MESSAGES_PER_DIALOG: i32 = 20;
+
+async fn postgres_store(database_url: &str) -> DialogStore {
+  let options = database_url.parse::<PgConnectOptions>().unwrap();
+
+  let mut connection = options.connect().await.unwrap();
+  connection
+    .execute(
+      format!(
+        r#"DROP DATABASE IF EXISTS "{}" WITH (FORCE)"#,
+        BENCH_DATABASE
+      )
+      .as_str(),
+    )
+    .await
+    .unwrap();
+  connection
+    .execute(format!(r#"CREATE DATABASE "{}""#, BENCH_DATABASE).as_str())
+    .await
+    .unwrap();
+
+  let pool = PgPoolOptions::new()
+    .connect_with(options.database(BENCH_DATABASE))
+    .await
+    .unwrap();
+  sqlx::migrate!().run(&pool).await.unwrap();
+
+  DialogStore::Postgres(PgDialogStore::new(ShardMap::new(vec![pool])))
+}
+
+async fn memory_store() -> DialogStore {
+  let dir = std::env::temp_dir().join(BENCH_DATABASE);
+  let _ = tokio::fs::remove_dir_all(&dir).await;
+
+  DialogStore::Memory(MemoryDialogStore::open(&dir).await.unwrap())
+}
+
+/// User 1 has a dialog with every peer
+async fn seed(store: &DialogStore) {
+  for peer_id in 2..=PEERS + 1 {
+    for index in 0..MESSAGES_PER_DIALOG {
+      store
+        .insert_message(peer_id, 1, format!("Message {}", index), None)
+        .await
+        .unwrap();
+    }
+  }
+}
+
+fn dialog_store(c: &mut Criterion) {
+  let runtime = Runtime::new().unwrap();
+  let stores = runtime.block_on(async {
+    let mut stores = vec![("memory", memory_store().await)];
+    if let Ok(database_url) = std::env::var("DATABASE_URL") {
+      stores.push(("postgres", postgres_store(&database_url).await));
+    }
+
+    for (_, store) in &stores {
+      seed(store).await;
+    }
+    stores
+  });
+
+  let mut group = c.benchmark_group("dialog_store");
+  for (backend, store) in &stores {
+    group.bench_with_input(
+      BenchmarkId::new("insert_message", backend),
+      store,
+      |b, store| {
+        b.to_async(&runtime).iter(|| async {
+          store
+            .insert_message(1, 2, "Benchmark".to_string(), None)
+            .await
+            .unwrap()
+        })
+      },
+    );
+
+    group.bench_with_input(
+      BenchmarkId::new("list_messages", backend),
+      store,
+      |b, store| {
+        b.to_async(&runtime).iter(|| async {
+          store
+            .list_messages(DialogKey::new(1, 3), None, 50)
+            .await
+            .unwrap()
+        })
+      },
+    );
+
+    group.bench_with_input(
+      BenchmarkId::new("list_dialogs", backend),
+      store,
+      |b, store| {
+        b.to_async(&runtime)
+          .iter(|| async { store.list_dialogs(1).await.unwrap() })
+      },
+    );
+
+    group.bench_with_input(
+      BenchmarkId::new("unread_counts", backend),
+      store,
+      |b, store| {
+        b.to_async(&runtime)
+          .iter(|| async { store.unread_counts().await.unwrap() })
+      },
+    );
+  }
+  group.finish();
+}
+
+criterion_group!(benches, dialog_store);
+criterion_main!(benches);
@@ -0,0 +1,109 @@
+//! Concurrent `JwtService::decode` calls with the shared Redis connection against the previous
+//! mutex-guarded one. Only measured when `REDIS_URL` is set
+
+use std::sync::Arc;
+
+use clap::Parser;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::future::join_all;
+use jsonwebtoken::{DecodingKey, Validation};
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use social_network::{
+  config::AppConfig,
+  db::{RedisClient, RedisOptions},
+  services::jwt::JwtService,
+};
+use tokio::{runtime::Runtime, sync::Mutex};
+
+const JWT_SECRET: &str = "bench-secret";
+const CONCURRENCY: [usize; 3] = [1, 16, 64];
+
+/// `decode` as it was with `Arc<Mutex<MultiplexedConnection>>`, every blacklist check waiting
+/// for the lock
+async fn mutex_decode(redis: &Mutex<MultiplexedConnection>, token: &str) {
+  jsonwebtoken::decode::<serde_json::Value>(
+    token,
+    &DecodingKey::from_secret(JWT_SECRET.as_bytes()),
+    &Validation::default(),
+  )
+  .unwrap();
+
+  redis
+    .lock()
+    .await
+    .get::<_, Option<String>>(format!("jwt_blacklist:{}", token))
+    .await
+    .unwrap();
+}
+
+fn jwt_decode(c: &mut Criterion) {
+  let Ok(redis_url) = std::env::var("REDIS_URL") else {
+    eprintln!("REDIS_URL is not set, skipping the jwt_decode benchmark");
+    return;
+  };
+
+  let runtime = Runtime::new().unwrap();
+  let (jwt_service, mutex_connection, token) = runtime.block_on(async {
+    let config = AppConfig::parse_from([
+      "social_network",
+      "--database-url",
+      "postgres://localhost/unused",
+      "--redis-url",
+      &redis_url,
+      "--jwt-secret",
+      JWT_SECRET,
+    ]);
+    let jwt_service = JwtService::new(
+      Arc::new(config),
+      RedisClient::connect(&redis_url, RedisOptions::default())
+        .await
+        .unwrap(),
+    );
+    let token = jwt_service.build_tokens("1").await.unwrap().access_token;
+
+    let mutex_connection = redis::Client::open(redis_url.as_str())
+      .unwrap()
+      .get_multiplexed_async_connection()
+      .await
+      .unwrap();
+
+    (jwt_service, Arc::new(Mutex::new(mutex_connection)), token)
+  });
+
+  let mut group = c.benchmark_group("jwt_decode");
+  for concurrency in CONCURRENCY {
+    group.throughput(Throughput::Elements(concurrency as u64));
+
+    group.bench_with_input(
+      BenchmarkId::new("mutex", concurrency),
+      &concurrency,
+      |b, &concurrency| {
+        b.to_async(&runtime).iter(|| {
+          join_all((0..concurrency).map(|_| {
+            let redis = mutex_connection.clone();
+            let token = token.clone();
+            tokio::spawn(async move { mutex_decode(&redis, &token).await })
+          }))
+        })
+      },
+    );
+
+    group.bench_with_input(
+      BenchmarkId::new("connection_manager", concurrency),
+      &concurrency,
+      |b, &concurrency| {
+        b.to_async(&runtime).iter(|| {
+          join_all((0..concurrency).map(|_| {
+            let jwt_service = jwt_service.clone();
+            let token = token.clone();
+            tokio::spawn(async move { jwt_service.decode(&token).await.unwrap() })
+          }))
+        })
+      },
+    );
+  }
+  group.finish();
+}
+
+criterion_group!(benches, jwt_decode);
+criterion_main!(benches);
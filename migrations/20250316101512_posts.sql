@@ -0,0 +1,12 @@
+CREATE TABLE posts (
+    id BIGSERIAL PRIMARY KEY,
+
+    author_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    visibility VARCHAR(32) NOT NULL DEFAULT 'public',
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX posts_author_id_id_idx ON posts (author_id, id DESC);
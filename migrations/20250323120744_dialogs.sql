@@ -0,0 +1,38 @@
+CREATE TABLE user_blocks (
+    blocker_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    blocked_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (blocker_id, blocked_id)
+);
+
+-- Messages of a dialog are keyed by the ordered user pair (user_a < user_b)
+CREATE TABLE messages (
+    id BIGSERIAL,
+
+    user_a INTEGER NOT NULL,
+    user_b INTEGER NOT NULL,
+    sender_id INTEGER NOT NULL,
+    text TEXT NOT NULL,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (user_a, user_b, id),
+    CHECK (user_a < user_b)
+);
+
+-- Every dialog has a row per participant holding its read state
+CREATE TABLE dialogs (
+    user_id INTEGER NOT NULL,
+    peer_id INTEGER NOT NULL,
+
+    last_message_id BIGINT NOT NULL,
+    last_read_message_id BIGINT NOT NULL DEFAULT 0,
+
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (user_id, peer_id)
+);
+
+CREATE INDEX dialogs_user_id_updated_at_idx ON dialogs (user_id, updated_at DESC);
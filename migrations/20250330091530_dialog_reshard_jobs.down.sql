@@ -0,0 +1 @@
+DROP TABLE IF EXISTS dialog_reshard_jobs;
@@ -0,0 +1,20 @@
+-- Progress of moving dialogs to a newly added shard, see src/bin/reshard.rs
+CREATE TABLE dialog_reshard_jobs (
+    id SERIAL PRIMARY KEY,
+
+    source_shards INTEGER NOT NULL,
+    target_shards INTEGER NOT NULL,
+    phase VARCHAR(32) NOT NULL,
+
+    checkpoint_shard INTEGER NOT NULL DEFAULT 0,
+    checkpoint_user_a INTEGER NOT NULL DEFAULT 0,
+    checkpoint_user_b INTEGER NOT NULL DEFAULT 0,
+    moved_dialogs BIGINT NOT NULL DEFAULT 0,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Only one job may be in progress
+CREATE UNIQUE INDEX dialog_reshard_jobs_in_progress_idx ON dialog_reshard_jobs ((TRUE))
+    WHERE phase NOT IN ('done', 'aborted');
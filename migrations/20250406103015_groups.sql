@@ -0,0 +1,39 @@
+CREATE TABLE group_chats (
+    id SERIAL PRIMARY KEY,
+
+    title VARCHAR(255) NOT NULL,
+    created_by INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Members with their role and read state
+CREATE TABLE group_members (
+    group_id INTEGER NOT NULL REFERENCES group_chats(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+    role VARCHAR(32) NOT NULL DEFAULT 'member',
+    last_read_message_id BIGINT NOT NULL DEFAULT 0,
+
+    joined_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (group_id, user_id)
+);
+
+CREATE INDEX group_members_user_id_idx ON group_members (user_id);
+
+-- Member events are kept as system messages of the acting member
+CREATE TABLE group_messages (
+    id BIGSERIAL PRIMARY KEY,
+
+    group_id INTEGER NOT NULL REFERENCES group_chats(id) ON DELETE CASCADE,
+    sender_id INTEGER NOT NULL,
+    kind VARCHAR(32) NOT NULL DEFAULT 'text',
+    text TEXT NOT NULL,
+    target_user_id INTEGER,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX group_messages_group_id_id_idx ON group_messages (group_id, id DESC);
@@ -0,0 +1,9 @@
+ALTER TABLE group_chats DROP COLUMN IF EXISTS message_ttl;
+DROP TABLE IF EXISTS group_message_edits;
+DROP INDEX IF EXISTS group_messages_expires_at_idx;
+ALTER TABLE group_messages DROP COLUMN IF EXISTS edited_at, DROP COLUMN IF EXISTS expires_at;
+
+ALTER TABLE dialogs DROP COLUMN IF EXISTS message_ttl;
+DROP TABLE IF EXISTS message_edits;
+DROP INDEX IF EXISTS messages_expires_at_idx;
+ALTER TABLE messages DROP COLUMN IF EXISTS edited_at, DROP COLUMN IF EXISTS expires_at;
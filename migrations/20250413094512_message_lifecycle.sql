@@ -0,0 +1,41 @@
+-- Dialog messages, applied to every dialog shard
+ALTER TABLE messages
+    ADD COLUMN edited_at TIMESTAMPTZ,
+    ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX messages_expires_at_idx ON messages (expires_at) WHERE expires_at IS NOT NULL;
+
+-- Previous versions of edited messages, version 1 is the original text
+CREATE TABLE message_edits (
+    user_a INTEGER NOT NULL,
+    user_b INTEGER NOT NULL,
+    message_id BIGINT NOT NULL,
+    version INTEGER NOT NULL,
+
+    text TEXT NOT NULL,
+    edited_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (user_a, user_b, message_id, version),
+    FOREIGN KEY (user_a, user_b, message_id) REFERENCES messages (user_a, user_b, id) ON DELETE CASCADE
+);
+
+-- Disappearing messages timeout in seconds, kept equal on both rows of the dialog
+ALTER TABLE dialogs ADD COLUMN message_ttl INTEGER;
+
+ALTER TABLE group_messages
+    ADD COLUMN edited_at TIMESTAMPTZ,
+    ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX group_messages_expires_at_idx ON group_messages (expires_at) WHERE expires_at IS NOT NULL;
+
+CREATE TABLE group_message_edits (
+    message_id BIGINT NOT NULL REFERENCES group_messages(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+
+    text TEXT NOT NULL,
+    edited_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (message_id, version)
+);
+
+ALTER TABLE group_chats ADD COLUMN message_ttl INTEGER;
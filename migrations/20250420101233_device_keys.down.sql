@@ -0,0 +1,2 @@
+ALTER TABLE messages DROP COLUMN IF EXISTS envelope;
+DROP TABLE IF EXISTS device_keys;
@@ -0,0 +1,21 @@
+-- Public keys published by user devices for end-to-end encryption, private keys never leave them
+CREATE TABLE device_keys (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id VARCHAR(64) NOT NULL,
+
+    -- Ed25519 key identifying the device, it signs the prekeys
+    identity_key TEXT NOT NULL,
+    -- X25519 key senders agree on message keys with, rotated by the device
+    signed_prekey_id INTEGER NOT NULL,
+    signed_prekey TEXT NOT NULL,
+    signed_prekey_signature TEXT NOT NULL,
+
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (user_id, device_id)
+);
+
+-- Ciphertexts of encrypted dialog messages for every device of the participants, the text stays empty.
+-- Applied to every dialog shard
+ALTER TABLE messages ADD COLUMN envelope JSONB;
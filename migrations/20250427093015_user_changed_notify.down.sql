@@ -0,0 +1,2 @@
+DROP TRIGGER IF EXISTS user_changed ON users;
+DROP FUNCTION IF EXISTS notify_user_changed();
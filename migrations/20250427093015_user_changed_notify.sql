@@ -0,0 +1,11 @@
+-- Instances cache users by id and evict them on this notification, whoever changes the row
+CREATE OR REPLACE FUNCTION notify_user_changed() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('user_changed', OLD.id::TEXT);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER user_changed
+    AFTER UPDATE OR DELETE ON users
+    FOR EACH ROW EXECUTE FUNCTION notify_user_changed();
@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use axum::{extract::State, response::IntoResponse, Extension, Json};
+
+use crate::{
+  app_state::AppState,
+  dto::{counter::CountersResponse, error::ErrorResponse, user::UserDto},
+};
+
+#[utoipa::path(
+  get,
+  path = "/counters",
+  tags = ["Dialog"],
+  description = "Get unread message counters of the current user, in total and by peer",
+  responses(
+    (status = 200, description = "Unread counters", body = CountersResponse),
+    (status = 503, description = "Counters are unavailable", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn get_counters(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+) -> impl IntoResponse {
+  app_state
+    .counter_service
+    .get(user.id)
+    .await
+    .map(|counters| Json(CountersResponse::from(counters)))
+}
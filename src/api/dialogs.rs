@@ -0,0 +1,343 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::IntoResponse,
+  Extension, Json,
+};
+use axum_valid::Valid;
+
+use crate::{
+  app_state::AppState,
+  dto::{
+    dialog::{
+      DialogSummaryResponse, EditMessageDto, ListMessagesQuery, MessageEditResponse,
+      MessageListResponse, MessageResponse, MessageTtlDto, MessageTtlResponse,
+      SendDialogMessageDto, DEFAULT_MESSAGES_PAGE_SIZE,
+    },
+    error::ErrorResponse,
+    user::UserDto,
+  },
+  errors::common::WithValidationRejection,
+  helpers::with_rejection::WithRejection,
+};
+
+#[utoipa::path(
+  post,
+  path = "/dialog/{user_id}/send",
+  tags = ["Dialog"],
+  description = "Send a message to the user, as text or as an end-to-end encrypted envelope",
+  params(
+    ("user_id" = i32, Path, description = "Recipient ID"),
+  ),
+  responses(
+    (status = 200, description = "Message sent", body = MessageResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 403, description = "Users have blocked each other", body = ErrorResponse),
+    (status = 404, description = "Recipient not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn send_message(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(user_id): Path<i32>,
+  WithRejection(Valid(Json(send_message_dto)), _): WithValidationRejection<
+    Valid<Json<SendDialogMessageDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .send(
+      user.id,
+      user_id,
+      send_message_dto.text.unwrap_or_default(),
+      send_message_dto.envelope,
+    )
+    .await
+    .map(|message| Json(MessageResponse::from(message)))
+}
+
+#[utoipa::path(
+  get,
+  path = "/dialog/{user_id}/list",
+  tags = ["Dialog"],
+  description = "List messages of the dialog with the user, newest first",
+  params(
+    ("user_id" = i32, Path, description = "Peer ID"),
+    ListMessagesQuery,
+  ),
+  responses(
+    (status = 200, description = "Page of messages", body = MessageListResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn list_messages(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(user_id): Path<i32>,
+  WithRejection(Valid(Query(query)), _): WithValidationRejection<Valid<Query<ListMessagesQuery>>>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .list(
+      user.id,
+      user_id,
+      query.cursor,
+      query.limit.unwrap_or(DEFAULT_MESSAGES_PAGE_SIZE),
+    )
+    .await
+    .map(|page| Json(MessageListResponse::from(page)))
+}
+
+#[utoipa::path(
+  get,
+  path = "/dialogs",
+  tags = ["Dialog"],
+  description = "List dialogs of the current user with the last message and unread count",
+  responses(
+    (status = 200, description = "Dialogs", body = Vec<DialogSummaryResponse>),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn list_dialogs(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .list_dialogs(user.id)
+    .await
+    .map(|dialogs| {
+      Json(
+        dialogs
+          .into_iter()
+          .map(DialogSummaryResponse::from)
+          .collect::<Vec<_>>(),
+      )
+    })
+}
+
+#[utoipa::path(
+  patch,
+  path = "/dialog/{user_id}/messages/{message_id}",
+  tags = ["Dialog"],
+  description = "Edit own message within the edit window, the previous text is kept in its history",
+  params(
+    ("user_id" = i32, Path, description = "Peer ID"),
+    ("message_id" = i64, Path, description = "Message ID"),
+  ),
+  responses(
+    (status = 200, description = "Message edited", body = MessageResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 403, description = "Not the sender or the edit window has passed", body = ErrorResponse),
+    (status = 404, description = "Message not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn edit_message(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path((user_id, message_id)): Path<(i32, i64)>,
+  WithRejection(Valid(Json(edit_message_dto)), _): WithValidationRejection<
+    Valid<Json<EditMessageDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .edit(user.id, user_id, message_id, edit_message_dto.text)
+    .await
+    .map(|message| Json(MessageResponse::from(message)))
+}
+
+#[utoipa::path(
+  delete,
+  path = "/dialog/{user_id}/messages/{message_id}",
+  tags = ["Dialog"],
+  description = "Delete own message for both participants",
+  params(
+    ("user_id" = i32, Path, description = "Peer ID"),
+    ("message_id" = i64, Path, description = "Message ID"),
+  ),
+  responses(
+    (status = 204, description = "Message deleted"),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 403, description = "Not the sender", body = ErrorResponse),
+    (status = 404, description = "Message not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn delete_message(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path((user_id, message_id)): Path<(i32, i64)>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .delete(user.id, user_id, message_id)
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+  get,
+  path = "/dialog/{user_id}/messages/{message_id}/edits",
+  tags = ["Dialog"],
+  description = "Previous versions of the message, oldest first",
+  params(
+    ("user_id" = i32, Path, description = "Peer ID"),
+    ("message_id" = i64, Path, description = "Message ID"),
+  ),
+  responses(
+    (status = 200, description = "Edit history", body = Vec<MessageEditResponse>),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 404, description = "Message not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn list_message_edits(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path((user_id, message_id)): Path<(i32, i64)>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .edits(user.id, user_id, message_id)
+    .await
+    .map(|edits| {
+      Json(
+        edits
+          .into_iter()
+          .map(MessageEditResponse::from)
+          .collect::<Vec<_>>(),
+      )
+    })
+}
+
+#[utoipa::path(
+  put,
+  path = "/dialog/{user_id}/ttl",
+  tags = ["Dialog"],
+  description = "Set the disappearing messages timeout of the dialog for messages sent from now on",
+  params(
+    ("user_id" = i32, Path, description = "Peer ID"),
+  ),
+  request_body = MessageTtlDto,
+  responses(
+    (status = 200, description = "Timeout set", body = MessageTtlResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 403, description = "Users have blocked each other", body = ErrorResponse),
+    (status = 404, description = "Peer not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn set_message_ttl(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(user_id): Path<i32>,
+  WithRejection(Valid(Json(message_ttl_dto)), _): WithValidationRejection<
+    Valid<Json<MessageTtlDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .set_message_ttl(user.id, user_id, message_ttl_dto.seconds)
+    .await
+    .map(|_| {
+      Json(MessageTtlResponse {
+        seconds: message_ttl_dto.seconds,
+      })
+    })
+}
+
+#[utoipa::path(
+  put,
+  path = "/user/{user_id}/block",
+  tags = ["Dialog"],
+  description = "Block messages between the user and the current one in both directions",
+  params(
+    ("user_id" = i32, Path, description = "Blocked user ID"),
+  ),
+  responses(
+    (status = 204, description = "User blocked"),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 404, description = "User not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn block_user(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(user_id): Path<i32>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .block(user.id, user_id)
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+  delete,
+  path = "/user/{user_id}/block",
+  tags = ["Dialog"],
+  description = "Lift the block of the user set by the current one",
+  params(
+    ("user_id" = i32, Path, description = "Blocked user ID"),
+  ),
+  responses(
+    (status = 204, description = "User unblocked"),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn unblock_user(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(user_id): Path<i32>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .unblock(user.id, user_id)
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{
+    ws::{Message, WebSocket, WebSocketUpgrade},
+    State,
+  },
+  response::IntoResponse,
+  Extension,
+};
+use futures::{SinkExt, StreamExt};
+
+use crate::{
+  app_state::AppState,
+  dto::{
+    counter::Conversation,
+    error::ErrorResponse,
+    event::{
+      ClientCommand, ErrorEvent, ReadCommand, RealtimeEvent, ReceiptEvent, TypingCommand,
+      TypingEvent,
+    },
+    user::UserDto,
+  },
+  errors::event::{EventError, EventResult},
+  services::events::TYPING_TTL,
+};
+
+#[utoipa::path(
+  get,
+  path = "/events",
+  tags = ["Events"],
+  description = "Open a WebSocket receiving realtime events of the user's conversations: \
+    new messages, delivered and read receipts and typing indicators. \
+    The client sends `typing` and `read` commands over the same socket",
+  responses(
+    (status = 101, description = "Switched to WebSocket, every frame is an event", body = RealtimeEvent),
+    (status = 401, description = "Unauthorized", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn events(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+  ws.on_upgrade(move |socket| run_session(app_state, user.id, socket))
+}
+
+async fn run_session(app_state: Arc<AppState>, user_id: i32, socket: WebSocket) {
+  let (mut sender, mut receiver) = socket.split();
+
+  let mut subscription = match app_state.event_hub.subscribe(user_id).await {
+    Ok(subscription) => subscription,
+    Err(e) => {
+      let _ = sender.send(error_frame(e)).await;
+      return;
+    }
+  };
+
+  loop {
+    tokio::select! {
+      payload = subscription.recv() => {
+        let Some(payload) = payload else { break };
+        if sender.send(Message::Text(payload.as_ref().into())).await.is_err() {
+          break;
+        }
+        acknowledge_delivery(&app_state, user_id, &payload).await;
+      }
+      frame = receiver.next() => match frame {
+        Some(Ok(Message::Text(text))) => {
+          if let Err(e) = handle_command(&app_state, user_id, &text).await {
+            if sender.send(error_frame(e)).await.is_err() {
+              break;
+            }
+          }
+        }
+        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+        // Pings are answered by axum, other frames are not part of the protocol
+        Some(Ok(_)) => {}
+      },
+    }
+  }
+
+  app_state.event_hub.unsubscribe(subscription).await;
+}
+
+async fn handle_command(app_state: &AppState, user_id: i32, text: &str) -> EventResult<()> {
+  match serde_json::from_str(text).map_err(EventError::InvalidCommand)? {
+    ClientCommand::Typing(TypingCommand { conversation }) => {
+      typing(app_state, user_id, conversation).await
+    }
+    ClientCommand::Read(ReadCommand {
+      conversation,
+      message_id,
+    }) => read(app_state, user_id, conversation, message_id).await,
+  }
+}
+
+/// The indicator is announced once per TTL, renewals while it is still shown only extend it
+async fn typing(app_state: &AppState, user_id: i32, conversation: Conversation) -> EventResult<()> {
+  let (viewers, viewer_conversation) = match conversation {
+    Conversation::Dialog(peer_id) => {
+      app_state
+        .dialog_service
+        .ensure_can_message(user_id, peer_id)
+        .await?;
+      (vec![peer_id], Conversation::Dialog(user_id))
+    }
+    Conversation::Group(group_id) => {
+      let mut members = app_state
+        .group_service
+        .members_of(user_id, group_id)
+        .await?;
+      members.retain(|member_id| *member_id != user_id);
+      (members, conversation)
+    }
+  };
+
+  if app_state
+    .event_service
+    .set_typing(user_id, conversation)
+    .await?
+  {
+    let event = RealtimeEvent::Typing(TypingEvent {
+      conversation: viewer_conversation,
+      user_id,
+      expires_in: TYPING_TTL,
+    });
+    app_state.event_service.publish(&viewers, &event).await;
+  }
+
+  Ok(())
+}
+
+/// Read receipts move the persistent cursor, the services notify the participants
+async fn read(
+  app_state: &AppState,
+  user_id: i32,
+  conversation: Conversation,
+  message_id: i64,
+) -> EventResult<()> {
+  match conversation {
+    Conversation::Dialog(peer_id) => match &app_state.dialog_proxy {
+      Some(dialog_proxy) => dialog_proxy.mark_read(user_id, peer_id, message_id).await?,
+      None => {
+        app_state
+          .dialog_service
+          .read(user_id, peer_id, message_id)
+          .await?
+      }
+    },
+    Conversation::Group(group_id) => {
+      app_state
+        .group_service
+        .read(user_id, group_id, message_id)
+        .await?
+    }
+  }
+
+  Ok(())
+}
+
+/// A message written to a connection of its recipient counts as delivered for the sender
+async fn acknowledge_delivery(app_state: &AppState, user_id: i32, payload: &str) {
+  let (sender_id, receipt) = match serde_json::from_str(payload) {
+    Ok(RealtimeEvent::Message(event)) => (
+      event.message.from,
+      ReceiptEvent {
+        conversation: Conversation::Dialog(user_id),
+        user_id,
+        message_id: event.message.id,
+      },
+    ),
+    Ok(RealtimeEvent::GroupMessage(event)) => (
+      event.message.from,
+      ReceiptEvent {
+        conversation: event.conversation,
+        user_id,
+        message_id: event.message.id,
+      },
+    ),
+    _ => return,
+  };
+
+  if sender_id != user_id {
+    app_state
+      .event_service
+      .publish(&[sender_id], &RealtimeEvent::Delivered(receipt))
+      .await;
+  }
+}
+
+fn error_frame(error: EventError) -> Message {
+  let event = RealtimeEvent::Error(ErrorEvent::from(error));
+  Message::Text(serde_json::to_string(&event).unwrap_or_default().into())
+}
@@ -0,0 +1,469 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::IntoResponse,
+  Extension, Json,
+};
+use axum_valid::Valid;
+
+use crate::{
+  app_state::AppState,
+  dto::{
+    dialog::{
+      EditMessageDto, ListMessagesQuery, MessageEditResponse, MessageTtlDto, SendMessageDto,
+      DEFAULT_MESSAGES_PAGE_SIZE,
+    },
+    error::ErrorResponse,
+    group::{
+      AddGroupMemberDto, CreateGroupDto, GroupMessageListResponse, GroupMessageResponse,
+      GroupResponse, GroupSummaryResponse, RenameGroupDto,
+    },
+    user::UserDto,
+  },
+  errors::common::WithValidationRejection,
+  helpers::with_rejection::WithRejection,
+};
+
+#[utoipa::path(
+  post,
+  path = "/groups",
+  tags = ["Group"],
+  description = "Create a group chat, the creator becomes its admin",
+  request_body = CreateGroupDto,
+  responses(
+    (status = 200, description = "Group created", body = GroupResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 404, description = "Member not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn create_group(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  WithRejection(Valid(Json(create_group_dto)), _): WithValidationRejection<
+    Valid<Json<CreateGroupDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .create(user.id, create_group_dto.title, create_group_dto.member_ids)
+    .await
+    .map(|group| Json(GroupResponse::from(group)))
+}
+
+#[utoipa::path(
+  get,
+  path = "/groups",
+  tags = ["Group"],
+  description = "List groups of the current user with the last message and unread count",
+  responses(
+    (status = 200, description = "Groups", body = Vec<GroupSummaryResponse>),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn list_groups(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+) -> impl IntoResponse {
+  app_state.group_service.list(user.id).await.map(|groups| {
+    Json(
+      groups
+        .into_iter()
+        .map(GroupSummaryResponse::from)
+        .collect::<Vec<_>>(),
+    )
+  })
+}
+
+#[utoipa::path(
+  get,
+  path = "/groups/{group_id}",
+  tags = ["Group"],
+  description = "Get the group with its members",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+  ),
+  responses(
+    (status = 200, description = "Group", body = GroupResponse),
+    (status = 404, description = "Group not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn get_group(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(group_id): Path<i32>,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .get(user.id, group_id)
+    .await
+    .map(|group| Json(GroupResponse::from(group)))
+}
+
+#[utoipa::path(
+  patch,
+  path = "/groups/{group_id}",
+  tags = ["Group"],
+  description = "Rename the group",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+  ),
+  request_body = RenameGroupDto,
+  responses(
+    (status = 200, description = "Group renamed", body = GroupResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 404, description = "Group not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn rename_group(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(group_id): Path<i32>,
+  WithRejection(Valid(Json(rename_group_dto)), _): WithValidationRejection<
+    Valid<Json<RenameGroupDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .rename(user.id, group_id, rename_group_dto.title)
+    .await
+    .map(|group| Json(GroupResponse::from(group)))
+}
+
+#[utoipa::path(
+  post,
+  path = "/groups/{group_id}/members",
+  tags = ["Group"],
+  description = "Add a member to the group, admins only",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+  ),
+  request_body = AddGroupMemberDto,
+  responses(
+    (status = 200, description = "Member added", body = GroupResponse),
+    (status = 400, description = "Group is full", body = ErrorResponse),
+    (status = 403, description = "Not an admin", body = ErrorResponse),
+    (status = 404, description = "Group or user not found", body = ErrorResponse),
+    (status = 409, description = "Already a member", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn add_member(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(group_id): Path<i32>,
+  WithRejection(Valid(Json(add_member_dto)), _): WithValidationRejection<
+    Valid<Json<AddGroupMemberDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .add_member(user.id, group_id, add_member_dto.user_id)
+    .await
+    .map(|group| Json(GroupResponse::from(group)))
+}
+
+#[utoipa::path(
+  delete,
+  path = "/groups/{group_id}/members/{user_id}",
+  tags = ["Group"],
+  description = "Remove a member from the group, admins only",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+    ("user_id" = i32, Path, description = "Member ID"),
+  ),
+  responses(
+    (status = 200, description = "Member removed", body = GroupResponse),
+    (status = 400, description = "Admin removing themselves", body = ErrorResponse),
+    (status = 403, description = "Not an admin", body = ErrorResponse),
+    (status = 404, description = "Group or member not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn remove_member(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path((group_id, user_id)): Path<(i32, i32)>,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .remove_member(user.id, group_id, user_id)
+    .await
+    .map(|group| Json(GroupResponse::from(group)))
+}
+
+#[utoipa::path(
+  post,
+  path = "/groups/{group_id}/leave",
+  tags = ["Group"],
+  description = "Leave the group",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+  ),
+  responses(
+    (status = 204, description = "Left the group"),
+    (status = 404, description = "Group not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn leave_group(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(group_id): Path<i32>,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .leave(user.id, group_id)
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+  post,
+  path = "/groups/{group_id}/send",
+  tags = ["Group"],
+  description = "Send a message to the group",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+  ),
+  request_body = SendMessageDto,
+  responses(
+    (status = 200, description = "Message sent", body = GroupMessageResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 404, description = "Group not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn send_group_message(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(group_id): Path<i32>,
+  WithRejection(Valid(Json(send_message_dto)), _): WithValidationRejection<
+    Valid<Json<SendMessageDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .send(user.id, group_id, send_message_dto.text)
+    .await
+    .map(|message| Json(GroupMessageResponse::from(message)))
+}
+
+#[utoipa::path(
+  get,
+  path = "/groups/{group_id}/messages",
+  tags = ["Group"],
+  description = "List group history including member events, newest first",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+    ListMessagesQuery,
+  ),
+  responses(
+    (status = 200, description = "Page of messages", body = GroupMessageListResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 404, description = "Group not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn list_group_messages(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(group_id): Path<i32>,
+  WithRejection(Valid(Query(query)), _): WithValidationRejection<Valid<Query<ListMessagesQuery>>>,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .history(
+      user.id,
+      group_id,
+      query.cursor,
+      query.limit.unwrap_or(DEFAULT_MESSAGES_PAGE_SIZE),
+    )
+    .await
+    .map(|page| Json(GroupMessageListResponse::from(page)))
+}
+
+#[utoipa::path(
+  patch,
+  path = "/groups/{group_id}/messages/{message_id}",
+  tags = ["Group"],
+  description = "Edit own message within the edit window, the previous text is kept in its history",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+    ("message_id" = i64, Path, description = "Message ID"),
+  ),
+  request_body = EditMessageDto,
+  responses(
+    (status = 200, description = "Message edited", body = GroupMessageResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 403, description = "Not the sender or the edit window has passed", body = ErrorResponse),
+    (status = 404, description = "Group or message not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn edit_group_message(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path((group_id, message_id)): Path<(i32, i64)>,
+  WithRejection(Valid(Json(edit_message_dto)), _): WithValidationRejection<
+    Valid<Json<EditMessageDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .edit(user.id, group_id, message_id, edit_message_dto.text)
+    .await
+    .map(|message| Json(GroupMessageResponse::from(message)))
+}
+
+#[utoipa::path(
+  delete,
+  path = "/groups/{group_id}/messages/{message_id}",
+  tags = ["Group"],
+  description = "Delete own message for all members",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+    ("message_id" = i64, Path, description = "Message ID"),
+  ),
+  responses(
+    (status = 204, description = "Message deleted"),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 403, description = "Not the sender", body = ErrorResponse),
+    (status = 404, description = "Group or message not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn delete_group_message(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path((group_id, message_id)): Path<(i32, i64)>,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .delete(user.id, group_id, message_id)
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+  get,
+  path = "/groups/{group_id}/messages/{message_id}/edits",
+  tags = ["Group"],
+  description = "Previous versions of the message, oldest first",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+    ("message_id" = i64, Path, description = "Message ID"),
+  ),
+  responses(
+    (status = 200, description = "Edit history", body = Vec<MessageEditResponse>),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 404, description = "Group or message not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn list_group_message_edits(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path((group_id, message_id)): Path<(i32, i64)>,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .edits(user.id, group_id, message_id)
+    .await
+    .map(|edits| {
+      Json(
+        edits
+          .into_iter()
+          .map(MessageEditResponse::from)
+          .collect::<Vec<_>>(),
+      )
+    })
+}
+
+#[utoipa::path(
+  put,
+  path = "/groups/{group_id}/ttl",
+  tags = ["Group"],
+  description = "Set the disappearing messages timeout of the group for messages sent from now on",
+  params(
+    ("group_id" = i32, Path, description = "Group ID"),
+  ),
+  request_body = MessageTtlDto,
+  responses(
+    (status = 200, description = "Timeout set", body = GroupResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 404, description = "Group not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn set_group_message_ttl(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(group_id): Path<i32>,
+  WithRejection(Valid(Json(message_ttl_dto)), _): WithValidationRejection<
+    Valid<Json<MessageTtlDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .group_service
+    .set_message_ttl(user.id, group_id, message_ttl_dto.seconds)
+    .await
+    .map(|group| Json(GroupResponse::from(group)))
+}
@@ -1,17 +1,34 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use std::sync::Arc;
 
-use crate::dto::error::ErrorResponse;
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+
+use crate::{app_state::AppState, dto::health::ReadinessResponse};
 
 #[utoipa::path(
   get,
   tags = ["Internal"],
-  path = "/health",
+  path = "/health/live",
+  description = "Liveness probe, answers while the process is able to serve requests at all",
   responses(
-    (status = 200, description = "Health check"),
-    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 200, description = "Process is alive"),
   ),
 )]
 #[axum::debug_handler]
-pub async fn health() -> impl IntoResponse {
+pub async fn live() -> impl IntoResponse {
   StatusCode::OK
 }
+
+#[utoipa::path(
+  get,
+  tags = ["Internal"],
+  path = "/health/ready",
+  description = "Readiness probe checking Postgres, Redis and the password hashing pool",
+  responses(
+    (status = 200, description = "Every dependency is up", body = ReadinessResponse),
+    (status = 503, description = "Some dependency is down", body = ReadinessResponse),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn ready(State(app_state): State<Arc<AppState>>) -> impl IntoResponse {
+  app_state.health_service.readiness().await
+}
@@ -0,0 +1,165 @@
+//! Internal API of the dialog service, called by the main server on behalf of its users
+
+use std::sync::Arc;
+
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::IntoResponse,
+  Extension, Json,
+};
+use axum_valid::Valid;
+
+use crate::{
+  app_state::AppState,
+  dto::dialog::{
+    DialogSummaryResponse, EditMessageDto, ListMessagesQuery, MarkReadDto, MessageEditResponse,
+    MessageListResponse, MessageResponse, MessageTtlDto, MessageTtlResponse, SendDialogMessageDto,
+    DEFAULT_MESSAGES_PAGE_SIZE,
+  },
+  errors::common::WithValidationRejection,
+  helpers::with_rejection::WithRejection,
+  middlewares::service_auth::ServiceCaller,
+};
+
+#[axum::debug_handler]
+pub async fn send_message(
+  State(app_state): State<Arc<AppState>>,
+  Extension(caller): Extension<ServiceCaller>,
+  Path(user_id): Path<i32>,
+  WithRejection(Valid(Json(send_message_dto)), _): WithValidationRejection<
+    Valid<Json<SendDialogMessageDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .send(
+      caller.user_id,
+      user_id,
+      send_message_dto.text.unwrap_or_default(),
+      send_message_dto.envelope,
+    )
+    .await
+    .map(|message| Json(MessageResponse::from(message)))
+}
+
+#[axum::debug_handler]
+pub async fn list_messages(
+  State(app_state): State<Arc<AppState>>,
+  Extension(caller): Extension<ServiceCaller>,
+  Path(user_id): Path<i32>,
+  WithRejection(Valid(Query(query)), _): WithValidationRejection<Valid<Query<ListMessagesQuery>>>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .list(
+      caller.user_id,
+      user_id,
+      query.cursor,
+      query.limit.unwrap_or(DEFAULT_MESSAGES_PAGE_SIZE),
+    )
+    .await
+    .map(|page| Json(MessageListResponse::from(page)))
+}
+
+#[axum::debug_handler]
+pub async fn list_dialogs(
+  State(app_state): State<Arc<AppState>>,
+  Extension(caller): Extension<ServiceCaller>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .list_dialogs(caller.user_id)
+    .await
+    .map(|dialogs| {
+      Json(
+        dialogs
+          .into_iter()
+          .map(DialogSummaryResponse::from)
+          .collect::<Vec<_>>(),
+      )
+    })
+}
+
+#[axum::debug_handler]
+pub async fn mark_read(
+  State(app_state): State<Arc<AppState>>,
+  Extension(caller): Extension<ServiceCaller>,
+  Path(user_id): Path<i32>,
+  WithRejection(Valid(Json(mark_read_dto)), _): WithValidationRejection<Valid<Json<MarkReadDto>>>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .read(caller.user_id, user_id, mark_read_dto.message_id)
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler]
+pub async fn edit_message(
+  State(app_state): State<Arc<AppState>>,
+  Extension(caller): Extension<ServiceCaller>,
+  Path((user_id, message_id)): Path<(i32, i64)>,
+  WithRejection(Valid(Json(edit_message_dto)), _): WithValidationRejection<
+    Valid<Json<EditMessageDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .edit(caller.user_id, user_id, message_id, edit_message_dto.text)
+    .await
+    .map(|message| Json(MessageResponse::from(message)))
+}
+
+#[axum::debug_handler]
+pub async fn delete_message(
+  State(app_state): State<Arc<AppState>>,
+  Extension(caller): Extension<ServiceCaller>,
+  Path((user_id, message_id)): Path<(i32, i64)>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .delete(caller.user_id, user_id, message_id)
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler]
+pub async fn list_message_edits(
+  State(app_state): State<Arc<AppState>>,
+  Extension(caller): Extension<ServiceCaller>,
+  Path((user_id, message_id)): Path<(i32, i64)>,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .edits(caller.user_id, user_id, message_id)
+    .await
+    .map(|edits| {
+      Json(
+        edits
+          .into_iter()
+          .map(MessageEditResponse::from)
+          .collect::<Vec<_>>(),
+      )
+    })
+}
+
+#[axum::debug_handler]
+pub async fn set_message_ttl(
+  State(app_state): State<Arc<AppState>>,
+  Extension(caller): Extension<ServiceCaller>,
+  Path(user_id): Path<i32>,
+  WithRejection(Valid(Json(message_ttl_dto)), _): WithValidationRejection<
+    Valid<Json<MessageTtlDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .dialog_service
+    .set_message_ttl(caller.user_id, user_id, message_ttl_dto.seconds)
+    .await
+    .map(|_| {
+      Json(MessageTtlResponse {
+        seconds: message_ttl_dto.seconds,
+      })
+    })
+}
@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  response::IntoResponse,
+  Extension, Json,
+};
+use axum_valid::Valid;
+
+use crate::{
+  app_state::AppState,
+  dto::{
+    error::ErrorResponse,
+    keys::{DeviceKeysResponse, PublishDeviceKeysDto},
+    user::UserDto,
+  },
+  errors::common::WithValidationRejection,
+  helpers::with_rejection::WithRejection,
+};
+
+#[utoipa::path(
+  put,
+  path = "/keys/devices/{device_id}",
+  tags = ["Keys"],
+  description = "Publish keys of a device of the current user or rotate its signed prekey",
+  params(
+    ("device_id" = String, Path, description = "Device ID chosen by the client"),
+  ),
+  request_body = PublishDeviceKeysDto,
+  responses(
+    (status = 200, description = "Keys published", body = DeviceKeysResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 409, description = "Identity key of the device has changed", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn publish_device_keys(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(device_id): Path<String>,
+  WithRejection(Valid(Json(publish_keys_dto)), _): WithValidationRejection<
+    Valid<Json<PublishDeviceKeysDto>>,
+  >,
+) -> impl IntoResponse {
+  app_state
+    .key_service
+    .publish(user.id, device_id, publish_keys_dto)
+    .await
+    .map(|keys| Json(DeviceKeysResponse::from(keys)))
+}
+
+#[utoipa::path(
+  delete,
+  path = "/keys/devices/{device_id}",
+  tags = ["Keys"],
+  description = "Remove a device of the current user from the key directory",
+  params(
+    ("device_id" = String, Path, description = "Device ID"),
+  ),
+  responses(
+    (status = 204, description = "Device removed"),
+    (status = 404, description = "Device not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn delete_device_keys(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  Path(device_id): Path<String>,
+) -> impl IntoResponse {
+  app_state
+    .key_service
+    .delete(user.id, device_id)
+    .await
+    .map(|_| StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+  get,
+  path = "/keys/{user_id}",
+  tags = ["Keys"],
+  description = "Published devices of the user, messages to the user are encrypted for each of them",
+  params(
+    ("user_id" = i32, Path, description = "User ID"),
+  ),
+  responses(
+    (status = 200, description = "Device keys", body = Vec<DeviceKeysResponse>),
+    (status = 404, description = "User not found", body = ErrorResponse),
+  ),
+  security(
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn list_device_keys(
+  State(app_state): State<Arc<AppState>>,
+  Path(user_id): Path<i32>,
+) -> impl IntoResponse {
+  app_state.key_service.list(user_id).await.map(|devices| {
+    Json(
+      devices
+        .into_iter()
+        .map(DeviceKeysResponse::from)
+        .collect::<Vec<_>>(),
+    )
+  })
+}
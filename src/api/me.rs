@@ -20,7 +20,7 @@ use crate::dto::{
   ),
 )]
 #[axum::debug_handler]
-#[tracing::instrument]
+#[tracing::instrument(skip(user), fields(user_id = user.id))]
 pub async fn get_me(Extension(user): Extension<UserDto>) -> impl IntoResponse {
   Json(UserMeResponse::from(user))
 }
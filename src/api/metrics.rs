@@ -0,0 +1,24 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::app_state::AppState;
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[utoipa::path(
+  get,
+  tags = ["Internal"],
+  path = "/metrics",
+  description = "Metrics in Prometheus text format",
+  responses(
+    (status = 200, description = "Metrics", content_type = "text/plain", body = String),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn metrics(State(app_state): State<Arc<AppState>>) -> impl IntoResponse {
+  (
+    [(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)],
+    app_state.metrics_service.render(),
+  )
+}
@@ -1,4 +1,12 @@
 pub mod auth;
+pub mod counters;
+pub mod dialogs;
+pub mod events;
+pub mod groups;
 pub mod health;
+pub mod internal;
+pub mod keys;
 pub mod me;
+pub mod metrics;
+pub mod posts;
 pub mod users;
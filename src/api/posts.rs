@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{Path, Query, State},
+  http::{header, HeaderMap, HeaderValue, StatusCode},
+  response::{IntoResponse, Response},
+  Extension, Json,
+};
+use axum_valid::Valid;
+
+use crate::{
+  app_state::AppState,
+  dto::{
+    error::ErrorResponse,
+    post::{ListPostsQuery, PostListResponse, DEFAULT_POSTS_PAGE_SIZE},
+    user::UserDto,
+  },
+  errors::{common::WithValidationRejection, post::PostError},
+  helpers::{atom, with_rejection::WithRejection},
+};
+
+const FEED_SIZE: i64 = 50;
+const FEED_CACHE_CONTROL: &str = "public, max-age=300";
+const ATOM_CONTENT_TYPE: &str = "application/atom+xml; charset=utf-8";
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+#[utoipa::path(
+  get,
+  path = "/user/{id}/posts",
+  tags = ["Post"],
+  description = "List posts of the user, newest first. Private posts are visible only to the author",
+  params(
+    ("id" = i32, Path, description = "Author ID"),
+    ListPostsQuery,
+  ),
+  responses(
+    (status = 200, description = "Page of posts", body = PostListResponse),
+    (status = 400, description = "Bad request", body = ErrorResponse),
+    (status = 404, description = "Author not found", body = ErrorResponse),
+  ),
+  security(
+    (),
+    ("user_auth" = []),
+    ("refresh_auth" = []),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn list_user_posts(
+  State(app_state): State<Arc<AppState>>,
+  Extension(viewer): Extension<Option<UserDto>>,
+  Path(id): Path<i32>,
+  WithRejection(Valid(Query(query)), _): WithValidationRejection<Valid<Query<ListPostsQuery>>>,
+) -> impl IntoResponse {
+  app_state
+    .post_service
+    .list_by_author(
+      id,
+      viewer.map(|viewer| viewer.id),
+      query.cursor,
+      query.limit.unwrap_or(DEFAULT_POSTS_PAGE_SIZE),
+    )
+    .await
+    .map(|page| Json(PostListResponse::from(page)))
+}
+
+#[utoipa::path(
+  get,
+  path = "/user/{id}/feed.atom",
+  tags = ["Post"],
+  description = "Public posts of the user as an Atom feed",
+  params(
+    ("id" = i32, Path, description = "Author ID"),
+  ),
+  responses(
+    (status = 200, description = "Atom feed", content_type = "application/atom+xml", body = String),
+    (status = 304, description = "Feed has not changed"),
+    (status = 404, description = "Author not found", body = ErrorResponse),
+  ),
+)]
+#[axum::debug_handler]
+pub async fn get_user_feed(
+  State(app_state): State<Arc<AppState>>,
+  Path(id): Path<i32>,
+  headers: HeaderMap,
+) -> Result<Response, PostError> {
+  let (author, posts) = app_state
+    .post_service
+    .get_public_feed(id, FEED_SIZE)
+    .await?;
+
+  let last_modified = atom::last_updated(&posts);
+  let etag = format!(
+    "W/\"{}-{}-{}\"",
+    author.id,
+    posts.first().map(|post| post.id).unwrap_or_default(),
+    last_modified
+      .map(|date| date.timestamp_micros())
+      .unwrap_or_default()
+  );
+
+  let if_none_match = headers
+    .get(header::IF_NONE_MATCH)
+    .and_then(|value| value.to_str().ok());
+  let if_modified_since = headers
+    .get(header::IF_MODIFIED_SINCE)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| chrono::NaiveDateTime::parse_from_str(value, HTTP_DATE_FORMAT).ok())
+    .map(|date| date.and_utc());
+
+  let is_not_modified = match (if_none_match, if_modified_since) {
+    (Some(if_none_match), _) => if_none_match
+      .split(',')
+      .any(|tag| tag.trim() == etag || tag.trim() == "*"),
+    (None, Some(since)) => {
+      last_modified.is_some_and(|modified| modified.timestamp() <= since.timestamp())
+    }
+    (None, None) => false,
+  };
+
+  let mut response = if is_not_modified {
+    StatusCode::NOT_MODIFIED.into_response()
+  } else {
+    (
+      [(header::CONTENT_TYPE, ATOM_CONTENT_TYPE)],
+      atom::render_feed(&app_state.config.public_url, &author, &posts),
+    )
+      .into_response()
+  };
+
+  let response_headers = response.headers_mut();
+  response_headers.insert(
+    header::CACHE_CONTROL,
+    HeaderValue::from_static(FEED_CACHE_CONTROL),
+  );
+  if let Ok(etag) = HeaderValue::from_str(&etag) {
+    response_headers.insert(header::ETAG, etag);
+  }
+  if let Some(last_modified) = last_modified
+    .and_then(|date| HeaderValue::from_str(&date.format(HTTP_DATE_FORMAT).to_string()).ok())
+  {
+    response_headers.insert(header::LAST_MODIFIED, last_modified);
+  }
+
+  Ok(response)
+}
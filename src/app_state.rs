@@ -1,26 +1,113 @@
+use std::time::Duration;
+
+use chrono::TimeDelta;
+use metrics_exporter_prometheus::PrometheusHandle;
+
 use crate::{
-  config::AppConfigRc,
-  db::DataSource,
+  config::{AppConfigRc, DialogMode},
+  db::{
+    dialogs::{postgres::PgDialogStore, DialogStore},
+    DataSource,
+  },
   errors::common::DatabaseError,
-  services::{encryption::EncryptionService, jwt::JwtService, users::UserService},
+  services::{
+    counters::CounterService, dialog_proxy::DialogProxy, dialogs::DialogService,
+    encryption::EncryptionService, event_hub::EventHub, events::EventService, groups::GroupService,
+    health::HealthService, idempotency::IdempotencyService, jwt::JwtService, keys::KeyService,
+    metrics::MetricsService, posts::PostService, rate_limit::RateLimitService,
+    user_cache::UserCache, users::UserService,
+  },
 };
 
 #[derive(Debug, Clone)]
 pub struct AppState {
   pub ds: DataSource,
+  pub config: AppConfigRc,
   pub user_service: UserService,
+  pub post_service: PostService,
+  pub dialog_service: DialogService,
+  pub group_service: GroupService,
+  pub key_service: KeyService,
+  pub counter_service: CounterService,
+  pub event_service: EventService,
+  pub health_service: HealthService,
+  pub metrics_service: MetricsService,
+  pub rate_limit_service: RateLimitService,
+  pub idempotency_service: IdempotencyService,
+  /// Routes realtime events to the WebSocket connections of this instance
+  pub event_hub: EventHub,
+  /// Set in remote dialog mode, dialog requests are then forwarded to the dialog service
+  pub dialog_proxy: Option<DialogProxy>,
   pub jwt_service: JwtService,
 }
 
 impl AppState {
-  pub async fn init(ds: DataSource, app_config: AppConfigRc) -> Result<Self, DatabaseError> {
+  pub async fn init(
+    ds: DataSource,
+    app_config: AppConfigRc,
+    metrics_handle: PrometheusHandle,
+  ) -> Result<Self, DatabaseError> {
     let jwt_service = JwtService::new(app_config.clone(), ds.redis.clone());
     let encryption_service = EncryptionService::new();
-    let user_service = UserService::new(ds.pg.clone(), jwt_service.clone(), encryption_service);
+    let user_service = UserService::new(
+      ds.pg.clone(),
+      UserCache::new(ds.redis.clone(), &app_config),
+      jwt_service.clone(),
+      encryption_service.clone(),
+    );
+    let health_service = HealthService::new(
+      ds.pg.clone(),
+      ds.redis.clone(),
+      encryption_service,
+      Duration::from_millis(app_config.health_check_timeout),
+    );
+    let post_service = PostService::new(ds.pg.clone());
+    let metrics_service =
+      MetricsService::new(metrics_handle, ds.pg.clone(), ds.dialog_shards.clone());
+    let counter_service = CounterService::new(ds.redis.clone());
+    let event_service = EventService::new(ds.redis.clone());
+    let event_hub = EventHub::connect(ds.redis.client()).await?;
+    // Dialogs of the remote mode are stored by the dialog service, the local store stays unused
+    let dialog_store = match app_config.dialog_mode {
+      DialogMode::Local => DialogStore::open(&app_config, ds.dialog_shards.clone()).await?,
+      DialogMode::Remote => DialogStore::Postgres(PgDialogStore::new(ds.dialog_shards.clone())),
+    };
+    let edit_window = TimeDelta::seconds(app_config.message_edit_window);
+    let dialog_service = DialogService::new(
+      ds.write(),
+      dialog_store,
+      counter_service.clone(),
+      event_service.clone(),
+      edit_window,
+    );
+    let group_service = GroupService::new(
+      ds.write(),
+      counter_service.clone(),
+      event_service.clone(),
+      edit_window,
+    );
+    let key_service = KeyService::new(ds.write());
+    let rate_limit_service = RateLimitService::new(ds.redis.clone());
+    let idempotency_service = IdempotencyService::new(ds.redis.clone(), &app_config);
+    let dialog_proxy = (app_config.dialog_mode == DialogMode::Remote)
+      .then(|| DialogProxy::new(&app_config.dialog_service_url, jwt_service.clone()));
 
     Ok(Self {
       ds,
+      config: app_config,
       user_service,
+      post_service,
+      dialog_service,
+      group_service,
+      key_service,
+      counter_service,
+      event_service,
+      health_service,
+      metrics_service,
+      rate_limit_service,
+      idempotency_service,
+      event_hub,
+      dialog_proxy,
       jwt_service,
     })
   }
@@ -0,0 +1,53 @@
+//! Dialog service, serving the internal dialog API to the main server running in remote dialog mode
+
+use std::{future::IntoFuture, net::SocketAddr};
+
+use social_network::{
+  config::{parse_layered, AppConfig},
+  dialog_app,
+  errors::common::InitError,
+  shutdown_signal,
+  telemetry::init_tracing,
+};
+
+use tracing::{debug, info};
+
+#[tokio::main]
+async fn main() -> miette::Result<()> {
+  let (config, _) = parse_layered::<AppConfig>()?;
+  let _telemetry = init_tracing(&config)?;
+  debug!("Run with config: {:?}", config);
+
+  let listener =
+    tokio::net::TcpListener::bind(format!("{}:{}", config.host, config.dialog_service_port))
+      .await
+      .map_err(InitError::Bind)?;
+  let internal_listener = tokio::net::TcpListener::bind(format!(
+    "{}:{}",
+    config.host, config.dialog_service_metrics_port
+  ))
+  .await
+  .map_err(InitError::Bind)?;
+
+  let (app, internal_app) = dialog_app(config).await?;
+  let server = axum::serve(
+    listener,
+    app.into_make_service_with_connect_info::<SocketAddr>(),
+  )
+  .with_graceful_shutdown(shutdown_signal());
+  let internal_server =
+    axum::serve(internal_listener, internal_app).with_graceful_shutdown(shutdown_signal());
+
+  info!(
+    "🚀 Dialog service on http://{} started successfully",
+    server.local_addr().map_err(InitError::Bind)?
+  );
+  info!(
+    "Metrics on http://{}/metrics",
+    internal_server.local_addr().map_err(InitError::Bind)?
+  );
+
+  tokio::try_join!(server.into_future(), internal_server.into_future()).map_err(InitError::Bind)?;
+
+  Ok(())
+}
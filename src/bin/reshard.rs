@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use social_network::{
+  db::{reshard::Resharder, shards::ShardMap},
+  errors::common::{DatabaseError, InitError},
+};
+use sqlx::postgres::PgPoolOptions;
+use tracing::info;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Move dialogs to a newly added shard while the service keeps running.
+///
+/// Apply migrations to the new shard, append its url to DIALOG_SHARD_URLS and run `start`.
+/// Then roll out the new DIALOG_SHARD_URLS to every instance and run `resume` to move the data.
+/// Running instances pick up every phase through the shard topology stored in the main database
+#[derive(Parser, Debug)]
+#[clap(author, about, long_about = None)]
+struct ReshardConfig {
+  /// Set database url
+  #[clap(long, env)]
+  database_url: String,
+
+  /// Set database urls of the dialog shards including the new one
+  #[clap(long, env, value_delimiter = ',')]
+  dialog_shard_urls: Vec<String>,
+
+  /// Set interval of reloading the dialog shard topology by running instances in seconds
+  #[clap(long, env, default_value = "5")]
+  dialog_topology_refresh_interval: u64,
+
+  /// Set number of dialogs processed between checkpoints
+  #[clap(long, env, default_value = "500")]
+  reshard_batch_size: i64,
+
+  #[clap(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Register a job adding the last configured shard, writes start being mirrored to it
+  Start,
+  /// Move dialogs of the job in progress, continuing from its checkpoint
+  Resume,
+  /// Stop the job in progress and delete the copies from the new shard
+  Abort,
+  /// Print the latest job
+  Status,
+}
+
+#[tokio::main]
+async fn main() -> miette::Result<()> {
+  tracing_subscriber::registry()
+    .with(tracing_subscriber::fmt::layer())
+    .with(tracing_subscriber::EnvFilter::from_env("LOG_LEVEL"))
+    .try_init()
+    .map_err(InitError::Logger)?;
+
+  let config = ReshardConfig::parse();
+
+  let main = PgPoolOptions::new()
+    .connect(&config.database_url)
+    .await
+    .map_err(DatabaseError::from)?;
+  let shards = ShardMap::connect(&config.dialog_shard_urls, &main, PgPoolOptions::new()).await?;
+
+  // Every instance has to reload the topology at least once before the next phase
+  let grace = Duration::from_secs(config.dialog_topology_refresh_interval * 2 + 1);
+  let resharder = Resharder::new(
+    main,
+    shards.all().to_vec(),
+    grace,
+    config.reshard_batch_size,
+  );
+
+  let job = match config.command {
+    Command::Start => Some(resharder.start().await?),
+    Command::Resume => Some(resharder.resume().await?),
+    Command::Abort => Some(resharder.abort().await?),
+    Command::Status => resharder.status().await?,
+  };
+
+  match job {
+    Some(job) => info!(
+      "Job {}: {:?}, {} -> {} shards, {} dialogs moved",
+      job.id, job.phase, job.source_shards, job.target_shards, job.moved_dialogs
+    ),
+    None => info!("No resharding jobs"),
+  }
+
+  Ok(())
+}
@@ -1,67 +1,134 @@
-use std::net::SocketAddr;
+use std::{future::IntoFuture, net::SocketAddr};
 
-use clap::Parser;
-use social_network::{app, config::AppConfig, errors::common::InitError};
+use clap::{Parser, Subcommand};
+use social_network::{
+  app,
+  config::{parse_layered, render_effective, AppConfig},
+  db::migrations,
+  errors::common::InitError,
+  shutdown_signal,
+  telemetry::init_tracing,
+};
 
-use tokio::signal;
 use tracing::{debug, info};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+#[derive(Parser, Debug)]
+#[clap(author, about, long_about = None)]
+struct Cli {
+  #[clap(flatten)]
+  config: AppConfig,
+
+  /// Run a maintenance command instead of the server
+  #[clap(subcommand)]
+  command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Manage the schema of the main database and dialog shards with the embedded migrations
+  Migrate {
+    #[clap(subcommand)]
+    command: MigrateCommand,
+  },
+  /// Inspect the configuration assembled from the config file, environment and flags
+  Config {
+    #[clap(subcommand)]
+    command: ConfigCommand,
+  },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+  /// Validate the configuration and print the effective options with the secrets masked
+  Check,
+}
+
+#[derive(Subcommand, Debug)]
+enum MigrateCommand {
+  /// Apply pending migrations
+  Up,
+  /// Revert the last applied migrations
+  Down {
+    /// Number of migrations to revert
+    #[clap(long, default_value = "1")]
+    steps: usize,
+  },
+  /// Print the migrations and whether they are applied
+  Status,
+}
 
 #[tokio::main]
 async fn main() -> miette::Result<()> {
-  tracing_subscriber::registry()
-    .with(tracing_subscriber::fmt::layer())
-    .with(tracing_subscriber::EnvFilter::from_env("LOG_LEVEL"))
-    .try_init()
-    .map_err(InitError::Logger)?;
+  let (Cli { config, command }, matches) = parse_layered::<Cli>()?;
+  if let Some(Command::Config {
+    command: ConfigCommand::Check,
+  }) = command
+  {
+    print!("{}", render_effective::<Cli>(&matches));
+    return Ok(());
+  }
 
-  let config = AppConfig::parse();
+  let _telemetry = init_tracing(&config)?;
   debug!("Run with config: {:?}", config);
 
+  if let Some(Command::Migrate { command }) = command {
+    return migrate(&config, command).await;
+  }
+
   let listener = tokio::net::TcpListener::bind(format!("{}:{}", config.host, config.port))
     .await
     .map_err(InitError::Bind)?;
+  let internal_listener =
+    tokio::net::TcpListener::bind(format!("{}:{}", config.host, config.metrics_port))
+      .await
+      .map_err(InitError::Bind)?;
 
+  let (app, internal_app) = app(config).await?;
   let server = axum::serve(
     listener,
-    app(config)
-      .await?
-      .into_make_service_with_connect_info::<SocketAddr>(),
+    app.into_make_service_with_connect_info::<SocketAddr>(),
   )
   .with_graceful_shutdown(shutdown_signal());
+  let internal_server =
+    axum::serve(internal_listener, internal_app).with_graceful_shutdown(shutdown_signal());
 
   info!(
     "🚀 Server with API doc on http://{}/api-docs started successfully",
     server.local_addr().map_err(InitError::Bind)?
   );
+  info!(
+    "Metrics on http://{}/metrics",
+    internal_server.local_addr().map_err(InitError::Bind)?
+  );
 
-  server.await.map_err(InitError::Bind)?;
+  tokio::try_join!(server.into_future(), internal_server.into_future()).map_err(InitError::Bind)?;
 
   Ok(())
 }
 
-async fn shutdown_signal() {
-  let ctrl_c = async {
-    signal::ctrl_c()
-      .await
-      .expect("failed to install Ctrl+C handler");
-  };
-
-  #[cfg(unix)]
-  let terminate = async {
-    signal::unix::signal(signal::unix::SignalKind::terminate())
-      .expect("failed to install signal handler")
-      .recv()
-      .await;
-  };
-
-  #[cfg(not(unix))]
-  let terminate = std::future::pending::<()>();
-
-  tokio::select! {
-      _ = ctrl_c => {},
-      _ = terminate => {},
+async fn migrate(config: &AppConfig, command: MigrateCommand) -> miette::Result<()> {
+  let urls = config.migrated_database_urls();
+
+  match command {
+    MigrateCommand::Up => migrations::up(&urls).await?,
+    MigrateCommand::Down { steps } => migrations::down(&urls, steps).await?,
+    MigrateCommand::Status => {
+      for (index, url) in urls.iter().enumerate() {
+        info!("Database {}:", index);
+        for migration in migrations::status(url).await? {
+          let state = match (migration.applied, migration.checksum_mismatch) {
+            (true, false) => "applied",
+            (true, true) => "applied, changed since",
+            (false, _) => "pending",
+          };
+          info!(
+            "  {} {}: {}",
+            migration.version, migration.description, state
+          );
+        }
+      }
+    }
   }
 
-  debug!("signal received, starting graceful shutdown");
+  Ok(())
 }
@@ -1,8 +1,12 @@
-use std::sync::Arc;
+use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 
 use clap::{Parser, ValueEnum};
 use serde::Serialize;
 
+mod sources;
+
+pub use sources::{parse_layered, render_effective};
+
 #[derive(Clone, ValueEnum, Debug, Serialize, PartialEq, Eq, Default, Copy)]
 pub enum Environment {
   #[default]
@@ -17,9 +21,116 @@ impl Environment {
   }
 }
 
+/// Where the dialog subsystem runs
+#[derive(Clone, ValueEnum, Debug, Serialize, PartialEq, Eq, Default, Copy)]
+pub enum DialogMode {
+  /// Dialogs are served by this process
+  #[default]
+  Local,
+  /// Dialog requests are proxied to the dialog service
+  Remote,
+}
+
+/// Backend storing dialog messages
+#[derive(Clone, ValueEnum, Debug, Serialize, PartialEq, Eq, Default, Copy)]
+pub enum DialogStoreKind {
+  /// Sharded Postgres
+  #[default]
+  Postgres,
+  /// Process memory persisted with a write-ahead log and snapshots
+  Memory,
+}
+
+/// Handling of the JWT blacklist check when Redis does not answer
+#[derive(Clone, ValueEnum, Debug, Serialize, PartialEq, Eq, Default, Copy)]
+pub enum BlacklistPolicy {
+  /// Accept the token, revoked tokens are usable again during the outage
+  FailOpen,
+  /// Reject the request, nobody can authenticate during the outage
+  FailClosed,
+  /// Check the revocations kept in memory: made or seen by this instance, and copied from Redis
+  /// every `jwt_revocation_sync_interval`
+  #[default]
+  LocalFallback,
+}
+
+/// Requests allowed per period written as `<requests>/<seconds>` like `20/60`, `0` requests disable the limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Quota {
+  pub requests: u32,
+  pub period: Duration,
+}
+
+impl Quota {
+  pub fn is_enabled(&self) -> bool {
+    self.requests > 0
+  }
+}
+
+impl FromStr for Quota {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let invalid = || format!("invalid quota {}, expected <requests>/<seconds>", value);
+    let (requests, seconds) = value.split_once('/').ok_or_else(invalid)?;
+    let requests = requests.trim().parse::<u32>().map_err(|_| invalid())?;
+    let seconds = seconds.trim().parse::<u64>().map_err(|_| invalid())?;
+    if seconds == 0 {
+      return Err(invalid());
+    }
+
+    Ok(Self {
+      requests,
+      period: Duration::from_secs(seconds),
+    })
+  }
+}
+
+/// Concurrency limit of one route written as `<path>=<requests>` like `/api/login=32`, with the
+/// path as routed like `/api/users/{id}`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteLimit {
+  pub path: String,
+  pub limit: usize,
+}
+
+impl FromStr for RouteLimit {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let invalid = || format!("invalid route limit {}, expected <path>=<requests>", value);
+    let (path, limit) = value.rsplit_once('=').ok_or_else(invalid)?;
+    let path = path.trim();
+    let limit = limit.trim().parse::<usize>().map_err(|_| invalid())?;
+    if !path.starts_with('/') || limit == 0 {
+      return Err(invalid());
+    }
+
+    Ok(Self {
+      path: path.to_string(),
+      limit,
+    })
+  }
+}
+
+/// Format of the log lines written to stdout
+#[derive(Clone, ValueEnum, Debug, Serialize, PartialEq, Eq, Default, Copy)]
+pub enum LogFormat {
+  /// Human readable lines
+  #[default]
+  Text,
+  /// One JSON object per line for log collectors
+  Json,
+}
+
 #[derive(Parser, Debug, Clone, Default)]
 #[clap(author, about, long_about = None)]
 pub struct AppConfig {
+  /// Set path of a TOML file with the options keyed like `database_url`, the environment and the
+  /// flags override it
+  #[clap(long, env)]
+  pub config_file: Option<PathBuf>,
+
   /// Set environment
   #[clap(short, long, env, default_value = "development")]
   pub environment: Environment,
@@ -33,14 +144,202 @@ pub struct AppConfig {
   #[clap(long, env, default_value = "4238")]
   pub port: u16,
 
+  /// Set port of the internal listener serving `/metrics`, keep it out of public reach
+  #[clap(long, env, default_value = "4240")]
+  pub metrics_port: u16,
+
+  /// Set public url of the service, used to build absolute links
+  #[clap(long, env, default_value = "http://localhost:4238")]
+  pub public_url: String,
+
+  /// Set maximum number of requests served at once, the others are rejected with `503`
+  #[clap(long, env, default_value = "1024")]
+  pub max_concurrent_requests: usize,
+
+  /// Set maximum number of requests served at once by every route
+  #[clap(long, env, default_value = "256")]
+  pub route_concurrency_limit: usize,
+
+  /// Set maximum number of requests served at once by chosen routes as `<path>=<requests>`,
+  /// like `/api/login=32`, the other routes keep `route_concurrency_limit`
+  #[clap(long, env, value_delimiter = ',')]
+  pub route_concurrency_limits: Vec<RouteLimit>,
+
+  /// Set time a request may take before it is answered with `503` in milliseconds
+  #[clap(long, env, default_value = "10000")]
+  pub request_timeout: u64,
+
+  /// Set maximum size of request bodies in bytes
+  #[clap(long, env, default_value = "2097152")]
+  pub max_request_body_size: usize,
+
   /// Set database url
   #[clap(long, env)]
   pub database_url: String,
 
-  /// Set redis url
+  /// Apply pending migrations to the main database and dialog shards on startup
+  #[clap(long, env)]
+  pub migrate_on_startup: bool,
+
+  /// Set maximum number of connections of every Postgres pool
+  #[clap(long, env, default_value = "10")]
+  pub database_max_connections: u32,
+
+  /// Set time a query waits for a free pool connection in milliseconds
+  #[clap(long, env, default_value = "3000")]
+  pub database_acquire_timeout: u64,
+
+  /// Set database urls of the read replicas, reads go to the primary when empty
+  #[clap(long, env, value_delimiter = ',')]
+  pub database_replica_urls: Vec<String>,
+
+  /// Set interval of the read replica health checks in seconds
+  #[clap(long, env, default_value = "5")]
+  pub database_replica_check_interval: u64,
+
+  /// Set database urls of the dialog shards, the main database is used when empty
+  #[clap(long, env, value_delimiter = ',')]
+  pub dialog_shard_urls: Vec<String>,
+
+  /// Set interval of reloading the dialog shard topology in seconds
+  #[clap(long, env, default_value = "5")]
+  pub dialog_topology_refresh_interval: u64,
+
+  /// Set interval of rebuilding unread counters from the message store in seconds
+  #[clap(long, env, default_value = "300")]
+  pub counters_reconcile_interval: u64,
+
+  /// Set how long senders can edit their messages in seconds
+  #[clap(long, env, default_value = "172800")] // 2 days
+  pub message_edit_window: i64,
+
+  /// Set interval of purging expired disappearing messages in seconds
+  #[clap(long, env, default_value = "30")]
+  pub message_sweep_interval: u64,
+
+  /// Set backend storing dialog messages
+  #[clap(long, env, default_value = "postgres")]
+  pub dialog_store: DialogStoreKind,
+
+  /// Set directory of the in-memory dialog store snapshot and write-ahead log
+  #[clap(long, env, default_value = "data/dialogs")]
+  pub memory_store_dir: String,
+
+  /// Set interval of snapshotting the in-memory dialog store in seconds
+  #[clap(long, env, default_value = "60")]
+  pub memory_store_snapshot_interval: u64,
+
+  /// Set whether dialogs are served in process or by the dialog service
+  #[clap(long, env, default_value = "local")]
+  pub dialog_mode: DialogMode,
+
+  /// Set url of the dialog service, used in remote dialog mode
+  #[clap(long, env, default_value = "http://localhost:4239")]
+  pub dialog_service_url: String,
+
+  /// Set port of the dialog service
+  #[clap(long, env, default_value = "4239")]
+  pub dialog_service_port: u16,
+
+  /// Set port of the dialog service internal listener serving `/metrics`
+  #[clap(long, env, default_value = "4241")]
+  pub dialog_service_metrics_port: u16,
+
+  /// Set secret of the tokens the services use to call each other
+  #[clap(long, env)]
+  pub service_token_secret: Option<String>,
+
+  /// Set OTLP/HTTP endpoint spans are exported to, like `http://localhost:4318/v1/traces`.
+  /// Spans are only logged when empty
+  #[clap(long, env)]
+  pub otlp_endpoint: Option<String>,
+
+  /// Set format of the logs, sensitive values are masked in both
+  #[clap(long, env, default_value = "text")]
+  pub log_format: LogFormat,
+
+  /// Set service name reported with the exported spans
+  #[clap(long, env, default_value = "social_network")]
+  pub otel_service_name: String,
+
+  /// Set timeout of every dependency check of the readiness probe in milliseconds
+  #[clap(long, env, default_value = "1000")]
+  pub health_check_timeout: u64,
+
+  /// Set redis url, with Sentinel only its database and credentials are used
   #[clap(long, env)]
   pub redis_url: String,
 
+  /// Set addresses of the Redis Sentinels like `redis://sentinel:26379`, the master is then
+  /// discovered through them
+  #[clap(long, env, value_delimiter = ',')]
+  pub redis_sentinel_urls: Vec<String>,
+
+  /// Set name of the master monitored by the Sentinels
+  #[clap(long, env, default_value = "mymaster")]
+  pub redis_sentinel_master: String,
+
+  /// Set interval of the Redis master discovery through Sentinel in seconds
+  #[clap(long, env, default_value = "5")]
+  pub redis_sentinel_check_interval: u64,
+
+  /// Set timeout of Redis commands in milliseconds
+  #[clap(long, env, default_value = "500")]
+  pub redis_response_timeout: u64,
+
+  /// Set number of consecutive Redis failures opening the circuit breaker
+  #[clap(long, env, default_value = "5")]
+  pub redis_breaker_failure_threshold: u32,
+
+  /// Set time the Redis circuit breaker stays open before probing again in milliseconds
+  #[clap(long, env, default_value = "5000")]
+  pub redis_breaker_reset_timeout: u64,
+
+  /// Set number of users cached in process
+  #[clap(long, env, default_value = "10000")]
+  pub user_cache_capacity: usize,
+
+  /// Set time users stay in the in-process cache in seconds
+  #[clap(long, env, default_value = "30")]
+  pub user_cache_local_ttl: u64,
+
+  /// Set time users stay in the Redis cache in seconds
+  #[clap(long, env, default_value = "300")]
+  pub user_cache_redis_ttl: u64,
+
+  /// Set rate limit of `register` and `login` per client IP as `<requests>/<seconds>`
+  #[clap(long, env, default_value = "20/60")]
+  pub rate_limit_auth: Quota,
+
+  /// Set rate limit of the other API routes per user, API key or client IP as `<requests>/<seconds>`
+  #[clap(long, env, default_value = "600/60")]
+  pub rate_limit_api: Quota,
+
+  /// Set API keys of integrations, requests presenting one in `x-api-key` are rate limited per key
+  #[clap(long, env, value_delimiter = ',')]
+  pub api_keys: Vec<String>,
+
+  /// Set whether the client IP is taken from `X-Forwarded-For`, only behind a trusted proxy
+  #[clap(long, env)]
+  pub trust_forwarded_for: bool,
+
+  /// Set time responses of requests with an `Idempotency-Key` are replayed in seconds
+  #[clap(long, env, default_value = "86400")]
+  pub idempotency_ttl: u64,
+
+  /// Set time responses of `register` with an `Idempotency-Key` are replayed in seconds, kept
+  /// short as they carry the issued tokens
+  #[clap(long, env, default_value = "60")]
+  pub idempotency_auth_ttl: u64,
+
+  /// Set how tokens are checked against the blacklist while Redis is unavailable
+  #[clap(long, env, default_value = "local-fallback")]
+  pub jwt_blacklist_policy: BlacklistPolicy,
+
+  /// Set interval of copying the JWT blacklist from Redis for the local fallback in seconds
+  #[clap(long, env, default_value = "30")]
+  pub jwt_revocation_sync_interval: u64,
+
   /// Set secret key
   #[clap(long, env)]
   pub jwt_secret: String,
@@ -54,4 +353,57 @@ pub struct AppConfig {
   pub jwt_refresh_expiration: i64,
 }
 
+impl AppConfig {
+  /// Urls of the databases sharing the schema, the main one and the distinct dialog shards
+  pub fn migrated_database_urls(&self) -> Vec<String> {
+    let mut urls = vec![self.database_url.clone()];
+    for url in &self.dialog_shard_urls {
+      if !urls.contains(url) {
+        urls.push(url.clone());
+      }
+    }
+    urls
+  }
+}
+
 pub type AppConfigRc = Arc<AppConfig>;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn quotas_are_parsed() {
+    assert_eq!(
+      "20/60".parse(),
+      Ok(Quota {
+        requests: 20,
+        period: Duration::from_secs(60),
+      })
+    );
+    assert_eq!(
+      " 600 / 1 ".parse(),
+      Ok(Quota {
+        requests: 600,
+        period: Duration::from_secs(1),
+      })
+    );
+
+    let disabled = "0/60".parse::<Quota>().unwrap();
+    assert!(!disabled.is_enabled());
+  }
+
+  #[test]
+  fn invalid_quotas_are_rejected() {
+    for value in ["", "20", "20/0", "-1/60", "20/1.5", "twenty/60", "20/60/1"] {
+      assert_eq!(
+        value.parse::<Quota>(),
+        Err(format!(
+          "invalid quota {}, expected <requests>/<seconds>",
+          value
+        )),
+        "{value}"
+      );
+    }
+  }
+}
@@ -0,0 +1,170 @@
+use std::{
+  sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering},
+  time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+  /// Calls go through
+  Closed,
+  /// Calls are rejected without reaching the dependency
+  Open,
+  /// One probe call is let through to decide whether the dependency is back
+  HalfOpen,
+}
+
+impl CircuitState {
+  fn from_u8(value: u8) -> Self {
+    match value {
+      0 => Self::Closed,
+      1 => Self::Open,
+      _ => Self::HalfOpen,
+    }
+  }
+}
+
+/// Stops calling a dependency after consecutive failures and lets a single probe through once
+/// per `reset_timeout` until one succeeds
+#[derive(Debug)]
+pub struct CircuitBreaker {
+  name: &'static str,
+  failure_threshold: u32,
+  reset_timeout: Duration,
+  started: Instant,
+  state: AtomicU8,
+  failures: AtomicU32,
+  /// Milliseconds since `started` after which the next probe is allowed
+  retry_at: AtomicU64,
+}
+
+impl CircuitBreaker {
+  pub fn new(name: &'static str, failure_threshold: u32, reset_timeout: Duration) -> Self {
+    metrics::gauge!("circuit_breaker_state", "name" => name).set(0.0);
+
+    Self {
+      name,
+      failure_threshold: failure_threshold.max(1),
+      reset_timeout,
+      started: Instant::now(),
+      state: AtomicU8::new(CircuitState::Closed as u8),
+      failures: AtomicU32::new(0),
+      retry_at: AtomicU64::new(0),
+    }
+  }
+
+  pub fn state(&self) -> CircuitState {
+    CircuitState::from_u8(self.state.load(Ordering::Acquire))
+  }
+
+  /// Whether the call may go to the dependency, its outcome must then be recorded
+  pub fn allow(&self) -> bool {
+    if self.state() == CircuitState::Closed {
+      return true;
+    }
+
+    let now = self.now();
+    let retry_at = self.retry_at.load(Ordering::Acquire);
+    if now < retry_at {
+      return false;
+    }
+    // Only the caller moving the retry time forward gets to probe
+    let won = self
+      .retry_at
+      .compare_exchange(
+        retry_at,
+        now + self.reset_millis(),
+        Ordering::AcqRel,
+        Ordering::Acquire,
+      )
+      .is_ok();
+    if won {
+      self.transition(CircuitState::HalfOpen);
+    }
+
+    won
+  }
+
+  pub fn record_success(&self) {
+    self.failures.store(0, Ordering::Release);
+    if self.state() != CircuitState::Closed {
+      info!(
+        "Circuit breaker {} closed, {} is back",
+        self.name, self.name
+      );
+      self.transition(CircuitState::Closed);
+    }
+  }
+
+  pub fn record_failure(&self) {
+    let failures = self.failures.fetch_add(1, Ordering::AcqRel) + 1;
+    let state = self.state();
+    if state == CircuitState::HalfOpen
+      || (state == CircuitState::Closed && failures >= self.failure_threshold)
+    {
+      self
+        .retry_at
+        .store(self.now() + self.reset_millis(), Ordering::Release);
+      if state == CircuitState::Closed {
+        warn!(
+          "Circuit breaker {} opened after {} consecutive failures",
+          self.name, failures
+        );
+      }
+      self.transition(CircuitState::Open);
+    }
+  }
+
+  fn transition(&self, state: CircuitState) {
+    self.state.store(state as u8, Ordering::Release);
+    metrics::gauge!("circuit_breaker_state", "name" => self.name).set(f64::from(state as u8));
+  }
+
+  fn now(&self) -> u64 {
+    self.started.elapsed().as_millis() as u64
+  }
+
+  fn reset_millis(&self) -> u64 {
+    self.reset_timeout.as_millis() as u64
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn opens_after_failures_and_closes_after_a_successful_probe() {
+    let breaker = CircuitBreaker::new("test", 3, Duration::from_millis(50));
+
+    breaker.record_failure();
+    breaker.record_failure();
+    breaker.record_success();
+    breaker.record_failure();
+    breaker.record_failure();
+    assert!(breaker.allow(), "failures must be consecutive to open");
+
+    breaker.record_failure();
+    assert_eq!(breaker.state(), CircuitState::Open);
+    assert!(!breaker.allow());
+
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(breaker.allow());
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    assert!(!breaker.allow(), "only one probe is let through");
+
+    breaker.record_failure();
+    assert_eq!(breaker.state(), CircuitState::Open);
+    assert!(!breaker.allow());
+
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(breaker.allow());
+    breaker.record_success();
+    assert_eq!(breaker.state(), CircuitState::Closed);
+    assert!(breaker.allow());
+  }
+}
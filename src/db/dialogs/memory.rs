@@ -0,0 +1,937 @@
+use std::{
+  collections::HashMap,
+  io::ErrorKind,
+  path::{Path, PathBuf},
+  sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+  time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use tokio::{
+  fs::{self, File, OpenOptions},
+  io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+  sync::Mutex,
+  task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use super::{messages_page, UnreadCount, MAX_DIALOGS};
+use crate::{
+  dto::dialog::{
+    DeletedMessageDto, DialogKey, DialogSummaryDto, EnvelopeDto, MessageDto, MessageEditDto,
+    MessagePageDto,
+  },
+  errors::dialog::{DialogError, DialogResult},
+};
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.json.tmp";
+const WAL_FILE: &str = "wal.log";
+
+/// Mutation appended to the write-ahead log before it is applied
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum WalEntry {
+  InsertMessage(MessageDto),
+  MarkRead {
+    user_id: i32,
+    peer_id: i32,
+    message_id: i64,
+  },
+  EditMessage {
+    user_a: i32,
+    user_b: i32,
+    message_id: i64,
+    text: String,
+    edited_at: DateTime<Utc>,
+  },
+  DeleteMessage {
+    user_a: i32,
+    user_b: i32,
+    message_id: i64,
+  },
+  SetMessageTtl {
+    user_a: i32,
+    user_b: i32,
+    seconds: Option<i32>,
+    updated_at: DateTime<Utc>,
+  },
+  /// Delete every message expired at the moment
+  PurgeExpired {
+    now: DateTime<Utc>,
+  },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct ReadState {
+  last_message_id: i64,
+  last_read_message_id: i64,
+  updated_at: DateTime<Utc>,
+  #[serde(default)]
+  message_ttl: Option<i32>,
+}
+
+impl ReadState {
+  fn new(updated_at: DateTime<Utc>) -> Self {
+    Self {
+      last_message_id: 0,
+      last_read_message_id: 0,
+      updated_at,
+      message_ttl: None,
+    }
+  }
+}
+
+/// Everything the store keeps, serialized as is into snapshots
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct MemoryState {
+  last_message_id: i64,
+  /// Messages by `user_a` and `user_b` of the dialog, ordered by id
+  messages: HashMap<i32, HashMap<i32, Vec<MessageDto>>>,
+  /// Read state by user and peer, every dialog has a row per participant
+  dialogs: HashMap<i32, HashMap<i32, ReadState>>,
+  /// Previous versions of edited messages by message id
+  #[serde(default)]
+  edits: HashMap<i64, Vec<MessageEditDto>>,
+}
+
+impl MemoryState {
+  fn apply(&mut self, entry: WalEntry) {
+    match entry {
+      WalEntry::InsertMessage(message) => {
+        let messages = self
+          .messages
+          .entry(message.user_a)
+          .or_default()
+          .entry(message.user_b)
+          .or_default();
+        // Replaying a log which was not truncated after the snapshot
+        if messages.last().is_some_and(|last| last.id >= message.id) {
+          return;
+        }
+
+        self.last_message_id = self.last_message_id.max(message.id);
+        for (user_id, peer_id, last_read_message_id) in [
+          (message.sender_id, message.recipient_id(), message.id),
+          (message.recipient_id(), message.sender_id, 0),
+        ] {
+          let state = self
+            .dialogs
+            .entry(user_id)
+            .or_default()
+            .entry(peer_id)
+            .or_insert_with(|| ReadState::new(message.created_at));
+          state.last_message_id = state.last_message_id.max(message.id);
+          state.last_read_message_id = state.last_read_message_id.max(last_read_message_id);
+          state.updated_at = message.created_at;
+        }
+
+        messages.push(message);
+      }
+      WalEntry::MarkRead {
+        user_id,
+        peer_id,
+        message_id,
+      } => {
+        if let Some(state) = self
+          .dialogs
+          .get_mut(&user_id)
+          .and_then(|dialogs| dialogs.get_mut(&peer_id))
+        {
+          state.last_read_message_id = state.last_read_message_id.max(message_id);
+        }
+      }
+      WalEntry::EditMessage {
+        user_a,
+        user_b,
+        message_id,
+        text,
+        edited_at,
+      } => {
+        let Some(message) = self.dialog_message_mut(user_a, user_b, message_id) else {
+          return;
+        };
+        let previous = std::mem::replace(&mut message.text, text);
+        message.edited_at = Some(edited_at);
+
+        let edits = self.edits.entry(message_id).or_default();
+        edits.push(MessageEditDto {
+          version: edits.len() as i32 + 1,
+          text: previous,
+          edited_at,
+        });
+      }
+      WalEntry::DeleteMessage {
+        user_a,
+        user_b,
+        message_id,
+      } => {
+        if let Some(messages) = self
+          .messages
+          .get_mut(&user_a)
+          .and_then(|dialogs| dialogs.get_mut(&user_b))
+        {
+          messages.retain(|message| message.id != message_id);
+        }
+        self.edits.remove(&message_id);
+        self.refresh_last_message(DialogKey::new(user_a, user_b));
+      }
+      WalEntry::SetMessageTtl {
+        user_a,
+        user_b,
+        seconds,
+        updated_at,
+      } => {
+        for (user_id, peer_id) in [(user_a, user_b), (user_b, user_a)] {
+          self
+            .dialogs
+            .entry(user_id)
+            .or_default()
+            .entry(peer_id)
+            .or_insert_with(|| ReadState::new(updated_at))
+            .message_ttl = seconds;
+        }
+      }
+      WalEntry::PurgeExpired { now } => {
+        let mut purged_keys = Vec::new();
+        for (user_a, dialogs) in self.messages.iter_mut() {
+          for (user_b, messages) in dialogs.iter_mut() {
+            let before = messages.len();
+            messages.retain(|message| {
+              let expired = message
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= now);
+              if expired {
+                self.edits.remove(&message.id);
+              }
+              !expired
+            });
+            if messages.len() < before {
+              purged_keys.push(DialogKey::new(*user_a, *user_b));
+            }
+          }
+        }
+
+        for key in purged_keys {
+          self.refresh_last_message(key);
+        }
+      }
+    }
+  }
+
+  fn dialog_message_mut(
+    &mut self,
+    user_a: i32,
+    user_b: i32,
+    message_id: i64,
+  ) -> Option<&mut MessageDto> {
+    let messages = self.messages.get_mut(&user_a)?.get_mut(&user_b)?;
+    let index = messages
+      .binary_search_by_key(&message_id, |message| message.id)
+      .ok()?;
+
+    Some(&mut messages[index])
+  }
+
+  fn dialog_message(&self, key: DialogKey, message_id: i64) -> Option<&MessageDto> {
+    let messages = self.dialog_messages(key);
+    messages
+      .binary_search_by_key(&message_id, |message| message.id)
+      .ok()
+      .map(|index| &messages[index])
+  }
+
+  /// Point both sides of the dialog at its newest remaining message after deletions
+  fn refresh_last_message(&mut self, key: DialogKey) {
+    let last_message_id = self
+      .dialog_messages(key)
+      .last()
+      .map_or(0, |message| message.id);
+
+    for (user_id, peer_id) in [(key.user_a, key.user_b), (key.user_b, key.user_a)] {
+      if let Some(state) = self
+        .dialogs
+        .get_mut(&user_id)
+        .and_then(|dialogs| dialogs.get_mut(&peer_id))
+      {
+        state.last_message_id = last_message_id;
+      }
+    }
+  }
+
+  fn read_state(&self, user_id: i32, peer_id: i32) -> Option<&ReadState> {
+    self.dialogs.get(&user_id)?.get(&peer_id)
+  }
+
+  /// The message was not read by its recipient yet
+  fn is_unread(&self, message: &MessageDto) -> bool {
+    self
+      .read_state(message.recipient_id(), message.sender_id)
+      .is_none_or(|state| state.last_read_message_id < message.id)
+  }
+
+  fn dialog_messages(&self, key: DialogKey) -> &[MessageDto] {
+    self
+      .messages
+      .get(&key.user_a)
+      .and_then(|dialogs| dialogs.get(&key.user_b))
+      .map(Vec::as_slice)
+      .unwrap_or_default()
+  }
+
+  fn unread(&self, user_id: i32, peer_id: i32) -> i64 {
+    self
+      .read_state(user_id, peer_id)
+      .map_or(0, |state| self.unread_count(user_id, peer_id, state))
+  }
+
+  fn unread_count(&self, user_id: i32, peer_id: i32, state: &ReadState) -> i64 {
+    let messages = self.dialog_messages(DialogKey::new(user_id, peer_id));
+    let first_unread = messages.partition_point(|message| message.id <= state.last_read_message_id);
+
+    messages[first_unread..]
+      .iter()
+      .filter(|message| message.sender_id == peer_id)
+      .count() as i64
+  }
+}
+
+/// Append-only log file, truncated back on failed writes so a torn entry never precedes valid ones
+#[derive(Debug)]
+struct Wal {
+  file: File,
+  len: u64,
+}
+
+impl Wal {
+  async fn append(&mut self, entry: &WalEntry) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    let written = async {
+      self.file.write_all(&line).await?;
+      self.file.sync_data().await
+    }
+    .await;
+
+    match written {
+      Ok(()) => {
+        self.len += line.len() as u64;
+        Ok(())
+      }
+      Err(e) => {
+        if let Err(truncate) = self.file.set_len(self.len).await {
+          warn!(
+            "Failed to truncate write-ahead log after failed write: {}",
+            truncate
+          );
+        }
+        Err(e)
+      }
+    }
+  }
+
+  async fn truncate(&mut self) -> std::io::Result<()> {
+    self.file.set_len(0).await?;
+    self.file.sync_data().await?;
+    self.len = 0;
+    Ok(())
+  }
+}
+
+#[derive(Debug)]
+struct Inner {
+  dir: PathBuf,
+  state: RwLock<MemoryState>,
+  wal: Mutex<Wal>,
+}
+
+/// Dialogs kept in process memory.
+///
+/// Every mutation is appended to a write-ahead log before being applied, periodic snapshots
+/// keep the log short. On start the latest snapshot is loaded and the log is replayed over it.
+/// The files are local, so only a single process may serve dialogs from this store
+#[derive(Clone, Debug)]
+pub struct MemoryDialogStore {
+  inner: Arc<Inner>,
+}
+
+impl MemoryDialogStore {
+  pub async fn open(dir: impl AsRef<Path>) -> std::io::Result<Self> {
+    let dir = dir.as_ref().to_path_buf();
+    fs::create_dir_all(&dir).await?;
+
+    let mut state = match fs::read(dir.join(SNAPSHOT_FILE)).await {
+      Ok(snapshot) => serde_json::from_slice::<MemoryState>(&snapshot)?,
+      Err(e) if e.kind() == ErrorKind::NotFound => MemoryState::default(),
+      Err(e) => return Err(e),
+    };
+    let (replayed, wal_len) = Self::replay(&dir.join(WAL_FILE), &mut state).await?;
+    info!(
+      "Loaded in-memory dialog store from {}, replayed {} log entries",
+      dir.display(),
+      replayed
+    );
+
+    let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(dir.join(WAL_FILE))
+      .await?;
+
+    Ok(Self {
+      inner: Arc::new(Inner {
+        dir,
+        state: RwLock::new(state),
+        wal: Mutex::new(Wal { file, len: wal_len }),
+      }),
+    })
+  }
+
+  pub async fn insert_message(
+    &self,
+    sender_id: i32,
+    recipient_id: i32,
+    text: String,
+    envelope: Option<EnvelopeDto>,
+  ) -> DialogResult<MessageDto> {
+    let key = DialogKey::new(sender_id, recipient_id);
+
+    // Holding the log lock orders ids the same way as the log entries
+    let mut wal = self.inner.wal.lock().await;
+    let message = {
+      let state = self.read_state();
+      let created_at = Utc::now();
+      let message_ttl = state
+        .read_state(sender_id, recipient_id)
+        .and_then(|read_state| read_state.message_ttl);
+
+      MessageDto {
+        id: state.last_message_id + 1,
+        user_a: key.user_a,
+        user_b: key.user_b,
+        sender_id,
+        text,
+        created_at,
+        edited_at: None,
+        expires_at: message_ttl
+          .map(|seconds| created_at + chrono::Duration::seconds(seconds.into())),
+        envelope: envelope.map(Json),
+      }
+    };
+
+    let entry = WalEntry::InsertMessage(message.clone());
+    wal
+      .append(&entry)
+      .await
+      .map_err(DialogError::FailedToPersist)?;
+    self.write_state().apply(entry);
+
+    Ok(message)
+  }
+
+  pub fn list_messages(
+    &self,
+    key: DialogKey,
+    cursor: Option<i64>,
+    limit: i64,
+  ) -> DialogResult<MessagePageDto> {
+    let state = self.read_state();
+    let messages = state.dialog_messages(key);
+    let end = cursor.map_or(messages.len(), |cursor| {
+      messages.partition_point(|message| message.id < cursor)
+    });
+    let now = Utc::now();
+
+    // Fetch one extra message to know whether there is a next page
+    let messages = messages[..end]
+      .iter()
+      .rev()
+      .filter(|message| !is_expired(message, now))
+      .take(limit as usize + 1)
+      .cloned()
+      .collect();
+
+    Ok(messages_page(messages, limit))
+  }
+
+  pub fn list_dialogs(&self, user_id: i32) -> DialogResult<Vec<DialogSummaryDto>> {
+    let state = self.read_state();
+    let Some(dialogs) = state.dialogs.get(&user_id) else {
+      return Ok(Vec::new());
+    };
+
+    let mut dialogs = dialogs
+      .iter()
+      .filter_map(|(peer_id, read_state)| {
+        let messages = state.dialog_messages(DialogKey::new(user_id, *peer_id));
+        let last_message = messages
+          .binary_search_by_key(&read_state.last_message_id, |message| message.id)
+          .ok()
+          .map(|index| messages[index].clone())?;
+
+        Some(DialogSummaryDto {
+          peer_id: *peer_id,
+          last_message,
+          unread_count: state.unread_count(user_id, *peer_id, read_state),
+          message_ttl: read_state.message_ttl,
+        })
+      })
+      .collect::<Vec<_>>();
+
+    dialogs.sort_by(|a, b| b.last_message.created_at.cmp(&a.last_message.created_at));
+    dialogs.truncate(MAX_DIALOGS as usize);
+
+    Ok(dialogs)
+  }
+
+  pub async fn mark_read(&self, user_id: i32, peer_id: i32, message_id: i64) -> DialogResult<i64> {
+    let mut wal = self.inner.wal.lock().await;
+
+    // Reading an already read dialog is the common case, it does not need a log entry
+    let is_unread = self
+      .read_state()
+      .read_state(user_id, peer_id)
+      .is_some_and(|state| state.last_read_message_id < message_id);
+    if !is_unread {
+      return Ok(self.read_state().unread(user_id, peer_id));
+    }
+
+    let entry = WalEntry::MarkRead {
+      user_id,
+      peer_id,
+      message_id,
+    };
+    wal
+      .append(&entry)
+      .await
+      .map_err(DialogError::FailedToPersist)?;
+    let mut state = self.write_state();
+    state.apply(entry);
+
+    Ok(state.unread(user_id, peer_id))
+  }
+
+  pub fn get_message(&self, key: DialogKey, message_id: i64) -> Option<MessageDto> {
+    self
+      .read_state()
+      .dialog_message(key, message_id)
+      .filter(|message| !is_expired(message, Utc::now()))
+      .cloned()
+  }
+
+  pub async fn edit_message(
+    &self,
+    key: DialogKey,
+    message_id: i64,
+    text: String,
+  ) -> DialogResult<Option<MessageDto>> {
+    let mut wal = self.inner.wal.lock().await;
+    if self.get_message(key, message_id).is_none() {
+      return Ok(None);
+    }
+
+    let entry = WalEntry::EditMessage {
+      user_a: key.user_a,
+      user_b: key.user_b,
+      message_id,
+      text,
+      edited_at: Utc::now(),
+    };
+    wal
+      .append(&entry)
+      .await
+      .map_err(DialogError::FailedToPersist)?;
+    self.write_state().apply(entry);
+
+    Ok(self.get_message(key, message_id))
+  }
+
+  pub fn message_edits(&self, key: DialogKey, message_id: i64) -> Vec<MessageEditDto> {
+    let state = self.read_state();
+    if state.dialog_message(key, message_id).is_none() {
+      return Vec::new();
+    }
+
+    state.edits.get(&message_id).cloned().unwrap_or_default()
+  }
+
+  pub async fn delete_message(
+    &self,
+    key: DialogKey,
+    message_id: i64,
+  ) -> DialogResult<Option<DeletedMessageDto>> {
+    let mut wal = self.inner.wal.lock().await;
+    let Some(deleted) = ({
+      let state = self.read_state();
+      state
+        .dialog_message(key, message_id)
+        .map(|message| DeletedMessageDto {
+          message: message.clone(),
+          unread: state.is_unread(message),
+        })
+    }) else {
+      return Ok(None);
+    };
+
+    let entry = WalEntry::DeleteMessage {
+      user_a: key.user_a,
+      user_b: key.user_b,
+      message_id,
+    };
+    wal
+      .append(&entry)
+      .await
+      .map_err(DialogError::FailedToPersist)?;
+    self.write_state().apply(entry);
+
+    Ok(Some(deleted))
+  }
+
+  pub async fn set_message_ttl(&self, key: DialogKey, seconds: Option<i32>) -> DialogResult<()> {
+    let mut wal = self.inner.wal.lock().await;
+
+    let entry = WalEntry::SetMessageTtl {
+      user_a: key.user_a,
+      user_b: key.user_b,
+      seconds,
+      updated_at: Utc::now(),
+    };
+    wal
+      .append(&entry)
+      .await
+      .map_err(DialogError::FailedToPersist)?;
+    self.write_state().apply(entry);
+
+    Ok(())
+  }
+
+  /// Writes wait for the log lock, so the expired messages found here are exactly the purged ones
+  pub async fn purge_expired(&self) -> DialogResult<Vec<DeletedMessageDto>> {
+    let mut wal = self.inner.wal.lock().await;
+    let now = Utc::now();
+
+    let purged = {
+      let state = self.read_state();
+      state
+        .messages
+        .values()
+        .flat_map(HashMap::values)
+        .flatten()
+        .filter(|message| is_expired(message, now))
+        .map(|message| DeletedMessageDto {
+          message: message.clone(),
+          unread: state.is_unread(message),
+        })
+        .collect::<Vec<_>>()
+    };
+    if purged.is_empty() {
+      return Ok(purged);
+    }
+
+    let entry = WalEntry::PurgeExpired { now };
+    wal
+      .append(&entry)
+      .await
+      .map_err(DialogError::FailedToPersist)?;
+    self.write_state().apply(entry);
+
+    Ok(purged)
+  }
+
+  pub fn unread_counts(&self) -> Vec<UnreadCount> {
+    let state = self.read_state();
+
+    state
+      .dialogs
+      .iter()
+      .flat_map(|(user_id, dialogs)| {
+        dialogs.iter().map(|(peer_id, read_state)| UnreadCount {
+          user_id: *user_id,
+          peer_id: *peer_id,
+          unread: state.unread_count(*user_id, *peer_id, read_state),
+        })
+      })
+      .filter(|count| count.unread > 0)
+      .collect()
+  }
+
+  /// Write the whole state into a new snapshot and start the log over.
+  ///
+  /// Writes wait for the snapshot, a crash before the log is truncated only replays entries
+  /// already in the snapshot, which are skipped
+  #[tracing::instrument(name = "snapshot_dialogs", skip(self))]
+  pub async fn snapshot(&self) -> std::io::Result<()> {
+    let mut wal = self.inner.wal.lock().await;
+    let snapshot = serde_json::to_vec(&*self.read_state())?;
+
+    let tmp_path = self.inner.dir.join(SNAPSHOT_TMP_FILE);
+    let mut file = File::create(&tmp_path).await?;
+    file.write_all(&snapshot).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp_path, self.inner.dir.join(SNAPSHOT_FILE)).await?;
+    File::open(&self.inner.dir).await?.sync_all().await?;
+
+    wal.truncate().await
+  }
+
+  pub fn spawn_snapshots(&self, interval: Duration) -> JoinHandle<()> {
+    let store = self.clone();
+
+    tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(interval);
+      ticker.tick().await;
+
+      loop {
+        ticker.tick().await;
+
+        if let Err(e) = store.snapshot().await {
+          warn!("Failed to snapshot in-memory dialog store: {}", e);
+        }
+      }
+    })
+  }
+
+  /// Apply the log entries, returns their count and the length of the valid part of the log.
+  ///
+  /// An entry torn by a crash ends the log, it is cut off so new entries follow valid ones
+  async fn replay(path: &Path, state: &mut MemoryState) -> std::io::Result<(usize, u64)> {
+    let file = match File::open(path).await {
+      Ok(file) => file,
+      Err(e) if e.kind() == ErrorKind::NotFound => return Ok((0, 0)),
+      Err(e) => return Err(e),
+    };
+    let file_len = file.metadata().await?.len();
+
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let mut replayed = 0;
+    let mut valid_len = 0;
+    loop {
+      line.clear();
+      let read = reader.read_until(b'\n', &mut line).await?;
+      if read == 0 || line.last() != Some(&b'\n') {
+        break;
+      }
+      let Ok(entry) = serde_json::from_slice::<WalEntry>(&line) else {
+        break;
+      };
+
+      state.apply(entry);
+      replayed += 1;
+      valid_len += read as u64;
+    }
+
+    if valid_len < file_len {
+      warn!(
+        "Cutting off {} bytes of torn write-ahead log entries",
+        file_len - valid_len
+      );
+      OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await?
+        .set_len(valid_len)
+        .await?;
+    }
+
+    Ok((replayed, valid_len))
+  }
+
+  fn read_state(&self) -> RwLockReadGuard<'_, MemoryState> {
+    self
+      .inner
+      .state
+      .read()
+      .unwrap_or_else(PoisonError::into_inner)
+  }
+
+  fn write_state(&self) -> RwLockWriteGuard<'_, MemoryState> {
+    self
+      .inner
+      .state
+      .write()
+      .unwrap_or_else(PoisonError::into_inner)
+  }
+}
+
+fn is_expired(message: &MessageDto, now: DateTime<Utc>) -> bool {
+  message
+    .expires_at
+    .is_some_and(|expires_at| expires_at <= now)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  async fn empty_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir).await;
+    dir
+  }
+
+  #[tokio::test]
+  async fn state_is_restored_from_snapshot_and_log() {
+    let dir = empty_dir("sn_test_memory_store_restore").await;
+
+    let store = MemoryDialogStore::open(&dir).await.unwrap();
+    store
+      .insert_message(1, 2, "ping".to_string(), None)
+      .await
+      .unwrap();
+    store
+      .insert_message(2, 1, "pong".to_string(), None)
+      .await
+      .unwrap();
+    store.snapshot().await.unwrap();
+    let message = store
+      .insert_message(3, 1, "hi".to_string(), None)
+      .await
+      .unwrap();
+    store.mark_read(1, 3, message.id).await.unwrap();
+    drop(store);
+
+    let store = MemoryDialogStore::open(&dir).await.unwrap();
+    let page = store.list_messages(DialogKey::new(1, 2), None, 1).unwrap();
+    assert_eq!(page.messages[0].text, "pong");
+    assert_eq!(page.next_cursor, Some(2));
+
+    let dialogs = store.list_dialogs(1).unwrap();
+    assert_eq!(dialogs.len(), 2);
+    assert_eq!(dialogs[0].peer_id, 3);
+    assert_eq!(dialogs[0].unread_count, 0);
+    assert_eq!(
+      store.unread_counts(),
+      vec![UnreadCount {
+        user_id: 1,
+        peer_id: 2,
+        unread: 1
+      }]
+    );
+
+    let message = store
+      .insert_message(1, 2, "again".to_string(), None)
+      .await
+      .unwrap();
+    assert_eq!(message.id, 4);
+  }
+
+  #[tokio::test]
+  async fn reading_up_to_a_middle_message_keeps_newer_ones_unread() {
+    let dir = empty_dir("sn_test_memory_store_partial_read").await;
+
+    let store = MemoryDialogStore::open(&dir).await.unwrap();
+    let mut messages = Vec::new();
+    for text in ["one", "two", "three", "four"] {
+      messages.push(
+        store
+          .insert_message(2, 1, text.to_string(), None)
+          .await
+          .unwrap(),
+      );
+    }
+
+    assert_eq!(store.mark_read(1, 2, messages[1].id).await.unwrap(), 2);
+    assert_eq!(
+      store.mark_read(1, 2, messages[0].id).await.unwrap(),
+      2,
+      "an older cursor does not move the read state back"
+    );
+    assert_eq!(store.list_dialogs(1).unwrap()[0].unread_count, 2);
+    assert_eq!(store.mark_read(1, 2, messages[3].id).await.unwrap(), 0);
+  }
+
+  #[tokio::test]
+  async fn edits_deletions_and_purges_are_replayed() {
+    let dir = empty_dir("sn_test_memory_store_lifecycle").await;
+    let key = DialogKey::new(1, 2);
+
+    let store = MemoryDialogStore::open(&dir).await.unwrap();
+    let edited = store
+      .insert_message(1, 2, "helo".to_string(), None)
+      .await
+      .unwrap();
+    let deleted = store
+      .insert_message(1, 2, "oops".to_string(), None)
+      .await
+      .unwrap();
+    store
+      .edit_message(key, edited.id, "hello".to_string())
+      .await
+      .unwrap();
+    let removed = store
+      .delete_message(key, deleted.id)
+      .await
+      .unwrap()
+      .unwrap();
+    assert!(removed.unread);
+
+    store.set_message_ttl(key, Some(0)).await.unwrap();
+    let expiring = store
+      .insert_message(1, 2, "gone".to_string(), None)
+      .await
+      .unwrap();
+    assert!(expiring.expires_at.is_some());
+    let purged = store.purge_expired().await.unwrap();
+    assert_eq!(purged.len(), 1);
+    assert_eq!(purged[0].message.id, expiring.id);
+    assert!(purged[0].unread);
+    drop(store);
+
+    let store = MemoryDialogStore::open(&dir).await.unwrap();
+    let page = store.list_messages(key, None, 10).unwrap();
+    assert_eq!(page.messages.len(), 1);
+    assert_eq!(page.messages[0].text, "hello");
+    assert!(page.messages[0].edited_at.is_some());
+
+    let edits = store.message_edits(key, edited.id);
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].text, "helo");
+
+    let dialogs = store.list_dialogs(1).unwrap();
+    assert_eq!(dialogs[0].last_message.id, edited.id);
+    assert_eq!(dialogs[0].message_ttl, Some(0));
+    assert_eq!(
+      store.unread_counts(),
+      vec![UnreadCount {
+        user_id: 2,
+        peer_id: 1,
+        unread: 1
+      }]
+    );
+  }
+
+  #[tokio::test]
+  async fn torn_log_entry_is_cut_off() {
+    let dir = empty_dir("sn_test_memory_store_torn").await;
+
+    let store = MemoryDialogStore::open(&dir).await.unwrap();
+    store
+      .insert_message(1, 2, "ping".to_string(), None)
+      .await
+      .unwrap();
+    drop(store);
+
+    let mut wal = OpenOptions::new()
+      .append(true)
+      .open(dir.join(WAL_FILE))
+      .await
+      .unwrap();
+    wal.write_all(br#"{"op":"insert_mess"#).await.unwrap();
+    drop(wal);
+
+    let store = MemoryDialogStore::open(&dir).await.unwrap();
+    store
+      .insert_message(2, 1, "pong".to_string(), None)
+      .await
+      .unwrap();
+    drop(store);
+
+    let store = MemoryDialogStore::open(&dir).await.unwrap();
+    let page = store.list_messages(DialogKey::new(1, 2), None, 10).unwrap();
+    assert_eq!(page.messages.len(), 2);
+  }
+}
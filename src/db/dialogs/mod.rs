@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+use crate::{
+  config::{AppConfig, DialogStoreKind},
+  db::shards::ShardMap,
+  dto::dialog::{
+    DeletedMessageDto, DialogKey, DialogSummaryDto, EnvelopeDto, MessageDto, MessageEditDto,
+    MessagePageDto,
+  },
+  errors::{common::DatabaseResult, dialog::DialogResult},
+};
+
+pub mod memory;
+pub mod postgres;
+
+use memory::MemoryDialogStore;
+use postgres::PgDialogStore;
+
+pub const MAX_DIALOGS: i64 = 100;
+/// Expired messages deleted per statement, so a sweep never holds locks for long
+pub const PURGE_BATCH: i64 = 1000;
+
+/// Unread messages of a user in a dialog according to the read state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnreadCount {
+  pub user_id: i32,
+  pub peer_id: i32,
+  pub unread: i64,
+}
+
+/// Cut the extra message fetched past the limit and point the cursor at the last returned one
+fn messages_page(mut messages: Vec<MessageDto>, limit: i64) -> MessagePageDto {
+  let next_cursor = if messages.len() as i64 > limit {
+    messages.truncate(limit as usize);
+    messages.last().map(|message| message.id)
+  } else {
+    None
+  };
+
+  MessagePageDto {
+    messages,
+    next_cursor,
+  }
+}
+
+/// Backend keeping messages and read state of the dialogs, selected by `DIALOG_STORE`
+#[derive(Clone, Debug)]
+pub enum DialogStore {
+  Postgres(PgDialogStore),
+  Memory(MemoryDialogStore),
+}
+
+impl DialogStore {
+  pub async fn open(app_config: &AppConfig, shards: ShardMap) -> DatabaseResult<Self> {
+    Ok(match app_config.dialog_store {
+      DialogStoreKind::Postgres => Self::Postgres(PgDialogStore::new(shards)),
+      DialogStoreKind::Memory => {
+        Self::Memory(MemoryDialogStore::open(&app_config.memory_store_dir).await?)
+      }
+    })
+  }
+
+  pub async fn insert_message(
+    &self,
+    sender_id: i32,
+    recipient_id: i32,
+    text: String,
+    envelope: Option<EnvelopeDto>,
+  ) -> DialogResult<MessageDto> {
+    match self {
+      Self::Postgres(store) => {
+        store
+          .insert_message(sender_id, recipient_id, text, envelope)
+          .await
+      }
+      Self::Memory(store) => {
+        store
+          .insert_message(sender_id, recipient_id, text, envelope)
+          .await
+      }
+    }
+  }
+
+  pub async fn list_messages(
+    &self,
+    key: DialogKey,
+    cursor: Option<i64>,
+    limit: i64,
+  ) -> DialogResult<MessagePageDto> {
+    match self {
+      Self::Postgres(store) => store.list_messages(key, cursor, limit).await,
+      Self::Memory(store) => store.list_messages(key, cursor, limit),
+    }
+  }
+
+  pub async fn list_dialogs(&self, user_id: i32) -> DialogResult<Vec<DialogSummaryDto>> {
+    match self {
+      Self::Postgres(store) => store.list_dialogs(user_id).await,
+      Self::Memory(store) => store.list_dialogs(user_id),
+    }
+  }
+
+  /// Move the read cursor of the user, returns the messages of the peer still unread after it
+  pub async fn mark_read(&self, user_id: i32, peer_id: i32, message_id: i64) -> DialogResult<i64> {
+    match self {
+      Self::Postgres(store) => store.mark_read(user_id, peer_id, message_id).await,
+      Self::Memory(store) => store.mark_read(user_id, peer_id, message_id).await,
+    }
+  }
+
+  pub async fn get_message(
+    &self,
+    key: DialogKey,
+    message_id: i64,
+  ) -> DialogResult<Option<MessageDto>> {
+    match self {
+      Self::Postgres(store) => store.get_message(key, message_id).await,
+      Self::Memory(store) => Ok(store.get_message(key, message_id)),
+    }
+  }
+
+  pub async fn edit_message(
+    &self,
+    key: DialogKey,
+    message_id: i64,
+    text: String,
+  ) -> DialogResult<Option<MessageDto>> {
+    match self {
+      Self::Postgres(store) => store.edit_message(key, message_id, text).await,
+      Self::Memory(store) => store.edit_message(key, message_id, text).await,
+    }
+  }
+
+  pub async fn message_edits(
+    &self,
+    key: DialogKey,
+    message_id: i64,
+  ) -> DialogResult<Vec<MessageEditDto>> {
+    match self {
+      Self::Postgres(store) => store.message_edits(key, message_id).await,
+      Self::Memory(store) => Ok(store.message_edits(key, message_id)),
+    }
+  }
+
+  pub async fn delete_message(
+    &self,
+    key: DialogKey,
+    message_id: i64,
+  ) -> DialogResult<Option<DeletedMessageDto>> {
+    match self {
+      Self::Postgres(store) => store.delete_message(key, message_id).await,
+      Self::Memory(store) => store.delete_message(key, message_id).await,
+    }
+  }
+
+  pub async fn set_message_ttl(&self, key: DialogKey, seconds: Option<i32>) -> DialogResult<()> {
+    match self {
+      Self::Postgres(store) => store.set_message_ttl(key, seconds).await,
+      Self::Memory(store) => store.set_message_ttl(key, seconds).await,
+    }
+  }
+
+  pub async fn purge_expired(&self) -> DialogResult<Vec<DeletedMessageDto>> {
+    match self {
+      Self::Postgres(store) => store.purge_expired().await,
+      Self::Memory(store) => store.purge_expired().await,
+    }
+  }
+
+  pub async fn unread_counts(&self) -> DialogResult<Vec<UnreadCount>> {
+    match self {
+      Self::Postgres(store) => store.unread_counts().await,
+      Self::Memory(store) => Ok(store.unread_counts()),
+    }
+  }
+
+  /// Periodically snapshot the in-memory store, Postgres needs no background work
+  pub fn spawn_snapshots(&self, interval: Duration) -> Option<JoinHandle<()>> {
+    match self {
+      Self::Postgres(_) => None,
+      Self::Memory(store) => Some(store.spawn_snapshots(interval)),
+    }
+  }
+}
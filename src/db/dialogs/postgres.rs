@@ -0,0 +1,813 @@
+use futures::future::try_join_all;
+use sqlx::{types::Json, PgConnection, PgPool};
+use tracing::warn;
+
+use super::{messages_page, UnreadCount, MAX_DIALOGS, PURGE_BATCH};
+use crate::{
+  db::shards::ShardMap,
+  dto::dialog::{
+    DeletedMessageDto, DialogKey, DialogSummaryDto, EnvelopeDto, MessageDto, MessageEditDto,
+    MessagePageDto,
+  },
+  errors::dialog::{DialogError, DialogResult},
+};
+
+/// Messages and read state of the dialogs spread over the Postgres shards
+#[derive(Clone, Debug)]
+pub struct PgDialogStore {
+  shards: ShardMap,
+}
+
+impl PgDialogStore {
+  pub fn new(shards: ShardMap) -> Self {
+    Self { shards }
+  }
+
+  /// Store the message on the shard of the dialog, and on the one it is being moved to.
+  ///
+  /// The message expires according to the disappearing messages timeout of the dialog
+  pub async fn insert_message(
+    &self,
+    sender_id: i32,
+    recipient_id: i32,
+    text: String,
+    envelope: Option<EnvelopeDto>,
+  ) -> DialogResult<MessageDto> {
+    let key = DialogKey::new(sender_id, recipient_id);
+    let (shard, mirror) = self.shards.write_shards(&key.shard_key());
+    let mut tx = shard
+      .begin()
+      .await
+      .map_err(DialogError::FailedToSendMessage)?;
+
+    let message = sqlx::query_as!(
+      MessageDto,
+      r#"
+        INSERT INTO messages (user_a, user_b, sender_id, text, expires_at, envelope)
+        VALUES (
+          $1, $2, $3, $4,
+          NOW() + (SELECT message_ttl FROM dialogs WHERE user_id = $3 AND peer_id = $5)
+            * INTERVAL '1 second',
+          $6
+        )
+        RETURNING
+          id, user_a, user_b, sender_id, text, created_at, edited_at, expires_at,
+          envelope AS "envelope: Json<EnvelopeDto>"
+      "#,
+      key.user_a,
+      key.user_b,
+      sender_id,
+      text,
+      recipient_id,
+      envelope.map(Json) as _
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(DialogError::FailedToSendMessage)?;
+
+    Self::upsert_dialogs(&mut tx, &message)
+      .await
+      .map_err(DialogError::FailedToSendMessage)?;
+
+    tx.commit()
+      .await
+      .map_err(DialogError::FailedToSendMessage)?;
+
+    // The dialog is being moved to another shard, a lost mirror write is fixed by the resharding verification
+    if let Some(mirror) = mirror {
+      if let Err(e) = Self::mirror_message(&mirror, &message).await {
+        warn!("Failed to mirror message {}: {}", message.id, e);
+      }
+    }
+
+    Ok(message)
+  }
+
+  /// Messages of the dialog, newest first. Expired messages are hidden until the sweeper purges them
+  pub async fn list_messages(
+    &self,
+    key: DialogKey,
+    cursor: Option<i64>,
+    limit: i64,
+  ) -> DialogResult<MessagePageDto> {
+    // Fetch one extra row to know whether there is a next page
+    let messages = sqlx::query_as!(
+      MessageDto,
+      r#"
+        SELECT
+          id, user_a, user_b, sender_id, text, created_at, edited_at, expires_at,
+          envelope AS "envelope: Json<EnvelopeDto>"
+        FROM messages
+        WHERE user_a = $1 AND user_b = $2
+          AND ($3::BIGINT IS NULL OR id < $3)
+          AND (expires_at IS NULL OR expires_at > NOW())
+        ORDER BY id DESC
+        LIMIT $4
+      "#,
+      key.user_a,
+      key.user_b,
+      cursor,
+      limit + 1
+    )
+    .fetch_all(&self.shards.shard(&key.shard_key()))
+    .await
+    .map_err(DialogError::FailedToFindMessages)?;
+
+    Ok(messages_page(messages, limit))
+  }
+
+  /// Dialogs of the user with the last message and unread count, most recent first.
+  ///
+  /// Dialogs of a user are spread over all shards, so every shard is queried
+  pub async fn list_dialogs(&self, user_id: i32) -> DialogResult<Vec<DialogSummaryDto>> {
+    let mut dialogs = try_join_all(
+      self
+        .shards
+        .all()
+        .iter()
+        .map(|shard| Self::list_shard_dialogs(shard, user_id)),
+    )
+    .await?
+    .into_iter()
+    .enumerate()
+    // Dialogs being moved have copies on two shards, only the one serving reads counts
+    .flat_map(|(shard_index, dialogs)| {
+      dialogs.into_iter().filter(move |dialog| {
+        let key = DialogKey::new(user_id, dialog.peer_id);
+        self.shards.shard_index(&key.shard_key()) == shard_index
+      })
+    })
+    .collect::<Vec<_>>();
+
+    dialogs.sort_by(|a, b| b.last_message.created_at.cmp(&a.last_message.created_at));
+    dialogs.truncate(MAX_DIALOGS as usize);
+
+    Ok(dialogs)
+  }
+
+  pub async fn mark_read(&self, user_id: i32, peer_id: i32, message_id: i64) -> DialogResult<i64> {
+    let key = DialogKey::new(user_id, peer_id);
+    let (shard, mirror) = self.shards.write_shards(&key.shard_key());
+
+    let mut unread = Vec::with_capacity(2);
+    for shard in std::iter::once(shard).chain(mirror) {
+      let remaining = sqlx::query_scalar!(
+        r#"
+          WITH updated AS (
+            UPDATE dialogs
+            SET last_read_message_id = GREATEST(last_read_message_id, $3)
+            WHERE user_id = $1 AND peer_id = $2
+            RETURNING last_read_message_id
+          )
+          SELECT COUNT(m.id) AS "unread!"
+          FROM updated u
+          JOIN messages m
+            ON m.user_a = $4
+            AND m.user_b = $5
+            AND m.id > u.last_read_message_id
+            AND m.sender_id = $2
+        "#,
+        user_id,
+        peer_id,
+        message_id,
+        key.user_a,
+        key.user_b
+      )
+      .fetch_one(&shard)
+      .await
+      .map_err(DialogError::FailedToFindMessages)?;
+      unread.push(remaining);
+    }
+
+    // The owning shard answers, the mirror of a resharding only follows it
+    Ok(unread[0])
+  }
+
+  pub async fn get_message(
+    &self,
+    key: DialogKey,
+    message_id: i64,
+  ) -> DialogResult<Option<MessageDto>> {
+    sqlx::query_as!(
+      MessageDto,
+      r#"
+        SELECT
+          id, user_a, user_b, sender_id, text, created_at, edited_at, expires_at,
+          envelope AS "envelope: Json<EnvelopeDto>"
+        FROM messages
+        WHERE user_a = $1 AND user_b = $2 AND id = $3
+          AND (expires_at IS NULL OR expires_at > NOW())
+      "#,
+      key.user_a,
+      key.user_b,
+      message_id
+    )
+    .fetch_optional(&self.shards.shard(&key.shard_key()))
+    .await
+    .map_err(DialogError::FailedToFindMessages)
+  }
+
+  /// Replace the text of the message keeping the previous one in its history
+  pub async fn edit_message(
+    &self,
+    key: DialogKey,
+    message_id: i64,
+    text: String,
+  ) -> DialogResult<Option<MessageDto>> {
+    let (shard, mirror) = self.shards.write_shards(&key.shard_key());
+    let message = Self::edit_on_shard(&shard, key, message_id, &text)
+      .await
+      .map_err(DialogError::FailedToUpdateMessage)?;
+
+    if let (Some(mirror), Some(_)) = (mirror, &message) {
+      if let Err(e) = Self::edit_on_shard(&mirror, key, message_id, &text).await {
+        warn!("Failed to mirror edit of message {}: {}", message_id, e);
+      }
+    }
+
+    Ok(message)
+  }
+
+  /// Previous versions of the message, oldest first
+  pub async fn message_edits(
+    &self,
+    key: DialogKey,
+    message_id: i64,
+  ) -> DialogResult<Vec<MessageEditDto>> {
+    sqlx::query_as!(
+      MessageEditDto,
+      r#"
+        SELECT version, text, edited_at
+        FROM message_edits
+        WHERE user_a = $1 AND user_b = $2 AND message_id = $3
+        ORDER BY version
+      "#,
+      key.user_a,
+      key.user_b,
+      message_id
+    )
+    .fetch_all(&self.shards.shard(&key.shard_key()))
+    .await
+    .map_err(DialogError::FailedToFindMessages)
+  }
+
+  /// Delete the message with its history for both participants
+  pub async fn delete_message(
+    &self,
+    key: DialogKey,
+    message_id: i64,
+  ) -> DialogResult<Option<DeletedMessageDto>> {
+    let (shard, mirror) = self.shards.write_shards(&key.shard_key());
+    let deleted = Self::delete_on_shard(&shard, key, message_id)
+      .await
+      .map_err(DialogError::FailedToUpdateMessage)?;
+
+    if let Some(mirror) = mirror {
+      if let Err(e) = Self::delete_on_shard(&mirror, key, message_id).await {
+        warn!("Failed to mirror deletion of message {}: {}", message_id, e);
+      }
+    }
+
+    Ok(deleted)
+  }
+
+  /// Set the disappearing messages timeout of the dialog, the dialog is created if it has no messages yet
+  pub async fn set_message_ttl(&self, key: DialogKey, seconds: Option<i32>) -> DialogResult<()> {
+    let (shard, mirror) = self.shards.write_shards(&key.shard_key());
+
+    for shard in std::iter::once(shard).chain(mirror) {
+      sqlx::query!(
+        r#"
+          INSERT INTO dialogs (user_id, peer_id, last_message_id, message_ttl)
+          VALUES ($1, $2, 0, $3), ($2, $1, 0, $3)
+          ON CONFLICT (user_id, peer_id) DO UPDATE SET message_ttl = EXCLUDED.message_ttl
+        "#,
+        key.user_a,
+        key.user_b,
+        seconds
+      )
+      .execute(&shard)
+      .await
+      .map_err(DialogError::FailedToUpdateMessage)?;
+    }
+
+    Ok(())
+  }
+
+  /// Delete expired messages from every shard.
+  ///
+  /// Copies of the dialogs being moved are purged too, but only the serving ones are returned
+  pub async fn purge_expired(&self) -> DialogResult<Vec<DeletedMessageDto>> {
+    let purged = try_join_all(self.shards.all().iter().map(Self::purge_shard))
+      .await?
+      .into_iter()
+      .enumerate()
+      .flat_map(|(shard_index, purged)| {
+        purged.into_iter().filter(move |deleted| {
+          let key = DialogKey::new(deleted.message.user_a, deleted.message.user_b);
+          self.shards.shard_index(&key.shard_key()) == shard_index
+        })
+      })
+      .collect();
+
+    Ok(purged)
+  }
+
+  /// Non-zero unread counts of every dialog, used to rebuild the counters
+  pub async fn unread_counts(&self) -> DialogResult<Vec<UnreadCount>> {
+    let counts = try_join_all(self.shards.all().iter().map(Self::shard_unread_counts))
+      .await?
+      .into_iter()
+      .enumerate()
+      .flat_map(|(shard_index, counts)| {
+        counts.into_iter().filter(move |count| {
+          let key = DialogKey::new(count.user_id, count.peer_id);
+          self.shards.shard_index(&key.shard_key()) == shard_index
+        })
+      })
+      .collect();
+
+    Ok(counts)
+  }
+
+  async fn shard_unread_counts(shard: &PgPool) -> DialogResult<Vec<UnreadCount>> {
+    sqlx::query_as!(
+      UnreadCount,
+      r#"
+        SELECT d.user_id, d.peer_id, COUNT(*) AS "unread!"
+        FROM dialogs d
+        JOIN messages m
+          ON m.user_a = LEAST(d.user_id, d.peer_id)
+          AND m.user_b = GREATEST(d.user_id, d.peer_id)
+          AND m.id > d.last_read_message_id
+          AND m.sender_id = d.peer_id
+        GROUP BY d.user_id, d.peer_id
+      "#
+    )
+    .fetch_all(shard)
+    .await
+    .map_err(DialogError::FailedToFindMessages)
+  }
+
+  async fn list_shard_dialogs(shard: &PgPool, user_id: i32) -> DialogResult<Vec<DialogSummaryDto>> {
+    let rows = sqlx::query!(
+      r#"
+        SELECT
+          d.peer_id,
+          m.id,
+          m.user_a,
+          m.user_b,
+          m.sender_id,
+          m.text,
+          m.created_at,
+          m.edited_at,
+          m.expires_at,
+          m.envelope AS "envelope: Json<EnvelopeDto>",
+          d.message_ttl,
+          (
+            SELECT COUNT(*)
+            FROM messages u
+            WHERE u.user_a = m.user_a AND u.user_b = m.user_b
+              AND u.id > d.last_read_message_id
+              AND u.sender_id = d.peer_id
+          ) AS "unread_count!"
+        FROM dialogs d
+        JOIN messages m
+          ON m.user_a = LEAST(d.user_id, d.peer_id)
+          AND m.user_b = GREATEST(d.user_id, d.peer_id)
+          AND m.id = d.last_message_id
+        WHERE d.user_id = $1
+        ORDER BY d.updated_at DESC
+        LIMIT $2
+      "#,
+      user_id,
+      MAX_DIALOGS
+    )
+    .fetch_all(shard)
+    .await
+    .map_err(DialogError::FailedToFindMessages)?;
+
+    Ok(
+      rows
+        .into_iter()
+        .map(|row| DialogSummaryDto {
+          peer_id: row.peer_id,
+          last_message: MessageDto {
+            id: row.id,
+            user_a: row.user_a,
+            user_b: row.user_b,
+            sender_id: row.sender_id,
+            text: row.text,
+            created_at: row.created_at,
+            edited_at: row.edited_at,
+            expires_at: row.expires_at,
+            envelope: row.envelope,
+          },
+          unread_count: row.unread_count,
+          message_ttl: row.message_ttl,
+        })
+        .collect(),
+    )
+  }
+
+  /// Update both sides of the dialog, the sender has read everything up to their own message
+  async fn upsert_dialogs(
+    connection: &mut PgConnection,
+    message: &MessageDto,
+  ) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+      r#"
+        INSERT INTO dialogs (user_id, peer_id, last_message_id, last_read_message_id)
+        VALUES ($1, $2, $3, $3), ($2, $1, $3, 0)
+        ON CONFLICT (user_id, peer_id) DO UPDATE SET
+          last_message_id = GREATEST(dialogs.last_message_id, EXCLUDED.last_message_id),
+          last_read_message_id = GREATEST(dialogs.last_read_message_id, EXCLUDED.last_read_message_id),
+          updated_at = NOW()
+      "#,
+      message.sender_id,
+      message.recipient_id(),
+      message.id
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+  }
+
+  async fn edit_on_shard(
+    shard: &PgPool,
+    key: DialogKey,
+    message_id: i64,
+    text: &str,
+  ) -> Result<Option<MessageDto>, sqlx::Error> {
+    let mut tx = shard.begin().await?;
+
+    // The row lock orders concurrent edits, so versions are taken one after another
+    let locked = sqlx::query_scalar!(
+      r#"
+        SELECT id FROM messages
+        WHERE user_a = $1 AND user_b = $2 AND id = $3
+        FOR UPDATE
+      "#,
+      key.user_a,
+      key.user_b,
+      message_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if locked.is_none() {
+      return Ok(None);
+    }
+
+    sqlx::query!(
+      r#"
+        INSERT INTO message_edits (user_a, user_b, message_id, version, text)
+        SELECT $1, $2, $3, COALESCE((
+          SELECT MAX(version) FROM message_edits
+          WHERE user_a = $1 AND user_b = $2 AND message_id = $3
+        ), 0) + 1, text
+        FROM messages
+        WHERE user_a = $1 AND user_b = $2 AND id = $3
+      "#,
+      key.user_a,
+      key.user_b,
+      message_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let message = sqlx::query_as!(
+      MessageDto,
+      r#"
+        UPDATE messages
+        SET text = $4, edited_at = NOW()
+        WHERE user_a = $1 AND user_b = $2 AND id = $3
+        RETURNING
+          id, user_a, user_b, sender_id, text, created_at, edited_at, expires_at,
+          envelope AS "envelope: Json<EnvelopeDto>"
+      "#,
+      key.user_a,
+      key.user_b,
+      message_id,
+      text
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some(message))
+  }
+
+  async fn delete_on_shard(
+    shard: &PgPool,
+    key: DialogKey,
+    message_id: i64,
+  ) -> Result<Option<DeletedMessageDto>, sqlx::Error> {
+    let mut tx = shard.begin().await?;
+
+    let message = sqlx::query_as!(
+      MessageDto,
+      r#"
+        DELETE FROM messages
+        WHERE user_a = $1 AND user_b = $2 AND id = $3
+        RETURNING
+          id, user_a, user_b, sender_id, text, created_at, edited_at, expires_at,
+          envelope AS "envelope: Json<EnvelopeDto>"
+      "#,
+      key.user_a,
+      key.user_b,
+      message_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(message) = message else {
+      return Ok(None);
+    };
+
+    let last_read_message_id = sqlx::query_scalar!(
+      r#"SELECT last_read_message_id FROM dialogs WHERE user_id = $1 AND peer_id = $2"#,
+      message.recipient_id(),
+      message.sender_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .unwrap_or_default();
+
+    Self::refresh_last_messages(&mut tx, &[key]).await?;
+    tx.commit().await?;
+
+    Ok(Some(DeletedMessageDto {
+      unread: message.id > last_read_message_id,
+      message,
+    }))
+  }
+
+  async fn purge_shard(shard: &PgPool) -> DialogResult<Vec<DeletedMessageDto>> {
+    let mut purged = Vec::new();
+
+    loop {
+      let mut tx = shard
+        .begin()
+        .await
+        .map_err(DialogError::FailedToUpdateMessage)?;
+
+      let rows = sqlx::query!(
+        r#"
+          WITH expired AS (
+            DELETE FROM messages
+            WHERE (user_a, user_b, id) IN (
+              SELECT user_a, user_b, id FROM messages
+              WHERE expires_at <= NOW()
+              LIMIT $1
+            )
+            RETURNING
+              id, user_a, user_b, sender_id, text, created_at, edited_at, expires_at, envelope
+          )
+          SELECT
+            e.id AS "id!",
+            e.user_a AS "user_a!",
+            e.user_b AS "user_b!",
+            e.sender_id AS "sender_id!",
+            e.text AS "text!",
+            e.created_at AS "created_at!",
+            e.edited_at AS "edited_at?",
+            e.expires_at AS "expires_at?",
+            e.envelope AS "envelope?: Json<EnvelopeDto>",
+            e.id > COALESCE(d.last_read_message_id, 0) AS "unread!"
+          FROM expired e
+          LEFT JOIN dialogs d
+            ON d.user_id = CASE WHEN e.sender_id = e.user_a THEN e.user_b ELSE e.user_a END
+            AND d.peer_id = e.sender_id
+        "#,
+        PURGE_BATCH
+      )
+      .fetch_all(&mut *tx)
+      .await
+      .map_err(DialogError::FailedToUpdateMessage)?;
+
+      let mut keys = rows
+        .iter()
+        .map(|row| DialogKey::new(row.user_a, row.user_b))
+        .collect::<Vec<_>>();
+      keys.sort_unstable_by_key(|key| (key.user_a, key.user_b));
+      keys.dedup();
+      Self::refresh_last_messages(&mut tx, &keys)
+        .await
+        .map_err(DialogError::FailedToUpdateMessage)?;
+
+      tx.commit()
+        .await
+        .map_err(DialogError::FailedToUpdateMessage)?;
+
+      let done = (rows.len() as i64) < PURGE_BATCH;
+      purged.extend(rows.into_iter().map(|row| DeletedMessageDto {
+        message: MessageDto {
+          id: row.id,
+          user_a: row.user_a,
+          user_b: row.user_b,
+          sender_id: row.sender_id,
+          text: row.text,
+          created_at: row.created_at,
+          edited_at: row.edited_at,
+          expires_at: row.expires_at,
+          envelope: row.envelope,
+        },
+        unread: row.unread,
+      }));
+      if done {
+        return Ok(purged);
+      }
+    }
+  }
+
+  /// Point the dialogs at their newest remaining message after deletions
+  async fn refresh_last_messages(
+    connection: &mut PgConnection,
+    keys: &[DialogKey],
+  ) -> Result<(), sqlx::Error> {
+    if keys.is_empty() {
+      return Ok(());
+    }
+
+    sqlx::query!(
+      r#"
+        UPDATE dialogs d
+        SET last_message_id = COALESCE((
+          SELECT MAX(m.id) FROM messages m
+          WHERE m.user_a = p.user_a AND m.user_b = p.user_b
+        ), 0)
+        FROM UNNEST($1::INTEGER[], $2::INTEGER[]) AS p(user_a, user_b)
+        WHERE (d.user_id = p.user_a AND d.peer_id = p.user_b)
+          OR (d.user_id = p.user_b AND d.peer_id = p.user_a)
+      "#,
+      &keys.iter().map(|key| key.user_a).collect::<Vec<_>>(),
+      &keys.iter().map(|key| key.user_b).collect::<Vec<_>>()
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(())
+  }
+
+  /// Copy the message to the shard the dialog is being moved to, keeping its id
+  async fn mirror_message(mirror: &PgPool, message: &MessageDto) -> Result<(), sqlx::Error> {
+    let mut tx = mirror.begin().await?;
+
+    sqlx::query!(
+      r#"
+        INSERT INTO messages (
+          id, user_a, user_b, sender_id, text, created_at, edited_at, expires_at, envelope
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (user_a, user_b, id) DO NOTHING
+      "#,
+      message.id,
+      message.user_a,
+      message.user_b,
+      message.sender_id,
+      message.text,
+      message.created_at,
+      message.edited_at,
+      message.expires_at,
+      message.envelope.as_ref().map(|envelope| Json(&envelope.0)) as _
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Self::upsert_dialogs(&mut tx, message).await?;
+
+    tx.commit().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::testing::create_database;
+
+  async fn count_messages(shard: &PgPool, key: DialogKey) -> i64 {
+    sqlx::query_scalar!(
+      r#"SELECT COUNT(*) AS "count!" FROM messages WHERE user_a = $1 AND user_b = $2"#,
+      key.user_a,
+      key.user_b
+    )
+    .fetch_one(shard)
+    .await
+    .unwrap()
+  }
+
+  #[tokio::test]
+  #[ignore = "requires postgres from docker-compose.yml"]
+  async fn messages_are_routed_to_dialog_shard() {
+    let shard_0 = create_database("sn_test_dialog_shard_0").await;
+    let shard_1 = create_database("sn_test_dialog_shard_1").await;
+
+    let shards = ShardMap::new(vec![shard_0.clone(), shard_1.clone()]);
+    let dialog_store = PgDialogStore::new(shards.clone());
+
+    let mut used_shards = [false; 2];
+    for peer_id in 2..=20 {
+      dialog_store
+        .insert_message(1, peer_id, format!("Hello, {}", peer_id), None)
+        .await
+        .unwrap();
+
+      let key = DialogKey::new(1, peer_id);
+      let shard_index = shards.shard_index(&key.shard_key());
+      used_shards[shard_index] = true;
+
+      assert_eq!(count_messages(&shards.all()[shard_index], key).await, 1);
+      assert_eq!(count_messages(&shards.all()[1 - shard_index], key).await, 0);
+
+      let page = dialog_store.list_messages(key, None, 10).await.unwrap();
+      assert_eq!(page.messages.len(), 1);
+      assert_eq!(page.messages[0].text, format!("Hello, {}", peer_id));
+    }
+    assert_eq!(used_shards, [true, true]);
+
+    let dialogs = dialog_store.list_dialogs(1).await.unwrap();
+    assert_eq!(dialogs.len(), 19);
+    assert!(dialogs.iter().all(|dialog| dialog.unread_count == 0));
+
+    let mut counts = dialog_store.unread_counts().await.unwrap();
+    counts.sort_by_key(|count| count.user_id);
+    assert_eq!(counts.len(), 19);
+    assert!(counts.iter().zip(2..=20).all(|(count, user_id)| *count
+      == UnreadCount {
+        user_id,
+        peer_id: 1,
+        unread: 1
+      }));
+
+    let key = DialogKey::new(1, 2);
+    let newest = dialog_store.list_messages(key, None, 1).await.unwrap();
+    let unread = dialog_store
+      .mark_read(2, 1, newest.messages[0].id)
+      .await
+      .unwrap();
+    assert_eq!(unread, 0);
+    assert_eq!(dialog_store.unread_counts().await.unwrap().len(), 18);
+  }
+
+  #[tokio::test]
+  #[ignore = "requires postgres from docker-compose.yml"]
+  async fn messages_are_edited_deleted_and_purged() {
+    let shard = create_database("sn_test_dialog_lifecycle").await;
+    let dialog_store = PgDialogStore::new(ShardMap::new(vec![shard]));
+    let key = DialogKey::new(1, 2);
+
+    let edited = dialog_store
+      .insert_message(1, 2, "helo".to_string(), None)
+      .await
+      .unwrap();
+    let deleted = dialog_store
+      .insert_message(1, 2, "oops".to_string(), None)
+      .await
+      .unwrap();
+    let message = dialog_store
+      .edit_message(key, edited.id, "hello".to_string())
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(message.text, "hello");
+    assert!(message.edited_at.is_some());
+
+    let edits = dialog_store.message_edits(key, edited.id).await.unwrap();
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].text, "helo");
+
+    let removed = dialog_store
+      .delete_message(key, deleted.id)
+      .await
+      .unwrap()
+      .unwrap();
+    assert!(removed.unread);
+    let dialogs = dialog_store.list_dialogs(2).await.unwrap();
+    assert_eq!(dialogs[0].last_message.id, edited.id);
+
+    dialog_store.set_message_ttl(key, Some(0)).await.unwrap();
+    let expiring = dialog_store
+      .insert_message(1, 2, "gone".to_string(), None)
+      .await
+      .unwrap();
+    assert!(expiring.expires_at.is_some());
+    assert!(dialog_store
+      .get_message(key, expiring.id)
+      .await
+      .unwrap()
+      .is_none());
+
+    let purged = dialog_store.purge_expired().await.unwrap();
+    assert_eq!(purged.len(), 1);
+    assert!(purged[0].unread);
+    assert_eq!(
+      dialog_store.unread_counts().await.unwrap(),
+      vec![UnreadCount {
+        user_id: 2,
+        peer_id: 1,
+        unread: 1
+      }]
+    );
+  }
+}
@@ -0,0 +1,95 @@
+use std::{future::Future, sync::Arc, time::Instant};
+
+use redis::{
+  aio::{ConnectionLike, ConnectionManager},
+  Arg, Cmd, ErrorKind, Pipeline, RedisError, RedisFuture, RedisResult, Value,
+};
+
+use super::circuit_breaker::CircuitBreaker;
+
+/// Redis connection recording the latency of every command it sends, commands are rejected
+/// right away while the circuit breaker is open
+#[derive(Clone)]
+pub struct MeteredConnection {
+  inner: ConnectionManager,
+  breaker: Arc<CircuitBreaker>,
+}
+
+impl MeteredConnection {
+  pub fn new(inner: ConnectionManager, breaker: Arc<CircuitBreaker>) -> Self {
+    Self { inner, breaker }
+  }
+
+  async fn guarded<T>(
+    breaker: &CircuitBreaker,
+    command: String,
+    request: impl Future<Output = RedisResult<T>>,
+  ) -> RedisResult<T> {
+    if !breaker.allow() {
+      return Err(RedisError::from((
+        ErrorKind::IoError,
+        "Redis circuit breaker is open",
+      )));
+    }
+
+    let started = Instant::now();
+    let result = request.await;
+    record(command, started);
+    match &result {
+      Err(e) if is_unavailable(e) => breaker.record_failure(),
+      _ => breaker.record_success(),
+    }
+
+    result
+  }
+}
+
+impl std::fmt::Debug for MeteredConnection {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("MeteredConnection").finish_non_exhaustive()
+  }
+}
+
+fn command_name(cmd: &Cmd) -> String {
+  match cmd.args_iter().next() {
+    Some(Arg::Simple(name)) => String::from_utf8_lossy(name).to_uppercase(),
+    _ => "UNKNOWN".to_string(),
+  }
+}
+
+/// Errors meaning Redis is unreachable, unlike replies of failed commands
+fn is_unavailable(e: &RedisError) -> bool {
+  e.is_io_error() || e.is_timeout() || e.is_connection_dropped() || e.is_connection_refusal()
+}
+
+fn record(command: String, started: Instant) {
+  metrics::histogram!("redis_command_duration_seconds", "command" => command)
+    .record(started.elapsed().as_secs_f64());
+}
+
+impl ConnectionLike for MeteredConnection {
+  fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+    Box::pin(Self::guarded(
+      &self.breaker,
+      command_name(cmd),
+      self.inner.req_packed_command(cmd),
+    ))
+  }
+
+  fn req_packed_commands<'a>(
+    &'a mut self,
+    cmd: &'a Pipeline,
+    offset: usize,
+    count: usize,
+  ) -> RedisFuture<'a, Vec<Value>> {
+    Box::pin(Self::guarded(
+      &self.breaker,
+      "PIPELINE".to_string(),
+      self.inner.req_packed_commands(cmd, offset, count),
+    ))
+  }
+
+  fn get_db(&self) -> i64 {
+    self.inner.get_db()
+  }
+}
@@ -0,0 +1,148 @@
+use sqlx::{
+  migrate::{Migrate, Migrator},
+  Connection, PgConnection,
+};
+use tracing::info;
+
+use crate::errors::common::DatabaseResult;
+
+/// Key of the session advisory lock held by the instance migrating a database
+const MIGRATION_LOCK_KEY: i64 = 0x736e_6d69_6772_6174;
+
+/// Migration of the embedded `migrations/` directory and whether the database has it
+#[derive(Debug, Clone)]
+pub struct MigrationStatusDto {
+  pub version: i64,
+  pub description: String,
+  pub applied: bool,
+  /// Applied migration whose file was changed afterwards
+  pub checksum_mismatch: bool,
+}
+
+fn migrator() -> Migrator {
+  let mut migrator = sqlx::migrate!();
+  // The advisory lock of `locked` already covers the whole run
+  migrator.locking = false;
+  migrator
+}
+
+/// Connect to the database with the migration lock held, concurrent instances wait for each other.
+///
+/// The lock is released when the connection is closed
+async fn locked(url: &str) -> DatabaseResult<PgConnection> {
+  let mut connection = PgConnection::connect(url).await?;
+  sqlx::query("SELECT pg_advisory_lock($1)")
+    .bind(MIGRATION_LOCK_KEY)
+    .execute(&mut connection)
+    .await?;
+
+  Ok(connection)
+}
+
+/// Apply pending migrations to every database
+pub async fn up(urls: &[String]) -> DatabaseResult<()> {
+  let migrator = migrator();
+  for url in urls {
+    let mut connection = locked(url).await?;
+    migrator.run(&mut connection).await?;
+    connection.close().await?;
+  }
+  info!("Applied migrations to {} databases", urls.len());
+
+  Ok(())
+}
+
+/// Revert the last `steps` applied migrations of every database
+pub async fn down(urls: &[String], steps: usize) -> DatabaseResult<()> {
+  let migrator = migrator();
+  for url in urls {
+    let mut connection = locked(url).await?;
+    connection.ensure_migrations_table().await?;
+
+    let mut applied = connection
+      .list_applied_migrations()
+      .await?
+      .into_iter()
+      .map(|migration| migration.version)
+      .collect::<Vec<_>>();
+    applied.sort_unstable();
+    let target = applied
+      .len()
+      .checked_sub(steps + 1)
+      .map_or(0, |index| applied[index]);
+
+    migrator.undo(&mut connection, target).await?;
+    connection.close().await?;
+  }
+  info!("Reverted {} migrations of {} databases", steps, urls.len());
+
+  Ok(())
+}
+
+/// Embedded migrations with their state in the database
+pub async fn status(url: &str) -> DatabaseResult<Vec<MigrationStatusDto>> {
+  let mut connection = locked(url).await?;
+  connection.ensure_migrations_table().await?;
+  let applied = connection.list_applied_migrations().await?;
+  connection.close().await?;
+
+  Ok(
+    migrator()
+      .iter()
+      .filter(|migration| migration.migration_type.is_up_migration())
+      .map(|migration| {
+        let applied = applied
+          .iter()
+          .find(|applied| applied.version == migration.version);
+
+        MigrationStatusDto {
+          version: migration.version,
+          description: migration.description.to_string(),
+          applied: applied.is_some(),
+          checksum_mismatch: applied.is_some_and(|applied| applied.checksum != migration.checksum),
+        }
+      })
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use sqlx::ConnectOptions;
+
+  use super::*;
+  use crate::db::testing::create_empty_database;
+
+  #[tokio::test]
+  #[ignore = "requires postgres from docker-compose.yml"]
+  async fn migrations_are_applied_and_reverted() {
+    let url = create_empty_database("sn_test_migrations")
+      .await
+      .to_url_lossy()
+      .to_string();
+    let urls = [url.clone()];
+
+    // Concurrent runs wait for the lock instead of failing on each other's tables
+    let (first, second) = tokio::join!(up(&urls), up(&urls));
+    first.unwrap();
+    second.unwrap();
+    let migrations = status(&url).await.unwrap();
+    assert!(migrations.iter().all(|migration| migration.applied));
+
+    down(&urls, 2).await.unwrap();
+    let migrations = status(&url).await.unwrap();
+    let pending = migrations
+      .iter()
+      .filter(|migration| !migration.applied)
+      .map(|migration| migration.version)
+      .collect::<Vec<_>>();
+    let last = migrations
+      .iter()
+      .rev()
+      .take(2)
+      .map(|migration| migration.version)
+      .collect::<Vec<_>>();
+    assert_eq!(pending.len(), 2);
+    assert!(pending.iter().all(|version| last.contains(version)));
+  }
+}
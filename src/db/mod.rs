@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use crate::{config::AppConfig, errors::common::DatabaseResult};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+
+pub mod circuit_breaker;
+pub mod dialogs;
+pub mod metered;
+pub mod migrations;
+pub mod redis_client;
+pub mod replicas;
+pub mod reshard;
+pub mod shards;
+#[cfg(test)]
+pub mod testing;
+
+pub use redis_client::{RedisClient, RedisOptions};
+use replicas::PgCluster;
+use shards::ShardMap;
+
+#[derive(Debug, Clone)]
+pub struct DataSource {
+  pub pg: PgCluster,
+  pub redis: RedisClient,
+  pub dialog_shards: ShardMap,
+}
+
+impl DataSource {
+  pub async fn init(app_config: &AppConfig) -> DatabaseResult<Self> {
+    let options = pool_options(app_config);
+    let pg = PgCluster::connect(
+      &app_config.database_url,
+      &app_config.database_replica_urls,
+      options.clone(),
+    )
+    .await?;
+    let dialog_shards =
+      ShardMap::connect(&app_config.dialog_shard_urls, &pg.write(), options).await?;
+    dialog_shards.refresh_topology(&pg.write()).await?;
+
+    let redis = if app_config.redis_sentinel_urls.is_empty() {
+      RedisClient::connect(&app_config.redis_url, app_config.into()).await?
+    } else {
+      RedisClient::connect_sentinel(
+        &app_config.redis_sentinel_urls,
+        &app_config.redis_sentinel_master,
+        &app_config.redis_url,
+        Duration::from_secs(app_config.redis_sentinel_check_interval),
+        app_config.into(),
+      )
+      .await?
+    };
+
+    Ok(Self {
+      pg,
+      redis,
+      dialog_shards,
+    })
+  }
+
+  /// Pool of the primary database
+  pub fn write(&self) -> PgPool {
+    self.pg.write()
+  }
+
+  /// Pool of a healthy read replica, replication lag makes recent writes possibly missing there
+  pub async fn read(&self) -> PgPool {
+    self.pg.read().await
+  }
+}
+
+/// Options shared by the Postgres pools, queries fail instead of waiting for a connection forever
+fn pool_options(app_config: &AppConfig) -> PgPoolOptions {
+  PgPoolOptions::new()
+    .max_connections(app_config.database_max_connections)
+    .acquire_timeout(Duration::from_millis(app_config.database_acquire_timeout))
+}
@@ -0,0 +1,235 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use arc_swap::ArcSwap;
+use redis::{
+  aio::{ConnectionManager, ConnectionManagerConfig},
+  sentinel::{Sentinel, SentinelNodeConnectionInfo},
+  IntoConnectionInfo, RedisResult,
+};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use super::{
+  circuit_breaker::{CircuitBreaker, CircuitState},
+  metered::MeteredConnection,
+};
+use crate::config::AppConfig;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RedisOptions {
+  /// Commands without a reply in time fail and count towards opening the circuit breaker
+  pub response_timeout: Duration,
+  /// Consecutive failures opening the circuit breaker
+  pub failure_threshold: u32,
+  /// Time the circuit breaker stays open before probing Redis again
+  pub reset_timeout: Duration,
+}
+
+impl Default for RedisOptions {
+  fn default() -> Self {
+    Self {
+      response_timeout: Duration::from_millis(500),
+      failure_threshold: 5,
+      reset_timeout: Duration::from_secs(5),
+    }
+  }
+}
+
+impl From<&AppConfig> for RedisOptions {
+  fn from(config: &AppConfig) -> Self {
+    Self {
+      response_timeout: Duration::from_millis(config.redis_response_timeout),
+      failure_threshold: config.redis_breaker_failure_threshold,
+      reset_timeout: Duration::from_millis(config.redis_breaker_reset_timeout),
+    }
+  }
+}
+
+struct Master {
+  client: redis::Client,
+  connection: ConnectionManager,
+}
+
+impl Master {
+  async fn connect(client: redis::Client, options: &RedisOptions) -> RedisResult<Self> {
+    let config = ConnectionManagerConfig::new()
+      .set_connection_timeout(options.response_timeout)
+      .set_response_timeout(options.response_timeout);
+    let connection = ConnectionManager::new_with_config(client.clone(), config).await?;
+
+    Ok(Self { client, connection })
+  }
+
+  fn address(&self) -> String {
+    self.client.get_connection_info().addr.to_string()
+  }
+}
+
+/// Master discovery through Redis Sentinel
+struct SentinelDiscovery {
+  sentinel: Sentinel,
+  master_name: String,
+  /// Database and credentials of the master, taken from `redis_url`
+  node: SentinelNodeConnectionInfo,
+}
+
+impl SentinelDiscovery {
+  async fn master(&mut self) -> RedisResult<redis::Client> {
+    self
+      .sentinel
+      .async_master_for(&self.master_name, Some(&self.node))
+      .await
+  }
+}
+
+/// Redis connection shared by the services without locking, clones multiplex their commands over
+/// one connection that reconnects by itself.
+///
+/// With Sentinel the connection is switched to the new master after a failover
+#[derive(Clone)]
+pub struct RedisClient {
+  master: Arc<ArcSwap<Master>>,
+  breaker: Arc<CircuitBreaker>,
+}
+
+impl fmt::Debug for RedisClient {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("RedisClient")
+      .field("master", &self.master.load().address())
+      .field("breaker", &self.breaker.state())
+      .finish()
+  }
+}
+
+impl RedisClient {
+  pub async fn connect(url: &str, options: RedisOptions) -> RedisResult<Self> {
+    let master = Master::connect(redis::Client::open(url)?, &options).await?;
+
+    Ok(Self::new(master, &options))
+  }
+
+  fn new(master: Master, options: &RedisOptions) -> Self {
+    Self {
+      master: Arc::new(ArcSwap::from_pointee(master)),
+      breaker: Arc::new(CircuitBreaker::new(
+        "redis",
+        options.failure_threshold,
+        options.reset_timeout,
+      )),
+    }
+  }
+
+  /// Connect to the master monitored by the Sentinels under the name, `url` gives its database and
+  /// credentials. The master is looked up again every `interval` to follow failovers
+  pub async fn connect_sentinel(
+    sentinel_urls: &[String],
+    master_name: &str,
+    url: &str,
+    interval: Duration,
+    options: RedisOptions,
+  ) -> RedisResult<Self> {
+    let mut discovery = SentinelDiscovery {
+      sentinel: Sentinel::build(sentinel_urls.to_vec())?,
+      master_name: master_name.to_string(),
+      node: SentinelNodeConnectionInfo {
+        tls_mode: None,
+        redis_connection_info: Some(url.into_connection_info()?.redis),
+      },
+    };
+    let master = Master::connect(discovery.master().await?, &options).await?;
+    info!("Connected to Redis master {}", master.address());
+
+    let client = Self::new(master, &options);
+    client.spawn_master_discovery(discovery, interval, options);
+
+    Ok(client)
+  }
+
+  /// Connection to the current master, cheap to take for every command
+  pub fn connection(&self) -> MeteredConnection {
+    MeteredConnection::new(self.master.load().connection.clone(), self.breaker.clone())
+  }
+
+  pub fn circuit_state(&self) -> CircuitState {
+    self.breaker.state()
+  }
+
+  /// Client of the current master for dedicated connections like Pub/Sub
+  pub fn client(&self) -> redis::Client {
+    self.master.load().client.clone()
+  }
+
+  fn spawn_master_discovery(
+    &self,
+    mut discovery: SentinelDiscovery,
+    interval: Duration,
+    options: RedisOptions,
+  ) -> JoinHandle<()> {
+    let master = self.master.clone();
+
+    tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(interval);
+      loop {
+        ticker.tick().await;
+
+        let client = match discovery.master().await {
+          Ok(client) => client,
+          Err(e) => {
+            error!("Failed to discover Redis master through Sentinel: {}", e);
+            continue;
+          }
+        };
+        let address = client.get_connection_info().addr.to_string();
+        if address == master.load().address() {
+          continue;
+        }
+
+        match Master::connect(client, &options).await {
+          Ok(new_master) => {
+            info!("Redis master moved to {}, switching connections", address);
+            master.store(Arc::new(new_master));
+          }
+          Err(e) => error!("Failed to connect to new Redis master {}: {}", address, e),
+        }
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use redis::AsyncCommands;
+
+  use super::*;
+
+  #[tokio::test]
+  #[ignore = "requires redis from docker-compose.yml"]
+  async fn clones_share_the_connection() {
+    let client = RedisClient::connect(
+      &std::env::var("REDIS_URL").unwrap(),
+      RedisOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    let writes = (0..100).map(|index| {
+      let client = client.clone();
+      async move {
+        client
+          .connection()
+          .set_ex::<_, _, ()>(format!("sn_test_redis_client:{}", index), index, 60)
+          .await
+      }
+    });
+    for result in futures::future::join_all(writes).await {
+      result.unwrap();
+    }
+
+    let value: i32 = client
+      .connection()
+      .get("sn_test_redis_client:42")
+      .await
+      .unwrap();
+    assert_eq!(value, 42);
+  }
+}
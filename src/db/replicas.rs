@@ -0,0 +1,368 @@
+use std::{
+  fmt,
+  future::Future,
+  str::FromStr,
+  sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::errors::common::DatabaseResult;
+
+/// Time a lagging replica gets to report that it caught up with the client before reads go to
+/// the primary
+const CATCH_UP_CHECK_TIMEOUT: Duration = Duration::from_millis(100);
+
+tokio::task_local! {
+  static READ_CONSISTENCY: Arc<ReadConsistency>;
+}
+
+/// Position in the write-ahead log, formatted by Postgres as two hex halves like `16/B374D848`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Lsn(u64);
+
+impl FromStr for Lsn {
+  type Err = ();
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let (high, low) = value.split_once('/').ok_or(())?;
+    let high = u32::from_str_radix(high, 16).map_err(|_| ())?;
+    let low = u32::from_str_radix(low, 16).map_err(|_| ())?;
+
+    Ok(Self((u64::from(high) << 32) | u64::from(low)))
+  }
+}
+
+impl fmt::Display for Lsn {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:X}/{:X}", self.0 >> 32, self.0 & u64::from(u32::MAX))
+  }
+}
+
+/// Consistency requirements of the reads made while handling one request
+#[derive(Debug, Default)]
+pub struct ReadConsistency {
+  /// Replicas must have replayed the log up to this position to serve the reads
+  min_lsn: Option<Lsn>,
+  /// Set once the primary pool is taken, its log position is then reported to the client
+  used_primary: AtomicBool,
+}
+
+impl ReadConsistency {
+  pub fn new(min_lsn: Option<Lsn>) -> Self {
+    Self {
+      min_lsn,
+      used_primary: AtomicBool::new(false),
+    }
+  }
+
+  pub fn used_primary(&self) -> bool {
+    self.used_primary.load(Ordering::Relaxed)
+  }
+
+  /// Run the future with the requirements applied to every `PgCluster::read` it makes
+  pub async fn scope<F: Future>(self: Arc<Self>, f: F) -> F::Output {
+    READ_CONSISTENCY.scope(self, f).await
+  }
+}
+
+#[derive(Debug)]
+struct Replica {
+  pool: PgPool,
+  healthy: AtomicBool,
+  /// Log position replayed by the replica as of the last check
+  replayed_lsn: AtomicU64,
+}
+
+impl Replica {
+  /// Query and record the log position replayed by the replica
+  async fn refresh_replayed_lsn(&self) -> Result<u64, sqlx::Error> {
+    // NULL when the url points to a primary, it then has every write
+    let lsn = sqlx::query_scalar::<_, Option<String>>("SELECT pg_last_wal_replay_lsn()::TEXT")
+      .fetch_one(&self.pool)
+      .await?
+      .map_or(Some(Lsn(u64::MAX)), |lsn| lsn.parse().ok())
+      .map_or(0, |lsn| lsn.0);
+    self.replayed_lsn.store(lsn, Ordering::Relaxed);
+
+    Ok(lsn)
+  }
+}
+
+/// Primary Postgres pool with the read replicas taking the load of read-only queries
+#[derive(Debug, Clone)]
+pub struct PgCluster {
+  primary: PgPool,
+  replicas: Arc<Vec<Replica>>,
+  next_replica: Arc<AtomicUsize>,
+}
+
+impl PgCluster {
+  pub fn new(primary: PgPool, replicas: Vec<PgPool>) -> Self {
+    let replicas = replicas
+      .into_iter()
+      .map(|pool| Replica {
+        pool,
+        healthy: AtomicBool::new(true),
+        replayed_lsn: AtomicU64::new(u64::MAX),
+      })
+      .collect();
+
+    Self {
+      primary,
+      replicas: Arc::new(replicas),
+      next_replica: Arc::new(AtomicUsize::new(0)),
+    }
+  }
+
+  /// Connect to the primary, replicas connect lazily so an unavailable one does not block startup
+  pub async fn connect(
+    primary_url: &str,
+    replica_urls: &[String],
+    options: PgPoolOptions,
+  ) -> DatabaseResult<Self> {
+    let primary = options.clone().connect(primary_url).await?;
+    let replicas = replica_urls
+      .iter()
+      .map(|url| options.clone().connect_lazy(url))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let cluster = Self::new(primary, replicas);
+    cluster.check_replicas(Duration::from_secs(1)).await;
+
+    Ok(cluster)
+  }
+
+  /// Pool of the primary, used for writes and reads that must see them
+  pub fn write(&self) -> PgPool {
+    let _ = READ_CONSISTENCY.try_with(|consistency| {
+      consistency.used_primary.store(true, Ordering::Relaxed);
+    });
+
+    self.primary.clone()
+  }
+
+  /// Next healthy replica in round-robin order that has caught up with the writes of the client,
+  /// the primary when there is no such replica.
+  ///
+  /// Replicas usually replay a write within milliseconds while their recorded position is only
+  /// refreshed by the health checks, so the first lagging replica is asked again before falling
+  /// back to the primary
+  pub async fn read(&self) -> PgPool {
+    let count = self.replicas.len();
+    if count == 0 {
+      return self.primary.clone();
+    }
+
+    let min_lsn = READ_CONSISTENCY
+      .try_with(|consistency| consistency.min_lsn)
+      .ok()
+      .flatten()
+      .map_or(0, |lsn| lsn.0);
+
+    let start = self.next_replica.fetch_add(1, Ordering::Relaxed);
+    let mut lagging = None;
+    for offset in 0..count {
+      let replica = &self.replicas[(start + offset) % count];
+      if !replica.healthy.load(Ordering::Relaxed) {
+        continue;
+      }
+      if replica.replayed_lsn.load(Ordering::Relaxed) >= min_lsn {
+        return replica.pool.clone();
+      }
+      lagging.get_or_insert(replica);
+    }
+
+    if let Some(replica) = lagging {
+      let refresh = tokio::time::timeout(CATCH_UP_CHECK_TIMEOUT, replica.refresh_replayed_lsn());
+      if matches!(refresh.await, Ok(Ok(lsn)) if lsn >= min_lsn) {
+        return replica.pool.clone();
+      }
+    }
+
+    self.primary.clone()
+  }
+
+  /// Every pool of the cluster named for metrics
+  pub fn pools(&self) -> Vec<(String, PgPool)> {
+    let replicas = self
+      .replicas
+      .iter()
+      .enumerate()
+      .map(|(index, replica)| (format!("replica_{}", index), replica.pool.clone()));
+
+    std::iter::once(("primary".to_string(), self.primary.clone()))
+      .chain(replicas)
+      .collect()
+  }
+
+  /// Current end of the primary write-ahead log, every committed write is before it
+  pub async fn current_lsn(&self) -> DatabaseResult<Lsn> {
+    let lsn = sqlx::query_scalar::<_, String>("SELECT pg_current_wal_lsn()::TEXT")
+      .fetch_one(&self.primary)
+      .await?;
+
+    Ok(lsn.parse().unwrap_or(Lsn(0)))
+  }
+
+  /// Ping every replica and record its replay position, failing ones leave the rotation
+  /// until they answer again
+  pub async fn check_replicas(&self, timeout: Duration) {
+    for (index, replica) in self.replicas.iter().enumerate() {
+      let healthy = match tokio::time::timeout(timeout, replica.refresh_replayed_lsn()).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+          warn!("Read replica {} failed health check: {}", index, e);
+          false
+        }
+        Err(_) => {
+          warn!("Read replica {} health check timed out", index);
+          false
+        }
+      };
+
+      let was_healthy = replica.healthy.swap(healthy, Ordering::Relaxed);
+      if healthy && !was_healthy {
+        info!("Read replica {} recovered, returning it to rotation", index);
+      }
+    }
+  }
+
+  pub fn spawn_health_checks(&self, interval: Duration) -> JoinHandle<()> {
+    let cluster = self.clone();
+
+    tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(interval);
+      loop {
+        ticker.tick().await;
+        cluster.check_replicas(interval).await;
+      }
+    })
+  }
+
+  #[cfg(test)]
+  fn set_healthy(&self, index: usize, healthy: bool) {
+    self.replicas[index]
+      .healthy
+      .store(healthy, Ordering::Relaxed);
+  }
+
+  #[cfg(test)]
+  fn set_replayed_lsn(&self, index: usize, lsn: Lsn) {
+    self.replicas[index]
+      .replayed_lsn
+      .store(lsn.0, Ordering::Relaxed);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::testing::create_empty_database;
+
+  fn lazy_pool(database: &str) -> PgPool {
+    PgPoolOptions::new()
+      .connect_lazy(&format!("postgres://localhost/{}", database))
+      .unwrap()
+  }
+
+  fn database(pool: &PgPool) -> String {
+    pool
+      .connect_options()
+      .get_database()
+      .unwrap_or_default()
+      .to_string()
+  }
+
+  #[tokio::test]
+  async fn reads_rotate_over_healthy_replicas() {
+    let cluster = PgCluster::new(
+      lazy_pool("primary"),
+      vec![lazy_pool("replica_a"), lazy_pool("replica_b")],
+    );
+
+    let mut reads = Vec::new();
+    for _ in 0..4 {
+      reads.push(database(&cluster.read().await));
+    }
+    assert_eq!(reads, ["replica_a", "replica_b", "replica_a", "replica_b"]);
+    assert_eq!(database(&cluster.write()), "primary");
+
+    cluster.set_healthy(0, false);
+    assert_eq!(database(&cluster.read().await), "replica_b");
+    assert_eq!(database(&cluster.read().await), "replica_b");
+
+    cluster.set_healthy(1, false);
+    assert_eq!(database(&cluster.read().await), "primary");
+
+    cluster.set_healthy(0, true);
+    assert_eq!(database(&cluster.read().await), "replica_a");
+  }
+
+  #[tokio::test]
+  async fn reads_skip_replicas_behind_the_client() {
+    let cluster = PgCluster::new(
+      lazy_pool("primary"),
+      vec![lazy_pool("replica_a"), lazy_pool("replica_b")],
+    );
+    cluster.set_replayed_lsn(0, "16/B374D848".parse().unwrap());
+    cluster.set_replayed_lsn(1, "16/B374D000".parse().unwrap());
+
+    let consistency = Arc::new(ReadConsistency::new("16/B374D848".parse().ok()));
+    let reads = consistency
+      .clone()
+      .scope(async {
+        [
+          database(&cluster.read().await),
+          database(&cluster.read().await),
+        ]
+      })
+      .await;
+    assert_eq!(reads, ["replica_a", "replica_a"]);
+    assert!(!consistency.used_primary());
+
+    let consistency = Arc::new(ReadConsistency::new("17/0".parse().ok()));
+    let read = consistency
+      .clone()
+      .scope(async { database(&cluster.read().await) })
+      .await;
+    assert_eq!(read, "primary");
+
+    consistency.clone().scope(async { cluster.write() }).await;
+    assert!(consistency.used_primary());
+  }
+
+  #[tokio::test]
+  #[ignore = "requires postgres from docker-compose.yml"]
+  async fn lagging_replicas_are_checked_again_before_the_primary() {
+    let options = create_empty_database("sn_test_replica_catch_up").await;
+    let replica = PgPoolOptions::new().connect_lazy_with(options);
+    let cluster = PgCluster::new(lazy_pool("primary"), vec![replica]);
+    // Behind the client as of the last health check, the database then reports having every
+    // write as it is not in recovery
+    cluster.set_replayed_lsn(0, Lsn(0));
+
+    let consistency = Arc::new(ReadConsistency::new("16/B374D848".parse().ok()));
+    let read = consistency
+      .clone()
+      .scope(async { database(&cluster.read().await) })
+      .await;
+    assert_eq!(read, "sn_test_replica_catch_up");
+    assert!(!consistency.used_primary());
+  }
+
+  #[test]
+  fn lsn_is_parsed_and_formatted() {
+    let lsn = "16/B374D848".parse::<Lsn>().unwrap();
+    assert_eq!(lsn, Lsn(0x16_B374_D848));
+    assert_eq!(lsn.to_string(), "16/B374D848");
+    assert!("0/0".parse::<Lsn>().unwrap() < lsn);
+    assert!("16".parse::<Lsn>().is_err());
+  }
+}
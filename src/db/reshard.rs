@@ -0,0 +1,750 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::{info, warn};
+
+use crate::{
+  db::shards::HashRing,
+  errors::{
+    common::DatabaseResult,
+    reshard::{ReshardError, ReshardResult},
+  },
+};
+
+/// Gap between the source sequences and the target one, so messages mirrored by instances
+/// which have not seen the cutover yet never collide with ids generated on the target
+const SEQUENCE_GAP: i64 = 1_000_000;
+
+const VERIFY_ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum ReshardPhase {
+  /// Writes of the moving dialogs are mirrored to the new shard
+  DualWrite,
+  /// Existing dialogs are copied to the new shard
+  Backfill,
+  /// Copies are compared with the source dialogs
+  Verify,
+  /// Reads are switched to the new shard, moved dialogs are deleted from the source shards
+  Cleanup,
+  Done,
+  /// Mirroring is stopped, copies are deleted from the new shard
+  Aborting,
+  Aborted,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReshardJob {
+  pub id: i32,
+  pub source_shards: i32,
+  pub target_shards: i32,
+  pub phase: ReshardPhase,
+  pub checkpoint_shard: i32,
+  pub checkpoint_user_a: i32,
+  pub checkpoint_user_b: i32,
+  pub moved_dialogs: i64,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl ReshardJob {
+  pub async fn latest(main: &PgPool) -> DatabaseResult<Option<Self>> {
+    let job = sqlx::query_as!(
+      ReshardJob,
+      r#"
+        SELECT
+          id, source_shards, target_shards, phase as "phase: ReshardPhase",
+          checkpoint_shard, checkpoint_user_a, checkpoint_user_b, moved_dialogs,
+          created_at, updated_at
+        FROM dialog_reshard_jobs
+        ORDER BY id DESC
+        LIMIT 1
+      "#
+    )
+    .fetch_optional(main)
+    .await?;
+
+    Ok(job)
+  }
+
+  pub fn is_finished(&self) -> bool {
+    matches!(self.phase, ReshardPhase::Done | ReshardPhase::Aborted)
+  }
+
+  fn target_shard(&self) -> usize {
+    self.target_shards as usize - 1
+  }
+}
+
+/// Summary of a dialog used to compare its copies on different shards
+#[derive(Debug, Eq, PartialEq)]
+struct DialogFingerprint {
+  messages: i64,
+  messages_checksum: String,
+  dialogs_checksum: String,
+}
+
+/// Moves dialogs to a newly added shard while the service keeps serving them.
+///
+/// Running instances follow the phase of the job through
+/// [`Topology::load`](crate::db::shards::Topology::load), so every phase change waits for the
+/// grace period before touching the data
+pub struct Resharder {
+  main: PgPool,
+  shards: Vec<PgPool>,
+  grace: Duration,
+  batch_size: i64,
+}
+
+impl Resharder {
+  pub fn new(main: PgPool, shards: Vec<PgPool>, grace: Duration, batch_size: i64) -> Self {
+    Self {
+      main,
+      shards,
+      grace,
+      batch_size,
+    }
+  }
+
+  pub async fn status(&self) -> ReshardResult<Option<ReshardJob>> {
+    Ok(ReshardJob::latest(&self.main).await?)
+  }
+
+  /// Register a job adding the last configured shard.
+  ///
+  /// The job starts in the dual-write phase, so instances configured with the new shard begin
+  /// mirroring writes to it. Data is moved by [`Resharder::resume`] once every instance runs with it
+  pub async fn start(&self) -> ReshardResult<ReshardJob> {
+    let latest = ReshardJob::latest(&self.main).await?;
+    if let Some(job) = latest.as_ref().filter(|job| !job.is_finished()) {
+      return Err(ReshardError::JobInProgress(job.id));
+    }
+
+    let target_shards = self.shards.len();
+    let source_shards = match latest {
+      Some(job) if job.phase == ReshardPhase::Done => job.target_shards as usize,
+      Some(job) => job.source_shards as usize,
+      None => target_shards - 1,
+    };
+    if source_shards + 1 != target_shards {
+      return Err(ReshardError::ShardCountMismatch {
+        expected: source_shards + 1,
+        configured: target_shards,
+      });
+    }
+
+    let job = sqlx::query_as!(
+      ReshardJob,
+      r#"
+        INSERT INTO dialog_reshard_jobs (source_shards, target_shards, phase)
+        VALUES ($1, $2, $3)
+        RETURNING
+          id, source_shards, target_shards, phase as "phase: ReshardPhase",
+          checkpoint_shard, checkpoint_user_a, checkpoint_user_b, moved_dialogs,
+          created_at, updated_at
+      "#,
+      source_shards as i32,
+      target_shards as i32,
+      ReshardPhase::DualWrite as ReshardPhase
+    )
+    .fetch_one(&self.main)
+    .await?;
+    info!(
+      "Started resharding job {} from {} to {} shards",
+      job.id, source_shards, target_shards
+    );
+
+    Ok(job)
+  }
+
+  /// Continue the job in progress from its checkpoint
+  pub async fn resume(&self) -> ReshardResult<ReshardJob> {
+    let mut job = self
+      .job_in_progress()
+      .await?
+      .ok_or(ReshardError::NoJobInProgress)?;
+
+    if job.phase == ReshardPhase::Aborting {
+      return self.abort().await;
+    }
+
+    loop {
+      info!("Resharding job {} is in {:?} phase", job.id, job.phase);
+
+      let next_phase = match job.phase {
+        ReshardPhase::DualWrite => {
+          self.wait_for_instances().await;
+          ReshardPhase::Backfill
+        }
+        ReshardPhase::Backfill => {
+          self.backfill(&job).await?;
+          ReshardPhase::Verify
+        }
+        ReshardPhase::Verify => {
+          self.verify(&job).await?;
+          self.sync_target_sequence(&job).await?;
+          ReshardPhase::Cleanup
+        }
+        ReshardPhase::Cleanup => {
+          self.wait_for_instances().await;
+          self.cleanup(&job).await?;
+          ReshardPhase::Done
+        }
+        ReshardPhase::Done | ReshardPhase::Aborting | ReshardPhase::Aborted => return Ok(job),
+      };
+
+      job = self.set_phase(&job, next_phase).await?;
+    }
+  }
+
+  /// Stop the job in progress and delete everything copied to the new shard
+  pub async fn abort(&self) -> ReshardResult<ReshardJob> {
+    let job = self
+      .job_in_progress()
+      .await?
+      .ok_or(ReshardError::NoJobInProgress)?;
+
+    if job.phase == ReshardPhase::Cleanup {
+      return Err(ReshardError::CannotAbort(job.phase));
+    }
+
+    let job = self.set_phase(&job, ReshardPhase::Aborting).await?;
+    self.wait_for_instances().await;
+
+    let target = &self.shards[job.target_shard()];
+    sqlx::query!("TRUNCATE message_edits, messages, dialogs")
+      .execute(target)
+      .await?;
+    warn!("Resharding job {} aborted, copies deleted", job.id);
+
+    self.set_phase(&job, ReshardPhase::Aborted).await
+  }
+
+  async fn backfill(&self, job: &ReshardJob) -> ReshardResult<()> {
+    let target = &self.shards[job.target_shard()];
+
+    self
+      .scan_moved_dialogs(job, |source, user_a, user_b| async move {
+        copy_dialog(&source, target, user_a, user_b).await
+      })
+      .await
+  }
+
+  async fn verify(&self, job: &ReshardJob) -> ReshardResult<()> {
+    let target = &self.shards[job.target_shard()];
+
+    self
+      .scan_moved_dialogs(job, |source, user_a, user_b| async move {
+        for attempt in 1..=VERIFY_ATTEMPTS {
+          let source_fingerprint = fingerprint(&source, user_a, user_b).await?;
+          let target_fingerprint = fingerprint(target, user_a, user_b).await?;
+          if source_fingerprint == target_fingerprint {
+            return Ok(());
+          }
+
+          warn!(
+            "Dialog {}:{} differs on the target shard, copying again (attempt {})",
+            user_a, user_b, attempt
+          );
+          copy_dialog(&source, target, user_a, user_b).await?;
+        }
+
+        Err(ReshardError::VerificationFailed(format!(
+          "{}:{}",
+          user_a, user_b
+        )))
+      })
+      .await
+  }
+
+  async fn cleanup(&self, job: &ReshardJob) -> ReshardResult<()> {
+    self
+      .scan_moved_dialogs(job, |source, user_a, user_b| async move {
+        delete_dialog(&source, user_a, user_b).await
+      })
+      .await
+  }
+
+  /// Move the sequence of the new shard past the sequences of the source shards
+  async fn sync_target_sequence(&self, job: &ReshardJob) -> ReshardResult<()> {
+    let mut last_value = 0;
+    for source in &self.shards[..job.source_shards as usize] {
+      let source_last_value =
+        sqlx::query_scalar!(r#"SELECT last_value AS "last_value!" FROM messages_id_seq"#)
+          .fetch_one(source)
+          .await?;
+      last_value = last_value.max(source_last_value);
+    }
+
+    sqlx::query!(
+      r#"SELECT setval('messages_id_seq', GREATEST((SELECT last_value FROM messages_id_seq), $1))"#,
+      last_value + SEQUENCE_GAP
+    )
+    .fetch_one(&self.shards[job.target_shard()])
+    .await?;
+
+    Ok(())
+  }
+
+  /// Call the action for every dialog of the source shards which belongs to the new shard,
+  /// saving the checkpoint after every batch
+  async fn scan_moved_dialogs<F, Fut>(&self, job: &ReshardJob, action: F) -> ReshardResult<()>
+  where
+    F: Fn(PgPool, i32, i32) -> Fut,
+    Fut: std::future::Future<Output = ReshardResult<()>>,
+  {
+    let source_ring = HashRing::new(job.source_shards as usize);
+    let target_ring = HashRing::new(job.target_shards as usize);
+    let target_shard = job.target_shard();
+
+    let mut checkpoint = (job.checkpoint_user_a, job.checkpoint_user_b);
+    for shard_index in job.checkpoint_shard as usize..job.source_shards as usize {
+      let source = &self.shards[shard_index];
+
+      loop {
+        let pairs = sqlx::query!(
+          r#"
+            SELECT DISTINCT user_a, user_b
+            FROM messages
+            WHERE (user_a, user_b) > ($1, $2)
+            ORDER BY user_a, user_b
+            LIMIT $3
+          "#,
+          checkpoint.0,
+          checkpoint.1,
+          self.batch_size
+        )
+        .fetch_all(source)
+        .await?;
+
+        let Some(last) = pairs.last() else {
+          break;
+        };
+        let next_checkpoint = (last.user_a, last.user_b);
+
+        let mut moved = 0;
+        for pair in &pairs {
+          let key = format!("{}:{}", pair.user_a, pair.user_b);
+          if source_ring.locate(&key) == shard_index && target_ring.locate(&key) == target_shard {
+            action(source.clone(), pair.user_a, pair.user_b).await?;
+            moved += 1;
+          }
+        }
+
+        checkpoint = next_checkpoint;
+        self
+          .save_checkpoint(job, shard_index, checkpoint, moved)
+          .await?;
+      }
+
+      checkpoint = (0, 0);
+      self
+        .save_checkpoint(job, shard_index + 1, checkpoint, 0)
+        .await?;
+    }
+
+    Ok(())
+  }
+
+  async fn save_checkpoint(
+    &self,
+    job: &ReshardJob,
+    shard_index: usize,
+    (user_a, user_b): (i32, i32),
+    moved: i64,
+  ) -> ReshardResult<()> {
+    // Only the backfill counts moved dialogs, other phases revisit the same ones
+    let moved = if job.phase == ReshardPhase::Backfill {
+      moved
+    } else {
+      0
+    };
+
+    sqlx::query!(
+      r#"
+        UPDATE dialog_reshard_jobs
+        SET checkpoint_shard = $2,
+          checkpoint_user_a = $3,
+          checkpoint_user_b = $4,
+          moved_dialogs = moved_dialogs + $5,
+          updated_at = NOW()
+        WHERE id = $1
+      "#,
+      job.id,
+      shard_index as i32,
+      user_a,
+      user_b,
+      moved
+    )
+    .execute(&self.main)
+    .await?;
+
+    Ok(())
+  }
+
+  async fn set_phase(&self, job: &ReshardJob, phase: ReshardPhase) -> ReshardResult<ReshardJob> {
+    let job = sqlx::query_as!(
+      ReshardJob,
+      r#"
+        UPDATE dialog_reshard_jobs
+        SET phase = $2,
+          checkpoint_shard = 0,
+          checkpoint_user_a = 0,
+          checkpoint_user_b = 0,
+          updated_at = NOW()
+        WHERE id = $1
+        RETURNING
+          id, source_shards, target_shards, phase as "phase: ReshardPhase",
+          checkpoint_shard, checkpoint_user_a, checkpoint_user_b, moved_dialogs,
+          created_at, updated_at
+      "#,
+      job.id,
+      phase as ReshardPhase
+    )
+    .fetch_one(&self.main)
+    .await?;
+
+    Ok(job)
+  }
+
+  async fn job_in_progress(&self) -> ReshardResult<Option<ReshardJob>> {
+    Ok(
+      ReshardJob::latest(&self.main)
+        .await?
+        .filter(|job| !job.is_finished()),
+    )
+  }
+
+  async fn wait_for_instances(&self) {
+    info!(
+      "Waiting {:?} for running instances to pick up the topology",
+      self.grace
+    );
+    tokio::time::sleep(self.grace).await;
+  }
+}
+
+async fn copy_dialog(
+  source: &PgPool,
+  target: &PgPool,
+  user_a: i32,
+  user_b: i32,
+) -> ReshardResult<()> {
+  let messages = sqlx::query!(
+    r#"
+      SELECT id, sender_id, text, created_at, edited_at, expires_at, envelope
+      FROM messages
+      WHERE user_a = $1 AND user_b = $2
+      ORDER BY id
+    "#,
+    user_a,
+    user_b
+  )
+  .fetch_all(source)
+  .await?;
+
+  let edits = sqlx::query!(
+    r#"
+      SELECT message_id, version, text, edited_at
+      FROM message_edits
+      WHERE user_a = $1 AND user_b = $2
+    "#,
+    user_a,
+    user_b
+  )
+  .fetch_all(source)
+  .await?;
+
+  let dialogs = sqlx::query!(
+    r#"
+      SELECT user_id, peer_id, last_message_id, last_read_message_id, message_ttl, updated_at
+      FROM dialogs
+      WHERE (user_id = $1 AND peer_id = $2) OR (user_id = $2 AND peer_id = $1)
+    "#,
+    user_a,
+    user_b
+  )
+  .fetch_all(source)
+  .await?;
+
+  let mut tx = target.begin().await?;
+
+  sqlx::query!(
+    r#"
+      INSERT INTO messages (
+        id, user_a, user_b, sender_id, text, created_at, edited_at, expires_at, envelope
+      )
+      SELECT id, $2, $3, sender_id, text, created_at, edited_at, expires_at, envelope
+      FROM UNNEST(
+        $1::BIGINT[], $4::INTEGER[], $5::TEXT[], $6::TIMESTAMPTZ[], $7::TIMESTAMPTZ[], $8::TIMESTAMPTZ[],
+        $9::JSONB[]
+      ) AS m(id, sender_id, text, created_at, edited_at, expires_at, envelope)
+      ON CONFLICT (user_a, user_b, id) DO UPDATE SET
+        sender_id = EXCLUDED.sender_id,
+        text = EXCLUDED.text,
+        created_at = EXCLUDED.created_at,
+        edited_at = EXCLUDED.edited_at,
+        expires_at = EXCLUDED.expires_at,
+        envelope = EXCLUDED.envelope
+    "#,
+    &messages.iter().map(|m| m.id).collect::<Vec<_>>(),
+    user_a,
+    user_b,
+    &messages.iter().map(|m| m.sender_id).collect::<Vec<_>>(),
+    &messages.iter().map(|m| m.text.clone()).collect::<Vec<_>>(),
+    &messages.iter().map(|m| m.created_at).collect::<Vec<_>>(),
+    &messages.iter().map(|m| m.edited_at).collect::<Vec<_>>() as _,
+    &messages.iter().map(|m| m.expires_at).collect::<Vec<_>>() as _,
+    &messages.iter().map(|m| m.envelope.clone()).collect::<Vec<_>>() as _
+  )
+  .execute(&mut *tx)
+  .await?;
+
+  sqlx::query!(
+    r#"
+      INSERT INTO message_edits (user_a, user_b, message_id, version, text, edited_at)
+      SELECT $2, $3, message_id, version, text, edited_at
+      FROM UNNEST($1::BIGINT[], $4::INTEGER[], $5::TEXT[], $6::TIMESTAMPTZ[])
+        AS e(message_id, version, text, edited_at)
+      WHERE EXISTS (SELECT 1 FROM messages WHERE user_a = $2 AND user_b = $3 AND id = e.message_id)
+      ON CONFLICT (user_a, user_b, message_id, version) DO NOTHING
+    "#,
+    &edits.iter().map(|e| e.message_id).collect::<Vec<_>>(),
+    user_a,
+    user_b,
+    &edits.iter().map(|e| e.version).collect::<Vec<_>>(),
+    &edits.iter().map(|e| e.text.clone()).collect::<Vec<_>>(),
+    &edits.iter().map(|e| e.edited_at).collect::<Vec<_>>()
+  )
+  .execute(&mut *tx)
+  .await?;
+
+  for dialog in dialogs {
+    sqlx::query!(
+      r#"
+        INSERT INTO dialogs (
+          user_id, peer_id, last_message_id, last_read_message_id, message_ttl, updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (user_id, peer_id) DO UPDATE SET
+          last_message_id = GREATEST(dialogs.last_message_id, EXCLUDED.last_message_id),
+          last_read_message_id = GREATEST(dialogs.last_read_message_id, EXCLUDED.last_read_message_id),
+          message_ttl = EXCLUDED.message_ttl,
+          updated_at = GREATEST(dialogs.updated_at, EXCLUDED.updated_at)
+      "#,
+      dialog.user_id,
+      dialog.peer_id,
+      dialog.last_message_id,
+      dialog.last_read_message_id,
+      dialog.message_ttl,
+      dialog.updated_at
+    )
+    .execute(&mut *tx)
+    .await?;
+  }
+
+  tx.commit().await?;
+
+  Ok(())
+}
+
+async fn delete_dialog(source: &PgPool, user_a: i32, user_b: i32) -> ReshardResult<()> {
+  let mut tx = source.begin().await?;
+
+  sqlx::query!(
+    r#"DELETE FROM messages WHERE user_a = $1 AND user_b = $2"#,
+    user_a,
+    user_b
+  )
+  .execute(&mut *tx)
+  .await?;
+
+  sqlx::query!(
+    r#"
+      DELETE FROM dialogs
+      WHERE (user_id = $1 AND peer_id = $2) OR (user_id = $2 AND peer_id = $1)
+    "#,
+    user_a,
+    user_b
+  )
+  .execute(&mut *tx)
+  .await?;
+
+  tx.commit().await?;
+
+  Ok(())
+}
+
+async fn fingerprint(shard: &PgPool, user_a: i32, user_b: i32) -> ReshardResult<DialogFingerprint> {
+  let fingerprint = sqlx::query_as!(
+    DialogFingerprint,
+    r#"
+      SELECT
+        (SELECT COUNT(*) FROM messages WHERE user_a = $1 AND user_b = $2) AS "messages!",
+        (
+          SELECT COALESCE(md5(string_agg(id || ':' || sender_id || ':' || text, ',' ORDER BY id)), '')
+          FROM messages
+          WHERE user_a = $1 AND user_b = $2
+        ) AS "messages_checksum!",
+        (
+          SELECT COALESCE(
+            string_agg(user_id || ':' || last_message_id || ':' || last_read_message_id, ',' ORDER BY user_id),
+            ''
+          )
+          FROM dialogs
+          WHERE (user_id = $1 AND peer_id = $2) OR (user_id = $2 AND peer_id = $1)
+        ) AS "dialogs_checksum!"
+    "#,
+    user_a,
+    user_b
+  )
+  .fetch_one(shard)
+  .await?;
+
+  Ok(fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    db::{dialogs::postgres::PgDialogStore, shards::ShardMap, testing::create_database},
+    dto::dialog::DialogKey,
+  };
+
+  const USERS: i32 = 30;
+
+  async fn count_messages(shard: &PgPool) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM messages"#)
+      .fetch_one(shard)
+      .await
+      .unwrap()
+  }
+
+  /// Main database doubling as the first shard, the second shard and the empty new shard
+  async fn seed(prefix: &str) -> (PgPool, Vec<PgPool>) {
+    let mut shards = Vec::new();
+    for index in 0..3 {
+      shards.push(create_database(&format!("{}_{}", prefix, index)).await);
+    }
+    let main = shards[0].clone();
+
+    let dialog_store = PgDialogStore::new(ShardMap::new(shards[..2].to_vec()));
+    for user_id in 1..USERS {
+      for text in ["ping", "pong"] {
+        dialog_store
+          .insert_message(user_id, user_id + 1, text.to_string(), None)
+          .await
+          .unwrap();
+      }
+    }
+
+    (main, shards)
+  }
+
+  #[tokio::test]
+  #[ignore = "requires postgres from docker-compose.yml"]
+  async fn dialogs_are_moved_to_new_shard() {
+    let (main, shards) = seed("sn_test_reshard_move").await;
+
+    let resharder = Resharder::new(main.clone(), shards.clone(), Duration::ZERO, 7);
+    let job = resharder.start().await.unwrap();
+    assert_eq!(job.phase, ReshardPhase::DualWrite);
+
+    let job = resharder.resume().await.unwrap();
+    assert_eq!(job.phase, ReshardPhase::Done);
+    assert!(job.moved_dialogs > 0);
+    assert!(count_messages(&shards[2]).await > 0);
+
+    let shard_map = ShardMap::new(shards.clone());
+    shard_map.refresh_topology(&main).await.unwrap();
+    assert_eq!(shard_map.topology().ring.shard_count(), 3);
+
+    let mut total = 0;
+    for shard in &shards {
+      total += count_messages(shard).await;
+    }
+    assert_eq!(total, 2 * (USERS as i64 - 1));
+
+    let dialog_store = PgDialogStore::new(shard_map.clone());
+    for user_id in 1..USERS {
+      let page = dialog_store
+        .list_messages(DialogKey::new(user_id, user_id + 1), None, 10)
+        .await
+        .unwrap();
+      assert_eq!(page.messages.len(), 2);
+
+      let message = dialog_store
+        .insert_message(user_id + 1, user_id, "again".to_string(), None)
+        .await
+        .unwrap();
+      assert!(message.id > page.messages[0].id);
+    }
+  }
+
+  #[tokio::test]
+  #[ignore = "requires postgres from docker-compose.yml"]
+  async fn aborted_job_leaves_source_shards_intact() {
+    let (main, shards) = seed("sn_test_reshard_abort").await;
+
+    sqlx::query!(
+      r#"
+        INSERT INTO dialog_reshard_jobs (source_shards, target_shards, phase)
+        VALUES (2, 3, 'backfill')
+      "#
+    )
+    .execute(&main)
+    .await
+    .unwrap();
+
+    let resharder = Resharder::new(main.clone(), shards.clone(), Duration::ZERO, 7);
+    let job = ReshardJob::latest(&main).await.unwrap().unwrap();
+    resharder.backfill(&job).await.unwrap();
+    assert!(count_messages(&shards[2]).await > 0);
+
+    // Writes of the moving dialogs are mirrored while reads stay on the source shards
+    let shard_map = ShardMap::new(shards.clone());
+    shard_map.refresh_topology(&main).await.unwrap();
+    let moved_key = (1..USERS)
+      .map(|user_id| DialogKey::new(user_id, user_id + 1))
+      .find(|key| {
+        shard_map
+          .topology()
+          .mirror_ring
+          .unwrap()
+          .locate(&key.shard_key())
+          == 2
+      })
+      .unwrap();
+    let dialog_store = PgDialogStore::new(shard_map.clone());
+    dialog_store
+      .insert_message(
+        moved_key.user_a,
+        moved_key.user_b,
+        "mirrored".to_string(),
+        None,
+      )
+      .await
+      .unwrap();
+    assert_ne!(shard_map.shard_index(&moved_key.shard_key()), 2);
+    let copy = fingerprint(&shards[2], moved_key.user_a, moved_key.user_b)
+      .await
+      .unwrap();
+    assert_eq!(copy.messages, 3);
+
+    let job = resharder.abort().await.unwrap();
+    assert_eq!(job.phase, ReshardPhase::Aborted);
+    assert_eq!(count_messages(&shards[2]).await, 0);
+
+    shard_map.refresh_topology(&main).await.unwrap();
+    assert_eq!(shard_map.topology().ring.shard_count(), 2);
+    assert!(shard_map.topology().mirror_ring.is_none());
+
+    let total = count_messages(&shards[0]).await + count_messages(&shards[1]).await;
+    assert_eq!(total, 2 * (USERS as i64 - 1) + 1);
+  }
+}
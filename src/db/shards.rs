@@ -0,0 +1,272 @@
+use std::{
+  sync::{Arc, PoisonError, RwLock, RwLockReadGuard},
+  time::Duration,
+};
+
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use tokio::task::JoinHandle;
+use tracing::error;
+
+use crate::{
+  db::reshard::{ReshardJob, ReshardPhase},
+  errors::common::{DatabaseError, DatabaseResult},
+};
+
+/// Number of points every shard occupies on the hash ring
+const VIRTUAL_NODES: usize = 64;
+
+/// Consistent hash ring over shards identified by their position in the shard list.
+///
+/// Appending a shard moves only the keys that land on its points, all other keys keep their shard
+#[derive(Debug, Clone)]
+pub struct HashRing {
+  points: Vec<(u64, usize)>,
+  shard_count: usize,
+}
+
+impl HashRing {
+  pub fn new(shard_count: usize) -> Self {
+    let mut points = (0..shard_count)
+      .flat_map(|shard| {
+        (0..VIRTUAL_NODES)
+          .map(move |node| (hash(format!("shard-{}#{}", shard, node).as_bytes()), shard))
+      })
+      .collect::<Vec<_>>();
+    points.sort_unstable();
+
+    Self {
+      points,
+      shard_count,
+    }
+  }
+
+  pub fn shard_count(&self) -> usize {
+    self.shard_count
+  }
+
+  /// Index of the shard owning the key
+  pub fn locate(&self, key: &str) -> usize {
+    if self.points.is_empty() {
+      return 0;
+    }
+
+    let key_hash = hash(key.as_bytes());
+    let position = self.points.partition_point(|(point, _)| *point < key_hash);
+
+    self.points[position % self.points.len()].1
+  }
+}
+
+/// Routing of keys while the storage is being resharded
+#[derive(Debug, Clone)]
+pub struct Topology {
+  /// Ring the keys are read from and written to
+  pub ring: HashRing,
+  /// Ring of the shard layout being moved to, writes are mirrored there until cutover
+  pub mirror_ring: Option<HashRing>,
+}
+
+impl Topology {
+  pub fn stable(shard_count: usize) -> Self {
+    Self {
+      ring: HashRing::new(shard_count),
+      mirror_ring: None,
+    }
+  }
+
+  /// Topology matching the latest resharding job, if any
+  pub async fn load(main: &PgPool, configured_shards: usize) -> DatabaseResult<Self> {
+    let job = ReshardJob::latest(main).await?;
+
+    let topology = match job {
+      None => Self::stable(configured_shards),
+      Some(job) => match job.phase {
+        ReshardPhase::DualWrite | ReshardPhase::Backfill | ReshardPhase::Verify => Self {
+          ring: HashRing::new(job.source_shards as usize),
+          mirror_ring: Some(HashRing::new(job.target_shards as usize)),
+        },
+        ReshardPhase::Cleanup | ReshardPhase::Done => Self::stable(job.target_shards as usize),
+        ReshardPhase::Aborting | ReshardPhase::Aborted => Self::stable(job.source_shards as usize),
+      },
+    };
+
+    let required_shards = topology
+      .mirror_ring
+      .as_ref()
+      .map_or(topology.ring.shard_count(), |ring| ring.shard_count());
+    if required_shards > configured_shards {
+      return Err(DatabaseError::ShardNotConfigured(
+        required_shards,
+        configured_shards,
+      ));
+    }
+
+    Ok(topology)
+  }
+}
+
+/// Postgres pools of the sharded storage with the topology routing keys to them
+#[derive(Debug, Clone)]
+pub struct ShardMap {
+  shards: Arc<Vec<PgPool>>,
+  topology: Arc<RwLock<Topology>>,
+}
+
+impl ShardMap {
+  pub fn new(shards: Vec<PgPool>) -> Self {
+    let topology = Topology::stable(shards.len());
+
+    Self {
+      shards: Arc::new(shards),
+      topology: Arc::new(RwLock::new(topology)),
+    }
+  }
+
+  /// Connect to every shard, the fallback pool is used as the only shard when no urls are given
+  pub async fn connect(
+    urls: &[String],
+    fallback: &PgPool,
+    options: PgPoolOptions,
+  ) -> DatabaseResult<Self> {
+    if urls.is_empty() {
+      return Ok(Self::new(vec![fallback.clone()]));
+    }
+
+    let mut shards = Vec::with_capacity(urls.len());
+    for url in urls {
+      shards.push(options.clone().connect(url).await?);
+    }
+
+    Ok(Self::new(shards))
+  }
+
+  pub fn topology(&self) -> Topology {
+    self.read_topology().clone()
+  }
+
+  pub fn set_topology(&self, topology: Topology) {
+    *self
+      .topology
+      .write()
+      .unwrap_or_else(PoisonError::into_inner) = topology;
+  }
+
+  /// Reload the topology from the main database
+  pub async fn refresh_topology(&self, main: &PgPool) -> DatabaseResult<()> {
+    let topology = Topology::load(main, self.shards.len()).await?;
+    self.set_topology(topology);
+
+    Ok(())
+  }
+
+  /// Periodically reload the topology so running instances follow the resharding tool
+  pub fn spawn_topology_refresh(&self, main: PgPool, interval: Duration) -> JoinHandle<()> {
+    let shards = self.clone();
+
+    tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(interval);
+      loop {
+        ticker.tick().await;
+        if let Err(e) = shards.refresh_topology(&main).await {
+          error!("Failed to refresh dialog shard topology: {}", e);
+        }
+      }
+    })
+  }
+
+  /// Index of the shard the key is read from
+  pub fn shard_index(&self, key: &str) -> usize {
+    self.read_topology().ring.locate(key)
+  }
+
+  /// Shard the key is read from
+  pub fn shard(&self, key: &str) -> PgPool {
+    self.shards[self.shard_index(key)].clone()
+  }
+
+  /// Shard the key is written to and the shard the write has to be mirrored to
+  pub fn write_shards(&self, key: &str) -> (PgPool, Option<PgPool>) {
+    let topology = self.read_topology();
+    let primary = topology.ring.locate(key);
+    let mirror = topology
+      .mirror_ring
+      .as_ref()
+      .map(|ring| ring.locate(key))
+      .filter(|mirror| *mirror != primary);
+
+    (
+      self.shards[primary].clone(),
+      mirror.map(|mirror| self.shards[mirror].clone()),
+    )
+  }
+
+  pub fn get(&self, index: usize) -> Option<&PgPool> {
+    self.shards.get(index)
+  }
+
+  pub fn all(&self) -> &[PgPool] {
+    &self.shards
+  }
+
+  fn read_topology(&self) -> RwLockReadGuard<'_, Topology> {
+    self.topology.read().unwrap_or_else(PoisonError::into_inner)
+  }
+}
+
+/// FNV-1a with a 64 bit finalizer, stable across builds and platforms
+fn hash(bytes: &[u8]) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for byte in bytes {
+    hash ^= u64::from(*byte);
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+
+  hash ^= hash >> 33;
+  hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+  hash ^= hash >> 33;
+  hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+  hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn keys() -> impl Iterator<Item = String> {
+    (1..100).flat_map(|a| (a + 1..=100).map(move |b| format!("{}:{}", a, b)))
+  }
+
+  #[test]
+  fn keys_are_spread_over_shards() {
+    let ring = HashRing::new(4);
+    let mut counts = [0usize; 4];
+    for key in keys() {
+      counts[ring.locate(&key)] += 1;
+    }
+
+    let total = counts.iter().sum::<usize>();
+    for count in counts {
+      assert!(count > total / 8, "unbalanced shards: {:?}", counts);
+    }
+  }
+
+  #[test]
+  fn adding_shard_moves_keys_only_to_new_shard() {
+    let before = HashRing::new(3);
+    let after = HashRing::new(4);
+
+    let mut moved = 0;
+    let mut total = 0;
+    for key in keys() {
+      let (from, to) = (before.locate(&key), after.locate(&key));
+      if from != to {
+        assert_eq!(to, 3);
+        moved += 1;
+      }
+      total += 1;
+    }
+
+    assert!(moved > 0);
+    assert!(moved < total / 2);
+  }
+}
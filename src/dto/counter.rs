@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+const GROUP_FIELD_PREFIX: &str = "g:";
+
+/// Conversation as seen by a user, an unread counter or a realtime event belongs to
+#[derive(
+  Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, ToSchema,
+)]
+#[serde(tag = "kind", content = "id", rename_all = "snake_case")]
+pub enum Conversation {
+  /// Dialog with the peer
+  Dialog(i32),
+  Group(i32),
+}
+
+impl Conversation {
+  /// Field of the counter in the Redis hash of the user
+  pub fn field(&self) -> String {
+    match self {
+      Self::Dialog(peer_id) => peer_id.to_string(),
+      Self::Group(group_id) => format!("{}{}", GROUP_FIELD_PREFIX, group_id),
+    }
+  }
+
+  pub fn from_field(field: &str) -> Option<Self> {
+    match field.strip_prefix(GROUP_FIELD_PREFIX) {
+      Some(group_id) => group_id.parse().ok().map(Self::Group),
+      None => field.parse().ok().map(Self::Dialog),
+    }
+  }
+}
+
+/// Unread messages of the user by conversation
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UnreadCountersDto {
+  pub total: i64,
+  pub conversations: BTreeMap<Conversation, i64>,
+}
+
+/// Result of comparing counters with the message store
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CounterReconciliationDto {
+  pub checked_users: usize,
+  pub drifted_users: usize,
+  pub fixed_users: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DialogCounterResponse {
+  pub peer_id: i32,
+  pub unread: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupCounterResponse {
+  pub group_id: i32,
+  pub unread: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CountersResponse {
+  pub total_unread: i64,
+  pub dialogs: Vec<DialogCounterResponse>,
+  pub groups: Vec<GroupCounterResponse>,
+}
+
+impl From<UnreadCountersDto> for CountersResponse {
+  fn from(counters: UnreadCountersDto) -> Self {
+    let mut response = Self {
+      total_unread: counters.total,
+      dialogs: Vec::new(),
+      groups: Vec::new(),
+    };
+
+    for (conversation, unread) in counters.conversations {
+      match conversation {
+        Conversation::Dialog(peer_id) => response
+          .dialogs
+          .push(DialogCounterResponse { peer_id, unread }),
+        Conversation::Group(group_id) => response
+          .groups
+          .push(GroupCounterResponse { group_id, unread }),
+      }
+    }
+
+    response
+  }
+}
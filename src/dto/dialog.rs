@@ -0,0 +1,305 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+
+use crate::dto::keys::{validate_device_id, validate_nonce, validate_public_key, MAX_DEVICES};
+
+pub const DEFAULT_MESSAGES_PAGE_SIZE: i64 = 50;
+/// Bounds of the disappearing messages timeout in seconds
+pub const MIN_MESSAGE_TTL: i32 = 5;
+pub const MAX_MESSAGE_TTL: i32 = 90 * 24 * 60 * 60;
+/// Envelopes carry a ciphertext for every device of both participants
+pub const MAX_ENVELOPE_CIPHERTEXTS: u64 = 2 * MAX_DEVICES as u64;
+/// Base64 length of a ciphertext, enough for the longest text with the authentication tag
+pub const MAX_CIPHERTEXT_LENGTH: u64 = 8192;
+
+/// Ordered pair of dialog participants, `user_a` is always the smaller id
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DialogKey {
+  pub user_a: i32,
+  pub user_b: i32,
+}
+
+impl DialogKey {
+  pub fn new(first: i32, second: i32) -> Self {
+    Self {
+      user_a: first.min(second),
+      user_b: first.max(second),
+    }
+  }
+
+  /// Key used to pick the storage shard of the dialog
+  pub fn shard_key(&self) -> String {
+    format!("{}:{}", self.user_a, self.user_b)
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageDto {
+  pub id: i64,
+  pub user_a: i32,
+  pub user_b: i32,
+  pub sender_id: i32,
+  pub text: String,
+  pub created_at: DateTime<Utc>,
+  #[serde(default)]
+  pub edited_at: Option<DateTime<Utc>>,
+  /// Set for messages sent while the dialog had disappearing messages on
+  #[serde(default)]
+  pub expires_at: Option<DateTime<Utc>>,
+  /// Set for end-to-end encrypted messages, their text is empty
+  #[serde(default)]
+  pub envelope: Option<Json<EnvelopeDto>>,
+}
+
+impl MessageDto {
+  pub fn recipient_id(&self) -> i32 {
+    self.peer_of(self.sender_id)
+  }
+
+  /// The other participant of the dialog for the given one
+  pub fn peer_of(&self, user_id: i32) -> i32 {
+    if user_id == self.user_a {
+      self.user_b
+    } else {
+      self.user_a
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct MessagePageDto {
+  pub messages: Vec<MessageDto>,
+  pub next_cursor: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct DialogSummaryDto {
+  pub peer_id: i32,
+  pub last_message: MessageDto,
+  pub unread_count: i64,
+  pub message_ttl: Option<i32>,
+}
+
+/// Previous version of an edited message
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageEditDto {
+  pub version: i32,
+  pub text: String,
+  /// When this version was replaced
+  pub edited_at: DateTime<Utc>,
+}
+
+/// Message deleted from the store, with whether its recipient had not read it yet
+#[derive(Debug, Clone)]
+pub struct DeletedMessageDto {
+  pub message: MessageDto,
+  pub unread: bool,
+}
+
+/// Expired messages deleted by a sweep
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MessageSweepDto {
+  pub dialog_messages: usize,
+  pub group_messages: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+pub struct SendMessageDto {
+  #[validate(length(min = 1, max = 4096))]
+  #[schema(example = "Hello!", required)]
+  pub text: String,
+}
+
+/// End-to-end encrypted message, the server stores it as is and never decrypts it
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvelopeDto {
+  #[validate(custom(function = "validate_device_id"))]
+  #[schema(example = "phone", required)]
+  pub sender_device_id: String,
+
+  /// The message encrypted for every device of both participants, the sending one excluded
+  #[validate(length(min = 1, max = MAX_ENVELOPE_CIPHERTEXTS), nested)]
+  pub ciphertexts: Vec<DeviceCiphertextDto>,
+}
+
+/// Copy of the message for one device, keyed by X25519 agreement with its signed prekey
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCiphertextDto {
+  #[schema(example = 2, required)]
+  pub user_id: i32,
+
+  #[validate(custom(function = "validate_device_id"))]
+  #[schema(example = "laptop", required)]
+  pub device_id: String,
+
+  #[schema(example = 1, required)]
+  pub signed_prekey_id: i32,
+
+  /// Base64 X25519 public key generated by the sender for this ciphertext
+  #[validate(custom(function = "validate_public_key"))]
+  #[schema(required)]
+  pub ephemeral_key: String,
+
+  /// Base64 AEAD nonce
+  #[validate(custom(function = "validate_nonce"))]
+  #[schema(required)]
+  pub nonce: String,
+
+  /// Base64 AEAD ciphertext with its authentication tag
+  #[validate(length(min = 1, max = MAX_CIPHERTEXT_LENGTH))]
+  #[schema(required)]
+  pub ciphertext: String,
+}
+
+/// Dialog messages carry either plain text or an envelope
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[validate(schema(function = "validate_message_body"))]
+pub struct SendDialogMessageDto {
+  #[validate(length(min = 1, max = 4096))]
+  #[schema(example = "Hello!")]
+  pub text: Option<String>,
+
+  #[validate(nested)]
+  pub envelope: Option<EnvelopeDto>,
+}
+
+fn validate_message_body(message: &SendDialogMessageDto) -> Result<(), ValidationError> {
+  match (&message.text, &message.envelope) {
+    (Some(_), None) | (None, Some(_)) => Ok(()),
+    _ => Err(ValidationError::new("text_or_envelope")),
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+pub struct EditMessageDto {
+  #[validate(length(min = 1, max = 4096))]
+  #[schema(example = "Hello again!", required)]
+  pub text: String,
+}
+
+/// Disappearing messages timeout applied to messages sent from now on
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+pub struct MessageTtlDto {
+  /// Seconds a message lives after it was sent, `null` turns disappearing messages off
+  #[validate(range(min = MIN_MESSAGE_TTL, max = MAX_MESSAGE_TTL))]
+  #[schema(example = 86400)]
+  pub seconds: Option<i32>,
+}
+
+/// Read receipt forwarded to the dialog service
+#[derive(Serialize, Deserialize, Debug, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkReadDto {
+  #[validate(range(min = 1))]
+  pub message_id: i64,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListMessagesQuery {
+  /// Id of the last message from the previous page
+  pub cursor: Option<i64>,
+
+  /// Page size
+  #[validate(range(min = 1, max = 100))]
+  #[param(minimum = 1, maximum = 100, example = 50)]
+  pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageResponse {
+  pub id: i64,
+  pub from: i32,
+  pub to: i32,
+  pub text: String,
+  pub created_at: DateTime<Utc>,
+  pub edited_at: Option<DateTime<Utc>>,
+  pub expires_at: Option<DateTime<Utc>>,
+  pub envelope: Option<EnvelopeDto>,
+}
+
+impl From<MessageDto> for MessageResponse {
+  fn from(message: MessageDto) -> Self {
+    Self {
+      id: message.id,
+      from: message.sender_id,
+      to: message.recipient_id(),
+      text: message.text,
+      created_at: message.created_at,
+      edited_at: message.edited_at,
+      expires_at: message.expires_at,
+      envelope: message.envelope.map(|envelope| envelope.0),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageListResponse {
+  pub items: Vec<MessageResponse>,
+  pub next_cursor: Option<i64>,
+}
+
+impl From<MessagePageDto> for MessageListResponse {
+  fn from(page: MessagePageDto) -> Self {
+    Self {
+      items: page
+        .messages
+        .into_iter()
+        .map(MessageResponse::from)
+        .collect(),
+      next_cursor: page.next_cursor,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DialogSummaryResponse {
+  pub peer_id: i32,
+  pub last_message: MessageResponse,
+  pub unread_count: i64,
+  /// Disappearing messages timeout in seconds
+  pub message_ttl: Option<i32>,
+}
+
+impl From<DialogSummaryDto> for DialogSummaryResponse {
+  fn from(dialog: DialogSummaryDto) -> Self {
+    Self {
+      peer_id: dialog.peer_id,
+      last_message: MessageResponse::from(dialog.last_message),
+      unread_count: dialog.unread_count,
+      message_ttl: dialog.message_ttl,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageEditResponse {
+  pub version: i32,
+  pub text: String,
+  pub edited_at: DateTime<Utc>,
+}
+
+impl From<MessageEditDto> for MessageEditResponse {
+  fn from(edit: MessageEditDto) -> Self {
+    Self {
+      version: edit.version,
+      text: edit.text,
+      edited_at: edit.edited_at,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageTtlResponse {
+  pub seconds: Option<i32>,
+}
@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::dto::{
+  counter::Conversation,
+  dialog::{MessageDto, MessageResponse},
+  group::GroupMessageResponse,
+};
+
+/// Event pushed to the WebSocket of a user, the conversation is as seen by that user
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RealtimeEvent {
+  Message(MessageEvent),
+  GroupMessage(GroupMessageEvent),
+  MessageEdited(MessageEvent),
+  GroupMessageEdited(GroupMessageEvent),
+  /// The sender deleted the message for everyone
+  MessageDeleted(DeletedEvent),
+  /// The message reached a connected device of the user
+  Delivered(ReceiptEvent),
+  /// The user has read the conversation up to the message
+  Read(ReceiptEvent),
+  Typing(TypingEvent),
+  /// A command of this connection failed, never published to other connections
+  Error(ErrorEvent),
+}
+
+impl RealtimeEvent {
+  /// New dialog message as seen by one of its participants
+  pub fn dialog_message(viewer_id: i32, message: &MessageDto) -> Self {
+    Self::Message(MessageEvent::new(viewer_id, message))
+  }
+
+  pub fn dialog_message_edited(viewer_id: i32, message: &MessageDto) -> Self {
+    Self::MessageEdited(MessageEvent::new(viewer_id, message))
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageEvent {
+  pub conversation: Conversation,
+  pub message: MessageResponse,
+}
+
+impl MessageEvent {
+  fn new(viewer_id: i32, message: &MessageDto) -> Self {
+    Self {
+      conversation: Conversation::Dialog(message.peer_of(viewer_id)),
+      message: MessageResponse::from(message.clone()),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMessageEvent {
+  pub conversation: Conversation,
+  pub message: GroupMessageResponse,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletedEvent {
+  pub conversation: Conversation,
+  pub message_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiptEvent {
+  pub conversation: Conversation,
+  pub user_id: i32,
+  pub message_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TypingEvent {
+  pub conversation: Conversation,
+  pub user_id: i32,
+  /// Seconds to show the indicator for unless a message or another typing event arrives
+  pub expires_in: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorEvent {
+  pub message: String,
+  pub code: String,
+}
+
+/// Command sent by the client over the WebSocket
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientCommand {
+  Typing(TypingCommand),
+  Read(ReadCommand),
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TypingCommand {
+  pub conversation: Conversation,
+}
+
+/// Move the read cursor of the conversation up to the message
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadCommand {
+  pub conversation: Conversation,
+  pub message_id: i64,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_wire_format() {
+    let command: ClientCommand = serde_json::from_str(
+      r#"{"type":"read","conversation":{"kind":"group","id":3},"messageId":42}"#,
+    )
+    .unwrap();
+    assert!(matches!(
+      command,
+      ClientCommand::Read(ReadCommand {
+        conversation: Conversation::Group(3),
+        message_id: 42,
+      })
+    ));
+
+    let event = RealtimeEvent::Typing(TypingEvent {
+      conversation: Conversation::Dialog(7),
+      user_id: 5,
+      expires_in: 5,
+    });
+    assert_eq!(
+      serde_json::to_string(&event).unwrap(),
+      r#"{"type":"typing","conversation":{"kind":"dialog","id":7},"userId":5,"expiresIn":5}"#
+    );
+  }
+}
@@ -0,0 +1,233 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+pub const MAX_GROUP_MEMBERS: usize = 200;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Clone, Copy, Eq, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum GroupRole {
+  Admin,
+  Member,
+}
+
+/// Text messages of the members and system messages of member events
+#[derive(Serialize, Deserialize, Debug, ToSchema, Clone, Copy, Eq, PartialEq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum GroupMessageKind {
+  Text,
+  Created,
+  Renamed,
+  MemberAdded,
+  MemberRemoved,
+  MemberLeft,
+  TtlChanged,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupDto {
+  pub id: i32,
+  pub title: String,
+  pub created_by: i32,
+  /// Disappearing messages timeout in seconds
+  pub message_ttl: Option<i32>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupMemberDto {
+  pub user_id: i32,
+  pub role: GroupRole,
+  pub joined_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct GroupDetailsDto {
+  pub group: GroupDto,
+  pub members: Vec<GroupMemberDto>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupMessageDto {
+  pub id: i64,
+  pub group_id: i32,
+  pub sender_id: i32,
+  pub kind: GroupMessageKind,
+  pub text: String,
+  /// Member the event is about, set for system messages of member events
+  pub target_user_id: Option<i32>,
+  pub created_at: DateTime<Utc>,
+  pub edited_at: Option<DateTime<Utc>>,
+  pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub struct GroupMessagePageDto {
+  pub messages: Vec<GroupMessageDto>,
+  pub next_cursor: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct GroupSummaryDto {
+  pub group: GroupDto,
+  pub last_message: Option<GroupMessageDto>,
+  pub unread_count: i64,
+}
+
+/// Unread messages of a member according to the read state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupUnreadCount {
+  pub user_id: i32,
+  pub group_id: i32,
+  pub unread: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateGroupDto {
+  #[validate(length(min = 1, max = 255))]
+  #[schema(example = "Weekend trip", required)]
+  pub title: String,
+
+  /// Members besides the creator, who becomes the admin
+  #[validate(length(max = 199))]
+  #[schema(example = json!([2, 3]))]
+  #[serde(default)]
+  pub member_ids: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+pub struct RenameGroupDto {
+  #[validate(length(min = 1, max = 255))]
+  #[schema(example = "Weekend trip", required)]
+  pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct AddGroupMemberDto {
+  #[schema(example = 2, required)]
+  pub user_id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMemberResponse {
+  pub user_id: i32,
+  pub role: GroupRole,
+  pub joined_at: DateTime<Utc>,
+}
+
+impl From<GroupMemberDto> for GroupMemberResponse {
+  fn from(member: GroupMemberDto) -> Self {
+    Self {
+      user_id: member.user_id,
+      role: member.role,
+      joined_at: member.joined_at,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupResponse {
+  pub id: i32,
+  pub title: String,
+  pub created_by: i32,
+  pub members: Vec<GroupMemberResponse>,
+  pub message_ttl: Option<i32>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl From<GroupDetailsDto> for GroupResponse {
+  fn from(details: GroupDetailsDto) -> Self {
+    Self {
+      id: details.group.id,
+      title: details.group.title,
+      created_by: details.group.created_by,
+      members: details
+        .members
+        .into_iter()
+        .map(GroupMemberResponse::from)
+        .collect(),
+      message_ttl: details.group.message_ttl,
+      created_at: details.group.created_at,
+      updated_at: details.group.updated_at,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMessageResponse {
+  pub id: i64,
+  pub group_id: i32,
+  pub from: i32,
+  pub kind: GroupMessageKind,
+  pub text: String,
+  pub target_user_id: Option<i32>,
+  pub created_at: DateTime<Utc>,
+  pub edited_at: Option<DateTime<Utc>>,
+  pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<GroupMessageDto> for GroupMessageResponse {
+  fn from(message: GroupMessageDto) -> Self {
+    Self {
+      id: message.id,
+      group_id: message.group_id,
+      from: message.sender_id,
+      kind: message.kind,
+      text: message.text,
+      target_user_id: message.target_user_id,
+      created_at: message.created_at,
+      edited_at: message.edited_at,
+      expires_at: message.expires_at,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMessageListResponse {
+  pub items: Vec<GroupMessageResponse>,
+  pub next_cursor: Option<i64>,
+}
+
+impl From<GroupMessagePageDto> for GroupMessageListResponse {
+  fn from(page: GroupMessagePageDto) -> Self {
+    Self {
+      items: page
+        .messages
+        .into_iter()
+        .map(GroupMessageResponse::from)
+        .collect(),
+      next_cursor: page.next_cursor,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupSummaryResponse {
+  pub id: i32,
+  pub title: String,
+  pub last_message: Option<GroupMessageResponse>,
+  pub unread_count: i64,
+}
+
+impl From<GroupSummaryDto> for GroupSummaryResponse {
+  fn from(summary: GroupSummaryDto) -> Self {
+    Self {
+      id: summary.group.id,
+      title: summary.group.title,
+      last_message: summary.last_message.map(GroupMessageResponse::from),
+      unread_count: summary.unread_count,
+    }
+  }
+}
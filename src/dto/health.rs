@@ -0,0 +1,49 @@
+use axum::{
+  http::StatusCode,
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::db::circuit_breaker::CircuitState;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+  Up,
+  Down,
+}
+
+/// Result of checking one dependency
+#[derive(Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyHealthResponse {
+  #[schema(example = "postgres")]
+  pub name: String,
+  pub status: HealthStatus,
+  /// Time the check took, up to the timeout when the dependency does not answer
+  #[schema(example = 2)]
+  pub latency_ms: u64,
+  /// State of the circuit breaker guarding the calls to the dependency, if it has one
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub circuit_breaker: Option<CircuitState>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct ReadinessResponse {
+  /// Up only when every dependency is up
+  pub status: HealthStatus,
+  pub dependencies: Vec<DependencyHealthResponse>,
+}
+
+impl IntoResponse for ReadinessResponse {
+  fn into_response(self) -> Response {
+    let status = match self.status {
+      HealthStatus::Up => StatusCode::OK,
+      HealthStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status, Json(self)).into_response()
+  }
+}
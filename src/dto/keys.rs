@@ -0,0 +1,135 @@
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+pub const MAX_DEVICES: usize = 10;
+pub const MAX_DEVICE_ID_LENGTH: usize = 64;
+
+const PUBLIC_KEY_LENGTH: usize = 32;
+const SIGNATURE_LENGTH: usize = 64;
+const NONCE_LENGTH: usize = 12;
+
+/// Keys of a device as published in the directory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeviceKeysDto {
+  pub user_id: i32,
+  pub device_id: String,
+  pub identity_key: String,
+  pub signed_prekey_id: i32,
+  pub signed_prekey: String,
+  pub signed_prekey_signature: String,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+/// Keys are base64 encoded, the server never checks the signatures, clients do
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishDeviceKeysDto {
+  /// Ed25519 public key of the device, it can not change for a published device
+  #[validate(custom(function = "validate_public_key"))]
+  #[schema(example = "11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=", required)]
+  pub identity_key: String,
+
+  #[validate(nested)]
+  pub signed_prekey: SignedPrekeyDto,
+}
+
+/// X25519 public key signed by the identity key, rotated by publishing the device again
+#[derive(Serialize, Deserialize, Debug, ToSchema, Validate, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedPrekeyDto {
+  #[validate(range(min = 0))]
+  #[schema(example = 1, required)]
+  pub key_id: i32,
+
+  #[validate(custom(function = "validate_public_key"))]
+  #[schema(example = "hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo=", required)]
+  pub public_key: String,
+
+  /// Ed25519 signature of the raw public key bytes
+  #[validate(custom(function = "validate_signature"))]
+  #[schema(required)]
+  pub signature: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceKeysResponse {
+  pub user_id: i32,
+  pub device_id: String,
+  pub identity_key: String,
+  pub signed_prekey: SignedPrekeyDto,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl From<DeviceKeysDto> for DeviceKeysResponse {
+  fn from(keys: DeviceKeysDto) -> Self {
+    Self {
+      user_id: keys.user_id,
+      device_id: keys.device_id,
+      identity_key: keys.identity_key,
+      signed_prekey: SignedPrekeyDto {
+        key_id: keys.signed_prekey_id,
+        public_key: keys.signed_prekey,
+        signature: keys.signed_prekey_signature,
+      },
+      updated_at: keys.updated_at,
+    }
+  }
+}
+
+/// Device ids are chosen by the clients and end up in urls
+pub fn is_valid_device_id(device_id: &str) -> bool {
+  (1..=MAX_DEVICE_ID_LENGTH).contains(&device_id.len())
+    && device_id
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub(crate) fn validate_device_id(device_id: &str) -> Result<(), ValidationError> {
+  if is_valid_device_id(device_id) {
+    Ok(())
+  } else {
+    Err(ValidationError::new("device_id"))
+  }
+}
+
+pub(crate) fn validate_public_key(value: &str) -> Result<(), ValidationError> {
+  validate_base64(value, PUBLIC_KEY_LENGTH, "public_key")
+}
+
+pub(crate) fn validate_nonce(value: &str) -> Result<(), ValidationError> {
+  validate_base64(value, NONCE_LENGTH, "nonce")
+}
+
+fn validate_signature(value: &str) -> Result<(), ValidationError> {
+  validate_base64(value, SIGNATURE_LENGTH, "signature")
+}
+
+fn validate_base64(value: &str, length: usize, code: &'static str) -> Result<(), ValidationError> {
+  match BASE64_STANDARD.decode(value) {
+    Ok(bytes) if bytes.len() == length => Ok(()),
+    _ => Err(ValidationError::new(code)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_key_validation() {
+    let key = BASE64_STANDARD.encode([7u8; PUBLIC_KEY_LENGTH]);
+    assert!(validate_public_key(&key).is_ok());
+    assert!(validate_public_key(&BASE64_STANDARD.encode([7u8; 31])).is_err());
+    assert!(validate_public_key("not base64!").is_err());
+
+    assert!(is_valid_device_id("phone-1_a"));
+    assert!(!is_valid_device_id(""));
+    assert!(!is_valid_device_id("../keys"));
+    assert!(!is_valid_device_id(&"a".repeat(MAX_DEVICE_ID_LENGTH + 1)));
+  }
+}
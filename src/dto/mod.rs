@@ -1,2 +1,9 @@
+pub mod counter;
+pub mod dialog;
 pub mod error;
+pub mod event;
+pub mod group;
+pub mod health;
+pub mod keys;
+pub mod post;
 pub mod user;
@@ -0,0 +1,99 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+pub const DEFAULT_POSTS_PAGE_SIZE: i64 = 20;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Clone, Copy, Eq, PartialEq, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum PostVisibility {
+  Public,
+  Private,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PostDto {
+  pub id: i64,
+  pub author_id: i32,
+  pub content: String,
+  pub visibility: PostVisibility,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PostAuthorDto {
+  pub id: i32,
+  pub first_name: Option<String>,
+  pub second_name: Option<String>,
+}
+
+impl PostAuthorDto {
+  pub fn display_name(&self) -> String {
+    match (&self.first_name, &self.second_name) {
+      (Some(first_name), Some(second_name)) => format!("{} {}", first_name, second_name),
+      (Some(name), None) | (None, Some(name)) => name.clone(),
+      (None, None) => format!("User {}", self.id),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct PostPageDto {
+  pub posts: Vec<PostDto>,
+  pub next_cursor: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListPostsQuery {
+  /// Id of the last post from the previous page
+  pub cursor: Option<i64>,
+
+  /// Page size
+  #[validate(range(min = 1, max = 100))]
+  #[param(minimum = 1, maximum = 100, example = 20)]
+  pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PostResponse {
+  pub id: i64,
+  pub author_id: i32,
+  pub content: String,
+  pub visibility: PostVisibility,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+impl From<PostDto> for PostResponse {
+  fn from(post: PostDto) -> Self {
+    Self {
+      id: post.id,
+      author_id: post.author_id,
+      content: post.content,
+      visibility: post.visibility,
+      created_at: post.created_at,
+      updated_at: post.updated_at,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PostListResponse {
+  pub items: Vec<PostResponse>,
+  pub next_cursor: Option<i64>,
+}
+
+impl From<PostPageDto> for PostListResponse {
+  fn from(page: PostPageDto) -> Self {
+    Self {
+      items: page.posts.into_iter().map(PostResponse::from).collect(),
+      next_cursor: page.next_cursor,
+    }
+  }
+}
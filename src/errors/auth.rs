@@ -41,6 +41,10 @@ pub enum AuthError {
   #[error("Session expired")]
   #[diagnostic(code(sn::errors::auth::session_expired))]
   SessionExpired,
+
+  #[error("Token revocation status is unavailable")]
+  #[diagnostic(code(sn::errors::auth::revocation_check_unavailable))]
+  RevocationCheckUnavailable,
 }
 
 impl AuthError {
@@ -66,6 +70,8 @@ impl AuthError {
       Self::InvalidAuthMethod | Self::OtpRequired => StatusCode::BAD_REQUEST,
 
       Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
+
+      Self::RevocationCheckUnavailable => StatusCode::SERVICE_UNAVAILABLE,
     }
   }
 
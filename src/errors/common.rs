@@ -25,6 +25,18 @@ pub enum InitError {
   #[diagnostic(code(sn::errors::init::bind))]
   Bind(std::io::Error),
 
+  #[error("Missing required configuration: {0}")]
+  #[diagnostic(code(sn::errors::init::missing_config))]
+  MissingConfig(&'static str),
+
+  #[error("Failed to build trace exporter: {0}")]
+  #[diagnostic(code(sn::errors::init::tracing))]
+  Tracing(opentelemetry::trace::TraceError),
+
+  #[error("Failed to install metrics recorder: {0}")]
+  #[diagnostic(code(sn::errors::init::metrics))]
+  Metrics(#[from] metrics_exporter_prometheus::BuildError),
+
   #[error("Failed to setup signal handlers")]
   #[diagnostic(code(sn::errors::init::signal))]
   SignalHandler,
@@ -40,6 +52,18 @@ pub enum DatabaseError {
   #[error("Failed to connect to redis: {0}")]
   #[diagnostic(code(sn::errors::database::connect))]
   RedisConnect(#[from] redis::RedisError),
+
+  #[error("Failed to open in-memory dialog store: {0}")]
+  #[diagnostic(code(sn::errors::database::memory_store))]
+  MemoryStore(#[from] std::io::Error),
+
+  #[error("Failed to migrate database: {0}")]
+  #[diagnostic(code(sn::errors::database::migrate))]
+  Migrate(#[from] sqlx::migrate::MigrateError),
+
+  #[error("Shard topology requires {0} shards, but only {1} are configured")]
+  #[diagnostic(code(sn::errors::database::shard_not_configured))]
+  ShardNotConfigured(usize, usize),
 }
 
 pub type DatabaseResult<T> = Result<T, DatabaseError>;
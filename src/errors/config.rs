@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+/// Errors of loading the configuration file and the secret files
+#[derive(Debug, Error, Diagnostic)]
+pub enum ConfigError {
+  #[error("Failed to read {path}: {source}")]
+  #[diagnostic(code(sn::errors::config::read))]
+  Read {
+    path: PathBuf,
+    source: std::io::Error,
+  },
+
+  #[error("Failed to parse config file {path}: {source}")]
+  #[diagnostic(code(sn::errors::config::parse))]
+  Parse {
+    path: PathBuf,
+    source: toml::de::Error,
+  },
+
+  #[error("Unknown key in config file: {0}")]
+  #[diagnostic(
+    code(sn::errors::config::unknown_key),
+    help("keys are the long flags in snake_case, like `database_url`, see `--help`")
+  )]
+  UnknownKey(String),
+
+  #[error("Unsupported value of {0}, expected a string, number, boolean or array of them")]
+  #[diagnostic(code(sn::errors::config::unsupported_value))]
+  UnsupportedValue(String),
+}
+
+pub type ConfigResult<T> = Result<T, ConfigError>;
@@ -0,0 +1,58 @@
+use axum::{
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use miette::Diagnostic;
+use thiserror::Error;
+use tracing::error;
+
+use crate::dto::error::ErrorResponse;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum CounterError {
+  #[error("Failed to update counters: {0}")]
+  #[diagnostic(code(sn::errors::counter::failed_to_update_counters))]
+  FailedToUpdateCounters(redis::RedisError),
+
+  #[error("Failed to get counters: {0}")]
+  #[diagnostic(code(sn::errors::counter::failed_to_get_counters))]
+  FailedToGetCounters(redis::RedisError),
+
+  #[error("Failed to rebuild counters from messages: {0}")]
+  #[diagnostic(code(sn::errors::counter::failed_to_rebuild_counters))]
+  FailedToRebuildCounters(String),
+}
+
+pub type CounterResult<T> = Result<T, CounterError>;
+
+impl CounterError {
+  pub fn status_code(&self) -> StatusCode {
+    StatusCode::SERVICE_UNAVAILABLE
+  }
+}
+
+impl IntoResponse for CounterError {
+  fn into_response(self) -> Response {
+    error!("Counter error: {:?}", self);
+
+    let status = self.status_code();
+    let error_response = match self {
+      Self::FailedToUpdateCounters(_) => ErrorResponse::new(
+        "Failed to update counters",
+        "sn::errors::counter::failed_to_update_counters",
+      ),
+
+      Self::FailedToGetCounters(_) => ErrorResponse::new(
+        "Failed to get counters",
+        "sn::errors::counter::failed_to_get_counters",
+      ),
+
+      Self::FailedToRebuildCounters(_) => ErrorResponse::new(
+        "Failed to rebuild counters",
+        "sn::errors::counter::failed_to_rebuild_counters",
+      ),
+    };
+
+    (status, error_response).into_response()
+  }
+}
@@ -0,0 +1,196 @@
+use axum::{
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use miette::Diagnostic;
+use thiserror::Error;
+use tracing::{error, warn};
+
+use crate::{dto::error::ErrorResponse, errors::counter::CounterError};
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum DialogError {
+  #[error("Failed to send message")]
+  #[diagnostic(code(sn::errors::dialog::failed_to_send_message))]
+  FailedToSendMessage(sqlx::Error),
+
+  #[error("Failed to get messages")]
+  #[diagnostic(code(sn::errors::dialog::failed_to_find_messages))]
+  FailedToFindMessages(sqlx::Error),
+
+  #[error("Failed to update message")]
+  #[diagnostic(code(sn::errors::dialog::failed_to_update_message))]
+  FailedToUpdateMessage(sqlx::Error),
+
+  #[error("Failed to update blocks")]
+  #[diagnostic(code(sn::errors::dialog::failed_to_update_blocks))]
+  FailedToUpdateBlocks(sqlx::Error),
+
+  #[error("Failed to persist dialog change: {0}")]
+  #[diagnostic(code(sn::errors::dialog::failed_to_persist))]
+  FailedToPersist(std::io::Error),
+
+  #[error("Peer not found: {0}")]
+  #[diagnostic(code(sn::errors::dialog::peer_not_found))]
+  PeerNotFound(i32),
+
+  #[error("Dialog with yourself is not allowed")]
+  #[diagnostic(code(sn::errors::dialog::self_dialog))]
+  SelfDialog,
+
+  #[error("User {0} is blocked")]
+  #[diagnostic(code(sn::errors::dialog::blocked))]
+  Blocked(i32),
+
+  #[error("Message not found: {0}")]
+  #[diagnostic(code(sn::errors::dialog::message_not_found))]
+  MessageNotFound(i64),
+
+  #[error("Only the sender can change message {0}")]
+  #[diagnostic(code(sn::errors::dialog::not_message_sender))]
+  NotMessageSender(i64),
+
+  #[error("Message {0} can no longer be edited")]
+  #[diagnostic(code(sn::errors::dialog::edit_window_expired))]
+  EditWindowExpired(i64),
+
+  #[error("Invalid envelope: {0}")]
+  #[diagnostic(code(sn::errors::dialog::invalid_envelope))]
+  InvalidEnvelope(String),
+
+  #[error("Encrypted message {0} can not be edited")]
+  #[diagnostic(code(sn::errors::dialog::encrypted_message))]
+  EncryptedMessage(i64),
+
+  #[error("Unread counters are unavailable: {0}")]
+  #[diagnostic(code(sn::errors::dialog::counters_unavailable))]
+  CountersUnavailable(#[from] CounterError),
+
+  #[error("Dialog service is unavailable: {0}")]
+  #[diagnostic(code(sn::errors::dialog::dialog_service_unavailable))]
+  DialogServiceUnavailable(String),
+}
+
+pub type DialogResult<T> = Result<T, DialogError>;
+
+impl DialogError {
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::PeerNotFound(_) | Self::MessageNotFound(_) => StatusCode::NOT_FOUND,
+      Self::SelfDialog | Self::InvalidEnvelope(_) | Self::EncryptedMessage(_) => {
+        StatusCode::BAD_REQUEST
+      }
+      Self::Blocked(_) | Self::NotMessageSender(_) | Self::EditWindowExpired(_) => {
+        StatusCode::FORBIDDEN
+      }
+      Self::FailedToSendMessage(_)
+      | Self::FailedToFindMessages(_)
+      | Self::FailedToUpdateMessage(_)
+      | Self::FailedToUpdateBlocks(_)
+      | Self::FailedToPersist(_) => StatusCode::INTERNAL_SERVER_ERROR,
+      Self::CountersUnavailable(e) => e.status_code(),
+      Self::DialogServiceUnavailable(_) => StatusCode::BAD_GATEWAY,
+    }
+  }
+
+  fn is_critical(&self) -> bool {
+    matches!(
+      self,
+      Self::FailedToSendMessage(_)
+        | Self::FailedToFindMessages(_)
+        | Self::FailedToUpdateMessage(_)
+        | Self::FailedToUpdateBlocks(_)
+        | Self::FailedToPersist(_)
+        | Self::CountersUnavailable(_)
+        | Self::DialogServiceUnavailable(_)
+    )
+  }
+}
+
+impl IntoResponse for DialogError {
+  fn into_response(self) -> Response {
+    if self.is_critical() {
+      error!("Critical dialog error: {:?}", self);
+    } else {
+      warn!("Dialog error: {:?}", self);
+    }
+
+    let status = self.status_code();
+    let error_response = match self {
+      Self::FailedToSendMessage(_) => ErrorResponse::new(
+        "Failed to send message",
+        "sn::errors::dialog::failed_to_send_message",
+      ),
+
+      Self::FailedToFindMessages(_) => ErrorResponse::new(
+        "Failed to get messages",
+        "sn::errors::dialog::failed_to_find_messages",
+      ),
+
+      Self::FailedToUpdateMessage(_) => ErrorResponse::new(
+        "Failed to update message",
+        "sn::errors::dialog::failed_to_update_message",
+      ),
+
+      Self::FailedToUpdateBlocks(_) => ErrorResponse::new(
+        "Failed to update blocks",
+        "sn::errors::dialog::failed_to_update_blocks",
+      ),
+
+      Self::FailedToPersist(_) => ErrorResponse::new(
+        "Failed to save dialog changes",
+        "sn::errors::dialog::failed_to_persist",
+      ),
+
+      Self::PeerNotFound(_) => {
+        ErrorResponse::new("User not found", "sn::errors::dialog::peer_not_found")
+      }
+
+      Self::SelfDialog => ErrorResponse::new(
+        "Dialog with yourself is not allowed",
+        "sn::errors::dialog::self_dialog",
+      ),
+
+      Self::Blocked(_) => ErrorResponse::new(
+        "Messaging between these users is blocked",
+        "sn::errors::dialog::blocked",
+      ),
+
+      Self::MessageNotFound(_) => {
+        ErrorResponse::new("Message not found", "sn::errors::dialog::message_not_found")
+      }
+
+      Self::NotMessageSender(_) => ErrorResponse::new(
+        "Only the sender can change the message",
+        "sn::errors::dialog::not_message_sender",
+      ),
+
+      Self::EditWindowExpired(_) => ErrorResponse::new(
+        "Message can no longer be edited",
+        "sn::errors::dialog::edit_window_expired",
+      ),
+
+      Self::InvalidEnvelope(reason) => ErrorResponse::new(
+        &format!("Invalid envelope: {}", reason),
+        "sn::errors::dialog::invalid_envelope",
+      ),
+
+      Self::EncryptedMessage(_) => ErrorResponse::new(
+        "Encrypted messages can not be edited",
+        "sn::errors::dialog::encrypted_message",
+      ),
+
+      Self::CountersUnavailable(_) => ErrorResponse::new(
+        "Unread counters are unavailable",
+        "sn::errors::dialog::counters_unavailable",
+      ),
+
+      Self::DialogServiceUnavailable(_) => ErrorResponse::new(
+        "Dialog service is unavailable",
+        "sn::errors::dialog::dialog_service_unavailable",
+      ),
+    };
+
+    (status, error_response).into_response()
+  }
+}
@@ -0,0 +1,69 @@
+use axum::http::StatusCode;
+use miette::Diagnostic;
+use thiserror::Error;
+use tracing::{error, warn};
+
+use crate::{
+  dto::event::ErrorEvent,
+  errors::{dialog::DialogError, group::GroupError},
+};
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum EventError {
+  #[error("Failed to subscribe to events: {0}")]
+  #[diagnostic(code(sn::errors::event::failed_to_subscribe))]
+  FailedToSubscribe(redis::RedisError),
+
+  #[error("Failed to update typing state: {0}")]
+  #[diagnostic(code(sn::errors::event::failed_to_update_typing))]
+  FailedToUpdateTyping(redis::RedisError),
+
+  #[error("Invalid command: {0}")]
+  #[diagnostic(code(sn::errors::event::invalid_command))]
+  InvalidCommand(serde_json::Error),
+
+  #[error(transparent)]
+  #[diagnostic(transparent)]
+  Dialog(#[from] DialogError),
+
+  #[error(transparent)]
+  #[diagnostic(transparent)]
+  Group(#[from] GroupError),
+}
+
+pub type EventResult<T> = Result<T, EventError>;
+
+impl EventError {
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::FailedToSubscribe(_) | Self::FailedToUpdateTyping(_) => StatusCode::SERVICE_UNAVAILABLE,
+      Self::InvalidCommand(_) => StatusCode::BAD_REQUEST,
+      Self::Dialog(e) => e.status_code(),
+      Self::Group(e) => e.status_code(),
+    }
+  }
+
+  fn is_critical(&self) -> bool {
+    self.status_code().is_server_error()
+  }
+}
+
+/// Errors of WebSocket commands are reported over the socket instead of a response
+impl From<EventError> for ErrorEvent {
+  fn from(error: EventError) -> Self {
+    let code = error
+      .code()
+      .map(|code| code.to_string())
+      .unwrap_or_else(|| "sn::errors::event::unknown".to_string());
+
+    let message = if error.is_critical() {
+      error!("Critical event error: {:?}", error);
+      "Failed to process the command".to_string()
+    } else {
+      warn!("Event error: {:?}", error);
+      error.to_string()
+    };
+
+    Self { message, code }
+  }
+}
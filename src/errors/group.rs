@@ -0,0 +1,181 @@
+use axum::{
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use miette::Diagnostic;
+use thiserror::Error;
+use tracing::{error, warn};
+
+use crate::{dto::error::ErrorResponse, errors::counter::CounterError};
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum GroupError {
+  #[error("Failed to update group: {0}")]
+  #[diagnostic(code(sn::errors::group::failed_to_update_group))]
+  FailedToUpdateGroup(sqlx::Error),
+
+  #[error("Failed to get groups: {0}")]
+  #[diagnostic(code(sn::errors::group::failed_to_find_groups))]
+  FailedToFindGroups(sqlx::Error),
+
+  #[error("Group not found: {0}")]
+  #[diagnostic(code(sn::errors::group::group_not_found))]
+  GroupNotFound(i32),
+
+  #[error("User not found: {0}")]
+  #[diagnostic(code(sn::errors::group::user_not_found))]
+  UserNotFound(i32),
+
+  #[error("User {0} is not a member of the group")]
+  #[diagnostic(code(sn::errors::group::not_member))]
+  NotMember(i32),
+
+  #[error("User {0} is already a member of the group")]
+  #[diagnostic(code(sn::errors::group::already_member))]
+  AlreadyMember(i32),
+
+  #[error("Only admins can manage members of group {0}")]
+  #[diagnostic(code(sn::errors::group::not_admin))]
+  NotAdmin(i32),
+
+  #[error("Group can not have more than {0} members")]
+  #[diagnostic(code(sn::errors::group::too_many_members))]
+  TooManyMembers(usize),
+
+  #[error("Members leave the group instead of removing themselves")]
+  #[diagnostic(code(sn::errors::group::remove_self))]
+  RemoveSelf,
+
+  #[error("Message not found: {0}")]
+  #[diagnostic(code(sn::errors::group::message_not_found))]
+  MessageNotFound(i64),
+
+  #[error("Only the sender can change message {0}")]
+  #[diagnostic(code(sn::errors::group::not_message_sender))]
+  NotMessageSender(i64),
+
+  #[error("Message {0} can no longer be edited")]
+  #[diagnostic(code(sn::errors::group::edit_window_expired))]
+  EditWindowExpired(i64),
+
+  #[error("System message {0} can not be changed")]
+  #[diagnostic(code(sn::errors::group::system_message))]
+  SystemMessage(i64),
+
+  #[error("Unread counters are unavailable: {0}")]
+  #[diagnostic(code(sn::errors::group::counters_unavailable))]
+  CountersUnavailable(#[from] CounterError),
+}
+
+pub type GroupResult<T> = Result<T, GroupError>;
+
+impl GroupError {
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::GroupNotFound(_)
+      | Self::UserNotFound(_)
+      | Self::NotMember(_)
+      | Self::MessageNotFound(_) => StatusCode::NOT_FOUND,
+      Self::AlreadyMember(_) => StatusCode::CONFLICT,
+      Self::NotAdmin(_) | Self::NotMessageSender(_) | Self::EditWindowExpired(_) => {
+        StatusCode::FORBIDDEN
+      }
+      Self::TooManyMembers(_) | Self::RemoveSelf | Self::SystemMessage(_) => {
+        StatusCode::BAD_REQUEST
+      }
+      Self::CountersUnavailable(e) => e.status_code(),
+      Self::FailedToUpdateGroup(_) | Self::FailedToFindGroups(_) => {
+        StatusCode::INTERNAL_SERVER_ERROR
+      }
+    }
+  }
+
+  fn is_critical(&self) -> bool {
+    matches!(
+      self,
+      Self::FailedToUpdateGroup(_) | Self::FailedToFindGroups(_) | Self::CountersUnavailable(_)
+    )
+  }
+}
+
+impl IntoResponse for GroupError {
+  fn into_response(self) -> Response {
+    if self.is_critical() {
+      error!("Critical group error: {:?}", self);
+    } else {
+      warn!("Group error: {:?}", self);
+    }
+
+    let status = self.status_code();
+    let error_response = match self {
+      Self::FailedToUpdateGroup(_) => ErrorResponse::new(
+        "Failed to update group",
+        "sn::errors::group::failed_to_update_group",
+      ),
+
+      Self::FailedToFindGroups(_) => ErrorResponse::new(
+        "Failed to get groups",
+        "sn::errors::group::failed_to_find_groups",
+      ),
+
+      Self::GroupNotFound(_) => {
+        ErrorResponse::new("Group not found", "sn::errors::group::group_not_found")
+      }
+
+      Self::UserNotFound(_) => {
+        ErrorResponse::new("User not found", "sn::errors::group::user_not_found")
+      }
+
+      Self::NotMember(_) => ErrorResponse::new(
+        "User is not a member of the group",
+        "sn::errors::group::not_member",
+      ),
+
+      Self::AlreadyMember(_) => ErrorResponse::new(
+        "User is already a member of the group",
+        "sn::errors::group::already_member",
+      ),
+
+      Self::NotAdmin(_) => ErrorResponse::new(
+        "Only admins can manage group members",
+        "sn::errors::group::not_admin",
+      ),
+
+      Self::TooManyMembers(max) => ErrorResponse::new(
+        &format!("Group can not have more than {} members", max),
+        "sn::errors::group::too_many_members",
+      ),
+
+      Self::RemoveSelf => ErrorResponse::new(
+        "Leave the group instead of removing yourself",
+        "sn::errors::group::remove_self",
+      ),
+
+      Self::MessageNotFound(_) => {
+        ErrorResponse::new("Message not found", "sn::errors::group::message_not_found")
+      }
+
+      Self::NotMessageSender(_) => ErrorResponse::new(
+        "Only the sender can change the message",
+        "sn::errors::group::not_message_sender",
+      ),
+
+      Self::EditWindowExpired(_) => ErrorResponse::new(
+        "Message can no longer be edited",
+        "sn::errors::group::edit_window_expired",
+      ),
+
+      Self::SystemMessage(_) => ErrorResponse::new(
+        "System messages can not be changed",
+        "sn::errors::group::system_message",
+      ),
+
+      Self::CountersUnavailable(_) => ErrorResponse::new(
+        "Unread counters are unavailable",
+        "sn::errors::group::counters_unavailable",
+      ),
+    };
+
+    (status, error_response).into_response()
+  }
+}
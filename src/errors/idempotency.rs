@@ -0,0 +1,82 @@
+use axum::{
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use miette::Diagnostic;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::dto::error::ErrorResponse;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum IdempotencyError {
+  #[error("Invalid idempotency key")]
+  #[diagnostic(code(sn::errors::idempotency::invalid_key))]
+  InvalidKey,
+
+  #[error("Request with the same idempotency key is in progress")]
+  #[diagnostic(code(sn::errors::idempotency::in_progress))]
+  InProgress,
+
+  #[error("Idempotency key was used with another request")]
+  #[diagnostic(code(sn::errors::idempotency::key_reused))]
+  KeyReused,
+
+  #[error("Failed to read body: {0}")]
+  #[diagnostic(code(sn::errors::idempotency::failed_to_read_body))]
+  FailedToReadBody(axum::Error),
+
+  #[error("Failed to store idempotency record: {0}")]
+  #[diagnostic(code(sn::errors::idempotency::failed_to_store))]
+  FailedToStore(redis::RedisError),
+}
+
+pub type IdempotencyResult<T> = Result<T, IdempotencyError>;
+
+impl IdempotencyError {
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::InvalidKey | Self::FailedToReadBody(_) => StatusCode::BAD_REQUEST,
+      Self::InProgress => StatusCode::CONFLICT,
+      Self::KeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+      Self::FailedToStore(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+  }
+}
+
+impl IntoResponse for IdempotencyError {
+  fn into_response(self) -> Response {
+    warn!("Idempotency error: {:?}", self);
+
+    let status = self.status_code();
+    let error_response = match &self {
+      Self::InvalidKey => ErrorResponse::new(
+        "Invalid idempotency key",
+        "sn::errors::idempotency::invalid_key",
+      )
+      .with_details("Expected up to 255 visible ASCII characters"),
+
+      Self::InProgress => ErrorResponse::new(
+        "Request with the same idempotency key is in progress",
+        "sn::errors::idempotency::in_progress",
+      ),
+
+      Self::KeyReused => ErrorResponse::new(
+        "Idempotency key was used with another request",
+        "sn::errors::idempotency::key_reused",
+      ),
+
+      Self::FailedToReadBody(_) => ErrorResponse::new(
+        "Failed to read body",
+        "sn::errors::idempotency::failed_to_read_body",
+      ),
+
+      Self::FailedToStore(_) => ErrorResponse::new(
+        "Failed to store idempotency record",
+        "sn::errors::idempotency::failed_to_store",
+      ),
+    };
+
+    (status, error_response).into_response()
+  }
+}
@@ -0,0 +1,108 @@
+use axum::{
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use miette::Diagnostic;
+use thiserror::Error;
+use tracing::{error, warn};
+
+use crate::dto::error::ErrorResponse;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum KeyError {
+  #[error("Failed to update keys: {0}")]
+  #[diagnostic(code(sn::errors::keys::failed_to_update_keys))]
+  FailedToUpdateKeys(sqlx::Error),
+
+  #[error("Failed to get keys: {0}")]
+  #[diagnostic(code(sn::errors::keys::failed_to_find_keys))]
+  FailedToFindKeys(sqlx::Error),
+
+  #[error("User not found: {0}")]
+  #[diagnostic(code(sn::errors::keys::user_not_found))]
+  UserNotFound(i32),
+
+  #[error("Device not found: {0}")]
+  #[diagnostic(code(sn::errors::keys::device_not_found))]
+  DeviceNotFound(String),
+
+  #[error("Invalid device id: {0}")]
+  #[diagnostic(code(sn::errors::keys::invalid_device_id))]
+  InvalidDeviceId(String),
+
+  #[error("Identity key of device {0} can not change")]
+  #[diagnostic(code(sn::errors::keys::identity_key_changed))]
+  IdentityKeyChanged(String),
+
+  #[error("User can not have more than {0} devices")]
+  #[diagnostic(code(sn::errors::keys::too_many_devices))]
+  TooManyDevices(usize),
+}
+
+pub type KeyResult<T> = Result<T, KeyError>;
+
+impl KeyError {
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::UserNotFound(_) | Self::DeviceNotFound(_) => StatusCode::NOT_FOUND,
+      Self::InvalidDeviceId(_) | Self::TooManyDevices(_) => StatusCode::BAD_REQUEST,
+      Self::IdentityKeyChanged(_) => StatusCode::CONFLICT,
+      Self::FailedToUpdateKeys(_) | Self::FailedToFindKeys(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  fn is_critical(&self) -> bool {
+    matches!(
+      self,
+      Self::FailedToUpdateKeys(_) | Self::FailedToFindKeys(_)
+    )
+  }
+}
+
+impl IntoResponse for KeyError {
+  fn into_response(self) -> Response {
+    if self.is_critical() {
+      error!("Critical key error: {:?}", self);
+    } else {
+      warn!("Key error: {:?}", self);
+    }
+
+    let status = self.status_code();
+    let error_response = match self {
+      Self::FailedToUpdateKeys(_) => ErrorResponse::new(
+        "Failed to update keys",
+        "sn::errors::keys::failed_to_update_keys",
+      ),
+
+      Self::FailedToFindKeys(_) => ErrorResponse::new(
+        "Failed to get keys",
+        "sn::errors::keys::failed_to_find_keys",
+      ),
+
+      Self::UserNotFound(_) => {
+        ErrorResponse::new("User not found", "sn::errors::keys::user_not_found")
+      }
+
+      Self::DeviceNotFound(_) => {
+        ErrorResponse::new("Device not found", "sn::errors::keys::device_not_found")
+      }
+
+      Self::InvalidDeviceId(_) => ErrorResponse::new(
+        "Device ids are 1 to 64 letters, digits, dashes or underscores",
+        "sn::errors::keys::invalid_device_id",
+      ),
+
+      Self::IdentityKeyChanged(_) => ErrorResponse::new(
+        "Identity key of a device can not change, remove the device first",
+        "sn::errors::keys::identity_key_changed",
+      ),
+
+      Self::TooManyDevices(max) => ErrorResponse::new(
+        &format!("User can not have more than {} devices", max),
+        "sn::errors::keys::too_many_devices",
+      ),
+    };
+
+    (status, error_response).into_response()
+  }
+}
@@ -1,3 +1,14 @@
 pub mod auth;
 pub mod common;
+pub mod config;
+pub mod counter;
+pub mod dialog;
+pub mod event;
+pub mod group;
+pub mod idempotency;
+pub mod keys;
+pub mod overload;
+pub mod post;
+pub mod rate_limit;
+pub mod reshard;
 pub mod user;
@@ -0,0 +1,57 @@
+use axum::{
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use miette::Diagnostic;
+use thiserror::Error;
+use tracing::{error, warn};
+
+use crate::dto::error::ErrorResponse;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum OverloadError {
+  #[error("Server is overloaded")]
+  #[diagnostic(code(sn::errors::overload::overloaded))]
+  Overloaded,
+
+  #[error("Request timed out")]
+  #[diagnostic(code(sn::errors::overload::timed_out))]
+  TimedOut,
+
+  #[error("Unhandled middleware error: {0}")]
+  #[diagnostic(code(sn::errors::overload::unhandled))]
+  Unhandled(String),
+}
+
+impl OverloadError {
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::Overloaded | Self::TimedOut => StatusCode::SERVICE_UNAVAILABLE,
+      Self::Unhandled(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+}
+
+impl IntoResponse for OverloadError {
+  fn into_response(self) -> Response {
+    let status = self.status_code();
+    let error_response = match &self {
+      Self::Overloaded => {
+        warn!("Request shed: {}", self);
+        ErrorResponse::new("Server is overloaded", "sn::errors::overload::overloaded")
+      }
+
+      Self::TimedOut => {
+        warn!("Request shed: {}", self);
+        ErrorResponse::new("Request timed out", "sn::errors::overload::timed_out")
+      }
+
+      Self::Unhandled(_) => {
+        error!("{}", self);
+        ErrorResponse::new("Internal server error", "sn::errors::overload::unhandled")
+      }
+    };
+
+    (status, error_response).into_response()
+  }
+}
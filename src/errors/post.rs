@@ -0,0 +1,59 @@
+use axum::{
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use miette::Diagnostic;
+use thiserror::Error;
+use tracing::{error, warn};
+
+use crate::dto::error::ErrorResponse;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum PostError {
+  #[error("Failed to get posts")]
+  #[diagnostic(code(sn::errors::post::failed_to_find_posts))]
+  FailedToFindPosts(sqlx::Error),
+
+  #[error("Author not found: {0}")]
+  #[diagnostic(code(sn::errors::post::author_not_found))]
+  AuthorNotFound(i32),
+}
+
+pub type PostResult<T> = Result<T, PostError>;
+
+impl PostError {
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::AuthorNotFound(_) => StatusCode::NOT_FOUND,
+      Self::FailedToFindPosts(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  fn is_critical(&self) -> bool {
+    matches!(self, Self::FailedToFindPosts(_))
+  }
+}
+
+impl IntoResponse for PostError {
+  fn into_response(self) -> Response {
+    if self.is_critical() {
+      error!("Critical post error: {:?}", self);
+    } else {
+      warn!("Post error: {:?}", self);
+    }
+
+    let status = self.status_code();
+    let error_response = match self {
+      Self::FailedToFindPosts(_) => ErrorResponse::new(
+        "Failed to get posts",
+        "sn::errors::post::failed_to_find_posts",
+      ),
+
+      Self::AuthorNotFound(_) => {
+        ErrorResponse::new("Author not found", "sn::errors::post::author_not_found")
+      }
+    };
+
+    (status, error_response).into_response()
+  }
+}
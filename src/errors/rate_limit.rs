@@ -0,0 +1,53 @@
+use axum::{
+  http::StatusCode,
+  response::{IntoResponse, Response},
+};
+use miette::Diagnostic;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::dto::error::ErrorResponse;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum RateLimitError {
+  #[error("Too many requests, retry in {0} seconds")]
+  #[diagnostic(code(sn::errors::rate_limit::too_many_requests))]
+  TooManyRequests(u64),
+
+  #[error("Failed to check rate limit: {0}")]
+  #[diagnostic(code(sn::errors::rate_limit::failed_to_check))]
+  FailedToCheck(redis::RedisError),
+}
+
+pub type RateLimitResult<T> = Result<T, RateLimitError>;
+
+impl RateLimitError {
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+      Self::FailedToCheck(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+  }
+}
+
+impl IntoResponse for RateLimitError {
+  fn into_response(self) -> Response {
+    warn!("Rate limit error: {:?}", self);
+
+    let status = self.status_code();
+    let error_response = match &self {
+      Self::TooManyRequests(retry_after) => ErrorResponse::new(
+        "Too many requests",
+        "sn::errors::rate_limit::too_many_requests",
+      )
+      .with_details(&format!("Retry in {} seconds", retry_after)),
+
+      Self::FailedToCheck(_) => ErrorResponse::new(
+        "Failed to check rate limit",
+        "sn::errors::rate_limit::failed_to_check",
+      ),
+    };
+
+    (status, error_response).into_response()
+  }
+}
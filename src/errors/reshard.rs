@@ -0,0 +1,44 @@
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::{db::reshard::ReshardPhase, errors::common::DatabaseError};
+
+/// Errors of the dialog resharding tool
+#[derive(Debug, Error, Diagnostic)]
+pub enum ReshardError {
+  #[error("Database error: {0}")]
+  #[diagnostic(code(sn::errors::reshard::database))]
+  Database(#[from] DatabaseError),
+
+  #[error("Query failed: {0}")]
+  #[diagnostic(code(sn::errors::reshard::query))]
+  Query(#[from] sqlx::Error),
+
+  #[error("Resharding job {0} is already in progress")]
+  #[diagnostic(
+    code(sn::errors::reshard::job_in_progress),
+    help("resume or abort the running job first")
+  )]
+  JobInProgress(i32),
+
+  #[error("No resharding job in progress")]
+  #[diagnostic(code(sn::errors::reshard::no_job_in_progress))]
+  NoJobInProgress,
+
+  #[error("Adding a shard requires {expected} shard urls, but {configured} are configured")]
+  #[diagnostic(
+    code(sn::errors::reshard::shard_count_mismatch),
+    help("append the url of the new shard to DIALOG_SHARD_URLS")
+  )]
+  ShardCountMismatch { expected: usize, configured: usize },
+
+  #[error("Dialog {0} differs between source and target shards")]
+  #[diagnostic(code(sn::errors::reshard::verification_failed))]
+  VerificationFailed(String),
+
+  #[error("Resharding job in {0:?} phase can not be aborted")]
+  #[diagnostic(code(sn::errors::reshard::cannot_abort))]
+  CannotAbort(ReshardPhase),
+}
+
+pub type ReshardResult<T> = Result<T, ReshardError>;
@@ -0,0 +1,143 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::dto::post::{PostAuthorDto, PostDto};
+
+const ENTRY_TITLE_MAX_CHARS: usize = 80;
+
+/// Render posts of the author as an Atom 1.0 feed
+///
+/// `base_url` is the public address of the service and is used to build absolute links
+pub fn render_feed(base_url: &str, author: &PostAuthorDto, posts: &[PostDto]) -> String {
+  let base_url = base_url.trim_end_matches('/');
+  let author_name = escape(&author.display_name());
+  let feed_url = format!("{}/user/{}/feed.atom", base_url, author.id);
+  let profile_url = format!("{}/api/user/get/{}", base_url, author.id);
+
+  let mut feed = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+  feed.push('\n');
+  feed.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+  feed.push('\n');
+  feed.push_str(&format!("  <id>{}</id>\n", escape(&feed_url)));
+  feed.push_str(&format!("  <title>{}</title>\n", author_name));
+  feed.push_str(&format!(
+    "  <updated>{}</updated>\n",
+    format_date(last_updated(posts).unwrap_or(DateTime::UNIX_EPOCH))
+  ));
+  feed.push_str(&format!(
+    "  <link rel=\"self\" type=\"application/atom+xml\" href=\"{}\"/>\n",
+    escape(&feed_url)
+  ));
+  feed.push_str(&format!(
+    "  <link rel=\"alternate\" href=\"{}\"/>\n",
+    escape(&profile_url)
+  ));
+  feed.push_str(&format!(
+    "  <author>\n    <name>{}</name>\n  </author>\n",
+    author_name
+  ));
+
+  for post in posts {
+    feed.push_str("  <entry>\n");
+    feed.push_str(&format!(
+      "    <id>{}/api/user/{}/posts#{}</id>\n",
+      escape(base_url),
+      post.author_id,
+      post.id
+    ));
+    feed.push_str(&format!(
+      "    <title>{}</title>\n",
+      escape(&entry_title(&post.content))
+    ));
+    feed.push_str(&format!(
+      "    <published>{}</published>\n",
+      format_date(post.created_at)
+    ));
+    feed.push_str(&format!(
+      "    <updated>{}</updated>\n",
+      format_date(post.updated_at)
+    ));
+    feed.push_str(&format!(
+      "    <content type=\"text\">{}</content>\n",
+      escape(&post.content)
+    ));
+    feed.push_str("  </entry>\n");
+  }
+
+  feed.push_str("</feed>\n");
+  feed
+}
+
+/// Time of the most recent change among the posts
+pub fn last_updated(posts: &[PostDto]) -> Option<DateTime<Utc>> {
+  posts.iter().map(|post| post.updated_at).max()
+}
+
+fn format_date(date: DateTime<Utc>) -> String {
+  date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn entry_title(content: &str) -> String {
+  let first_line = content.lines().next().unwrap_or_default().trim();
+
+  if first_line.chars().count() > ENTRY_TITLE_MAX_CHARS {
+    let mut title = first_line
+      .chars()
+      .take(ENTRY_TITLE_MAX_CHARS)
+      .collect::<String>();
+    title.push('…');
+    title
+  } else {
+    first_line.to_string()
+  }
+}
+
+fn escape(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+
+  for c in value.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&apos;"),
+      _ => escaped.push(c),
+    }
+  }
+
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dto::post::PostVisibility;
+  use chrono::TimeZone;
+
+  #[test]
+  fn feed_is_rendered_with_escaped_content() {
+    let author = PostAuthorDto {
+      id: 7,
+      first_name: Some("Tom".to_string()),
+      second_name: Some("& Jerry".to_string()),
+    };
+    let date = Utc.with_ymd_and_hms(2025, 3, 16, 10, 15, 0).unwrap();
+    let posts = vec![PostDto {
+      id: 42,
+      author_id: 7,
+      content: "<b>Hello</b>\nsecond line".to_string(),
+      visibility: PostVisibility::Public,
+      created_at: date,
+      updated_at: date,
+    }];
+
+    let feed = render_feed("http://localhost:4238/", &author, &posts);
+
+    assert!(feed.contains("<title>Tom &amp; Jerry</title>"));
+    assert!(feed.contains("<id>http://localhost:4238/user/7/feed.atom</id>"));
+    assert!(feed.contains("<id>http://localhost:4238/api/user/7/posts#42</id>"));
+    assert!(feed.contains("<title>&lt;b&gt;Hello&lt;/b&gt;</title>"));
+    assert!(feed.contains("<updated>2025-03-16T10:15:00Z</updated>"));
+    assert!(!feed.contains("<b>"));
+  }
+}
@@ -1 +1,2 @@
+pub mod atom;
 pub mod with_rejection;
@@ -1,39 +1,55 @@
 use app_state::AppState;
 use axum::{
-  extract::MatchedPath,
+  error_handling::HandleErrorLayer,
+  extract::{DefaultBodyLimit, MatchedPath},
   http::{
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER},
     HeaderName, HeaderValue, Method, Request,
   },
+  middleware,
+  response::Response,
   Router,
 };
-use config::AppConfig;
+use config::{AppConfig, DialogMode};
 use db::DataSource;
 use errors::common::InitError;
-use std::sync::Arc;
-use tower::ServiceBuilder;
+use middlewares::{
+  idempotency::{IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER},
+  metrics::track_http_metrics,
+  overload::{handle_overload, limit_route_concurrency, OverloadOptions, RouteLimits},
+  rate_limit::{API_KEY_HEADER, RATE_LIMIT_HEADERS},
+  read_your_writes::MIN_LSN_HEADER,
+};
+use services::{reconciliation::CounterReconciler, sweeper::MessageSweeper};
+use std::{sync::Arc, time::Duration};
+use telemetry::TRACE_ID_HEADER;
+use tokio::signal;
+use tower::{limit::GlobalConcurrencyLimitLayer, ServiceBuilder};
 use tower_http::{
   cors::CorsLayer,
+  limit::RequestBodyLimitLayer,
   request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
   trace::{self, TraceLayer},
 };
-use tracing::{info_span, Level};
+use tracing::{debug, info_span, Level, Span};
 
 pub mod app_state;
 pub mod config;
 pub mod db;
+pub mod dto;
 pub mod errors;
+pub mod services;
+pub mod telemetry;
 
 mod api;
-mod dto;
 mod helpers;
 mod middlewares;
 mod router;
-mod services;
 
-const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
 
-pub async fn app(app_config: AppConfig) -> miette::Result<Router> {
+/// Public API and the internal router serving `/metrics` on `METRICS_PORT`
+pub async fn app(app_config: AppConfig) -> miette::Result<(Router, Router)> {
   let cors = CorsLayer::new()
     .allow_origin(
       app_config
@@ -41,10 +57,151 @@ pub async fn app(app_config: AppConfig) -> miette::Result<Router> {
         .parse::<HeaderValue>()
         .map_err(InitError::CorsOrigin)?,
     )
-    .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+    .allow_methods([
+      Method::GET,
+      Method::POST,
+      Method::PUT,
+      Method::PATCH,
+      Method::DELETE,
+    ])
     .allow_credentials(true)
-    .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
+    .allow_headers([
+      AUTHORIZATION,
+      ACCEPT,
+      CONTENT_TYPE,
+      HeaderName::from_static(MIN_LSN_HEADER),
+      HeaderName::from_static(API_KEY_HEADER),
+      HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+    ])
+    .expose_headers(
+      [
+        HeaderName::from_static(MIN_LSN_HEADER),
+        HeaderName::from_static(REQUEST_ID_HEADER),
+        HeaderName::from_static(TRACE_ID_HEADER),
+        HeaderName::from_static(IDEMPOTENT_REPLAYED_HEADER),
+        RETRY_AFTER,
+      ]
+      .into_iter()
+      .chain(RATE_LIMIT_HEADERS.map(HeaderName::from_static))
+      .collect::<Vec<_>>(),
+    );
+
+  if app_config.dialog_mode == DialogMode::Remote && app_config.service_token_secret.is_none() {
+    return Err(InitError::MissingConfig("SERVICE_TOKEN_SECRET").into());
+  }
+
+  let overload = OverloadOptions::from(&app_config);
+  let app_state = init_state(app_config).await?;
+  if app_state.config.dialog_mode == DialogMode::Local {
+    spawn_dialog_jobs(&app_state);
+  }
+
+  let app_state = Arc::new(app_state);
+  let internal = router::create_internal_router(app_state.clone());
+  let app = with_overload_protection(router::create_router(app_state), overload).layer(cors);
+
+  Ok((with_request_tracing(app), internal))
+}
+
+/// App of the dialog service binary, serving the internal dialog API to the main server, and the
+/// internal router serving `/metrics` on `DIALOG_SERVICE_METRICS_PORT`
+pub async fn dialog_app(mut app_config: AppConfig) -> miette::Result<(Router, Router)> {
+  if app_config.service_token_secret.is_none() {
+    return Err(InitError::MissingConfig("SERVICE_TOKEN_SECRET").into());
+  }
+  // The dialog service itself always serves dialogs in process
+  app_config.dialog_mode = DialogMode::Local;
+
+  let overload = OverloadOptions::from(&app_config);
+  let app_state = init_state(app_config).await?;
+  spawn_dialog_jobs(&app_state);
+
+  let app_state = Arc::new(app_state);
+  let internal = router::create_internal_router(app_state.clone());
+  let app = with_overload_protection(router::create_dialog_router(app_state), overload);
+
+  Ok((with_request_tracing(app), internal))
+}
+
+async fn init_state(app_config: AppConfig) -> miette::Result<AppState> {
+  if app_config.migrate_on_startup {
+    db::migrations::up(&app_config.migrated_database_urls()).await?;
+  }
+
+  let metrics_handle = services::metrics::install_recorder().map_err(InitError::Metrics)?;
+  let db = DataSource::init(&app_config).await?;
+  db.pg.spawn_health_checks(Duration::from_secs(
+    app_config.database_replica_check_interval,
+  ));
+  db.dialog_shards.spawn_topology_refresh(
+    db.write(),
+    Duration::from_secs(app_config.dialog_topology_refresh_interval),
+  );
+
+  let primary = db.write();
+  let app_state = AppState::init(db, Arc::new(app_config), metrics_handle).await?;
+  app_state
+    .user_service
+    .cache()
+    .spawn_invalidation_listener(primary);
+  app_state
+    .jwt_service
+    .spawn_revocation_sync(Duration::from_secs(
+      app_state.config.jwt_revocation_sync_interval,
+    ));
 
+  Ok(app_state)
+}
+
+/// Background jobs of the dialog subsystem, run by the process serving dialogs
+fn spawn_dialog_jobs(app_state: &AppState) {
+  app_state
+    .dialog_service
+    .store()
+    .spawn_snapshots(Duration::from_secs(
+      app_state.config.memory_store_snapshot_interval,
+    ));
+  CounterReconciler::new(
+    app_state.counter_service.clone(),
+    app_state.dialog_service.store().clone(),
+    app_state.group_service.clone(),
+  )
+  .spawn(Duration::from_secs(
+    app_state.config.counters_reconcile_interval,
+  ));
+  MessageSweeper::new(
+    app_state.dialog_service.store().clone(),
+    app_state.group_service.clone(),
+    app_state.counter_service.clone(),
+  )
+  .spawn(Duration::from_secs(app_state.config.message_sweep_interval));
+}
+
+/// Reject requests over the global and per route concurrency limits, past the timeout or with
+/// too large bodies instead of queueing them
+fn with_overload_protection(app: Router, options: OverloadOptions) -> Router {
+  let route_limits = Arc::new(RouteLimits::new(&options));
+
+  let middleware_stack = ServiceBuilder::new()
+    .layer(HandleErrorLayer::new(handle_overload))
+    .load_shed()
+    .layer(GlobalConcurrencyLimitLayer::new(
+      options.max_concurrent_requests,
+    ))
+    .timeout(options.request_timeout)
+    // Extractors enforce their own default limit, aligned with the one checked upfront
+    .layer(DefaultBodyLimit::max(options.max_request_body_size))
+    .layer(RequestBodyLimitLayer::new(options.max_request_body_size));
+
+  app
+    .layer(middleware::from_fn_with_state(
+      route_limits,
+      limit_route_concurrency,
+    ))
+    .layer(middleware_stack)
+}
+
+fn with_request_tracing(app: Router) -> Router {
   let x_request_id = HeaderName::from_static(REQUEST_ID_HEADER);
 
   let middleware_stack = ServiceBuilder::new()
@@ -62,23 +219,233 @@ pub async fn app(app_config: AppConfig) -> miette::Result<Router> {
             .extensions()
             .get::<MatchedPath>()
             .map(MatchedPath::as_str);
-          info_span!(
+          let span = info_span!(
               "http_request",
               matched_path,
               path =? request.uri(),
               method =? request.method(),
               request_id =? request_id,
-          )
+              trace_id = tracing::field::Empty,
+          );
+          telemetry::set_parent_from_headers(&span, request.headers());
+          if let Some(trace_id) = telemetry::trace_id(&span) {
+            span.record("trace_id", trace_id);
+          }
+
+          span
         })
         .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
     )
-    .layer(cors)
-    .layer(PropagateRequestIdLayer::new(x_request_id));
+    .layer(PropagateRequestIdLayer::new(x_request_id))
+    .layer(middleware::from_fn(track_http_metrics))
+    .layer(middleware::map_response(set_trace_id_header));
+
+  app.layer(middleware_stack)
+}
+
+/// Return the trace id of the request next to its request id
+async fn set_trace_id_header(mut response: Response) -> Response {
+  if let Some(trace_id) = telemetry::trace_id(&Span::current()) {
+    if let Ok(value) = HeaderValue::from_str(&trace_id) {
+      response.headers_mut().insert(TRACE_ID_HEADER, value);
+    }
+  }
+
+  response
+}
+
+/// Resolves on Ctrl+C or SIGTERM, used for graceful shutdown of the binaries
+pub async fn shutdown_signal() {
+  let ctrl_c = async {
+    signal::ctrl_c()
+      .await
+      .expect("failed to install Ctrl+C handler");
+  };
+
+  #[cfg(unix)]
+  let terminate = async {
+    signal::unix::signal(signal::unix::SignalKind::terminate())
+      .expect("failed to install signal handler")
+      .recv()
+      .await;
+  };
+
+  #[cfg(not(unix))]
+  let terminate = std::future::pending::<()>();
+
+  tokio::select! {
+      _ = ctrl_c => {},
+      _ = terminate => {},
+  }
+
+  debug!("signal received, starting graceful shutdown");
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::{
+    body::Body,
+    http::{header::RETRY_AFTER, StatusCode},
+    routing::{get, post},
+  };
+  use tokio::sync::Notify;
+  use tower::ServiceExt;
+
+  const OPTIONS: OverloadOptions = OverloadOptions {
+    max_concurrent_requests: 16,
+    route_concurrency_limit: 16,
+    route_concurrency_limits: Vec::new(),
+    request_timeout: Duration::from_secs(10),
+    max_request_body_size: 1024,
+  };
+
+  /// `/blocked` and `/held` hold their requests until released, `/free` answers right away
+  fn app(options: OverloadOptions) -> (Router, Arc<Notify>, Arc<Notify>) {
+    let entered = Arc::new(Notify::new());
+    let release = Arc::new(Notify::new());
+    let (on_enter, on_release) = (entered.clone(), release.clone());
+    let blocked = get(move || async move {
+      // Waiting before telling the test, so that releasing every request reaches this one
+      let released = on_release.notified();
+      tokio::pin!(released);
+      released.as_mut().enable();
+      on_enter.notify_one();
+      released.await;
+    });
+
+    let router = Router::new()
+      .route("/blocked", blocked.clone())
+      .route("/held", blocked)
+      .route("/free", get(|| async {}))
+      .route("/echo", post(|body: String| async move { body }));
+
+    (with_overload_protection(router, options), entered, release)
+  }
+
+  async fn status(app: &Router, uri: &str) -> (StatusCode, Option<HeaderValue>) {
+    let response = app
+      .clone()
+      .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+      .await
+      .unwrap();
+
+    (
+      response.status(),
+      response.headers().get(RETRY_AFTER).cloned(),
+    )
+  }
+
+  #[tokio::test]
+  async fn sheds_requests_over_the_route_concurrency_limit() {
+    let (app, entered, release) = app(OverloadOptions {
+      route_concurrency_limit: 1,
+      ..OPTIONS
+    });
+    let in_flight = tokio::spawn({
+      let app = app.clone();
+      async move { status(&app, "/blocked").await }
+    });
+    entered.notified().await;
+
+    let (shed, retry_after) = status(&app, "/blocked").await;
+    assert_eq!(shed, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(retry_after, Some(HeaderValue::from(1)));
+    assert_eq!(
+      status(&app, "/free").await.0,
+      StatusCode::OK,
+      "other routes have their own limit"
+    );
+
+    release.notify_one();
+    assert_eq!(in_flight.await.unwrap().0, StatusCode::OK);
+    assert_eq!(
+      status(&app, "/free").await.0,
+      StatusCode::OK,
+      "permits are released"
+    );
+  }
+
+  #[tokio::test]
+  async fn routes_shed_over_their_own_limits() {
+    let (app, entered, release) = app(OverloadOptions {
+      route_concurrency_limits: vec!["/blocked=1".parse().unwrap(), "/held=2".parse().unwrap()],
+      ..OPTIONS
+    });
+    let mut in_flight = Vec::new();
+    for uri in ["/blocked", "/held", "/held"] {
+      in_flight.push(tokio::spawn({
+        let app = app.clone();
+        async move { status(&app, uri).await }
+      }));
+      entered.notified().await;
+    }
+
+    assert_eq!(
+      status(&app, "/blocked").await.0,
+      StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(
+      status(&app, "/held").await.0,
+      StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    release.notify_waiters();
+    for request in in_flight {
+      assert_eq!(request.await.unwrap().0, StatusCode::OK);
+    }
+  }
+
+  #[tokio::test]
+  async fn sheds_requests_over_the_global_concurrency_limit() {
+    let (app, entered, release) = app(OverloadOptions {
+      max_concurrent_requests: 1,
+      ..OPTIONS
+    });
+    let in_flight = tokio::spawn({
+      let app = app.clone();
+      async move { status(&app, "/blocked").await }
+    });
+    entered.notified().await;
+
+    assert_eq!(
+      status(&app, "/free").await.0,
+      StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    release.notify_one();
+    assert_eq!(in_flight.await.unwrap().0, StatusCode::OK);
+    assert_eq!(status(&app, "/free").await.0, StatusCode::OK);
+  }
+
+  #[tokio::test]
+  async fn times_out_slow_requests() {
+    let (app, _, _) = app(OverloadOptions {
+      request_timeout: Duration::from_millis(20),
+      ..OPTIONS
+    });
 
-  let db = DataSource::init(&app_config.database_url, &app_config.redis_url).await?;
-  let app_state = AppState::init(db, Arc::new(app_config)).await?;
+    let (timed_out, retry_after) = status(&app, "/blocked").await;
+    assert_eq!(timed_out, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(retry_after.is_some());
+  }
 
-  let app = router::create_router(Arc::new(app_state)).layer(middleware_stack);
+  #[tokio::test]
+  async fn rejects_too_large_bodies() {
+    let (app, _, _) = app(OverloadOptions {
+      max_request_body_size: 8,
+      ..OPTIONS
+    });
+    let echo = |body: &'static str| {
+      app
+        .clone()
+        .oneshot(Request::post("/echo").body(Body::from(body)).unwrap())
+    };
 
-  Ok(app)
+    assert_eq!(echo("small").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(
+      echo("larger than the limit").await.unwrap().status(),
+      StatusCode::PAYLOAD_TOO_LARGE
+    );
+  }
 }
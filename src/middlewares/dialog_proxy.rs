@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{Request, State},
+  middleware::Next,
+  response::Response,
+  Extension,
+};
+
+use crate::{app_state::AppState, dto::user::UserDto, errors::dialog::DialogResult};
+
+/// Forward the request to the dialog service in remote dialog mode, otherwise handle it in process.
+///
+/// Must run after the user authentication
+#[tracing::instrument(skip(app_state, user, req, next))]
+#[axum::debug_middleware]
+pub async fn proxy_dialog_requests(
+  State(app_state): State<Arc<AppState>>,
+  Extension(user): Extension<UserDto>,
+  req: Request,
+  next: Next,
+) -> DialogResult<Response> {
+  match &app_state.dialog_proxy {
+    Some(dialog_proxy) => dialog_proxy.forward(user.id, req).await,
+    None => Ok(next.run(req).await),
+  }
+}
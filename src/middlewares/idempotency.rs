@@ -0,0 +1,263 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+  body::{to_bytes, Body},
+  extract::{Request, State},
+  http::{HeaderValue, Method, StatusCode},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::{
+  app_state::AppState,
+  errors::idempotency::{IdempotencyError, IdempotencyResult},
+  middlewares::rate_limit::{client_key, RouteGroup},
+  services::idempotency::{Claim, IdempotencyService, StoredResponse},
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on the stored responses returned to the retries
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Serve `POST` requests carrying an `Idempotency-Key` once per client and key, retries get the
+/// stored response for the idempotency TTL of the route group.
+///
+/// Must run after the authentication to scope the keys by user. Failed requests, `5xx` and `429`,
+/// release their key, and requests are not deduplicated while Redis is unavailable
+#[tracing::instrument(skip(app_state, req, next))]
+#[axum::debug_middleware]
+pub async fn idempotency(
+  State((app_state, group)): State<(Arc<AppState>, RouteGroup)>,
+  req: Request,
+  next: Next,
+) -> IdempotencyResult<Response> {
+  if req.method() != Method::POST {
+    return Ok(next.run(req).await);
+  }
+
+  let client = client_key(&req, &app_state.config);
+  let options = IdempotencyOptions {
+    ttl: group.idempotency_ttl(&app_state.config),
+    max_body_size: app_state.config.max_request_body_size,
+  };
+  serve_once(&app_state.idempotency_service, &client, options, req, next).await
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IdempotencyOptions {
+  ttl: Duration,
+  max_body_size: usize,
+}
+
+async fn serve_once(
+  service: &IdempotencyService,
+  client: &str,
+  options: IdempotencyOptions,
+  req: Request,
+  next: Next,
+) -> IdempotencyResult<Response> {
+  let Some(key) = req.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+    return Ok(next.run(req).await);
+  };
+  let key = key
+    .to_str()
+    .ok()
+    .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LENGTH)
+    .ok_or(IdempotencyError::InvalidKey)?
+    .to_string();
+
+  let (parts, body) = req.into_parts();
+  let body = to_bytes(body, options.max_body_size)
+    .await
+    .map_err(IdempotencyError::FailedToReadBody)?;
+  let request_hash = request_hash(parts.uri.path(), &body);
+  let req = Request::from_parts(parts, Body::from(body));
+
+  match service.claim(client, &key, &request_hash).await {
+    Ok(Claim::Acquired) => {}
+    Ok(Claim::Replay(stored)) => {
+      metrics::counter!("idempotent_replays_total").increment(1);
+      let mut response = stored.into_response();
+      response
+        .headers_mut()
+        .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+      return Ok(response);
+    }
+    Err(IdempotencyError::FailedToStore(e)) => {
+      warn!("Serving the request without idempotency: {}", e);
+      return Ok(next.run(req).await);
+    }
+    Err(e) => return Err(e),
+  }
+
+  let response = next.run(req).await;
+  let status = response.status();
+  if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+    service.release(client, &key).await;
+    return Ok(response);
+  }
+
+  let (parts, body) = response.into_parts();
+  let body = match to_bytes(body, usize::MAX).await {
+    Ok(body) => body,
+    Err(e) => {
+      service.release(client, &key).await;
+      return Err(IdempotencyError::FailedToReadBody(e));
+    }
+  };
+  service
+    .complete(
+      client,
+      &key,
+      &request_hash,
+      StoredResponse::new(&parts, &body),
+      options.ttl,
+    )
+    .await;
+
+  Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Retries have to repeat the route and the body of the request that claimed the key
+fn request_hash(path: &str, body: &[u8]) -> String {
+  format!(
+    "{:x}",
+    Sha256::new()
+      .chain_update(path)
+      .chain_update(body)
+      .finalize()
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use axum::{middleware, routing::post, Router};
+  use clap::Parser;
+  use tower::ServiceExt;
+
+  use super::*;
+  use crate::{
+    config::AppConfig,
+    db::{testing::FakeRedis, RedisClient},
+  };
+
+  const CLIENT: &str = "user:1";
+  const OPTIONS: IdempotencyOptions = IdempotencyOptions {
+    ttl: Duration::from_secs(60),
+    max_body_size: 1024,
+  };
+
+  /// `/created` answers `201` and `/failed` answers `500`, both counting their calls
+  async fn app(redis: &FakeRedis) -> (Router, IdempotencyService, Arc<AtomicUsize>) {
+    let config = AppConfig::parse_from([
+      "social_network",
+      "--database-url",
+      "postgres://localhost/app",
+      "--redis-url",
+      &redis.url,
+      "--jwt-secret",
+      "secret",
+    ]);
+    let client = RedisClient::connect(&redis.url, Default::default())
+      .await
+      .unwrap();
+    let service = IdempotencyService::new(client, &config);
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let (created_calls, failed_calls) = (calls.clone(), calls.clone());
+    let layer_service = service.clone();
+    let router = Router::new()
+      .route(
+        "/created",
+        post(move |body: String| async move {
+          created_calls.fetch_add(1, Ordering::SeqCst);
+          (StatusCode::CREATED, body)
+        }),
+      )
+      .route(
+        "/failed",
+        post(move || async move {
+          failed_calls.fetch_add(1, Ordering::SeqCst);
+          StatusCode::INTERNAL_SERVER_ERROR
+        }),
+      )
+      .layer(middleware::from_fn(move |req: Request, next: Next| {
+        let service = layer_service.clone();
+        async move { serve_once(&service, CLIENT, OPTIONS, req, next).await }
+      }));
+
+    (router, service, calls)
+  }
+
+  async fn send(app: &Router, uri: &str, key: &str, body: &'static str) -> Response {
+    app
+      .clone()
+      .oneshot(
+        Request::post(uri)
+          .header(IDEMPOTENCY_KEY_HEADER, key)
+          .body(Body::from(body))
+          .unwrap(),
+      )
+      .await
+      .unwrap()
+  }
+
+  #[tokio::test]
+  async fn retries_are_replayed_and_reused_keys_rejected() {
+    let redis = FakeRedis::start().await;
+    let (app, _, calls) = app(&redis).await;
+
+    let first = send(&app, "/created", "key", "message").await;
+    assert_eq!(first.status(), StatusCode::CREATED);
+    assert!(first.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+
+    let retry = send(&app, "/created", "key", "message").await;
+    assert_eq!(retry.status(), StatusCode::CREATED);
+    assert_eq!(
+      retry.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(),
+      "true"
+    );
+    let body = to_bytes(retry.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"message");
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let reused = send(&app, "/created", "key", "another message").await;
+    assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let other_key = send(&app, "/created", "other-key", "message").await;
+    assert_eq!(other_key.status(), StatusCode::CREATED);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+  }
+
+  #[tokio::test]
+  async fn retries_of_requests_in_progress_are_rejected() {
+    let redis = FakeRedis::start().await;
+    let (app, service, calls) = app(&redis).await;
+    service
+      .claim(CLIENT, "key", &request_hash("/created", b"message"))
+      .await
+      .unwrap();
+
+    let retry = send(&app, "/created", "key", "message").await;
+    assert_eq!(retry.status(), StatusCode::CONFLICT);
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+  }
+
+  #[tokio::test]
+  async fn failed_requests_release_their_key() {
+    let redis = FakeRedis::start().await;
+    let (app, _, calls) = app(&redis).await;
+
+    for _ in 0..2 {
+      let response = send(&app, "/failed", "key", "").await;
+      assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+  }
+}
@@ -0,0 +1,74 @@
+use std::time::Instant;
+
+use axum::{
+  extract::{MatchedPath, Request},
+  middleware::Next,
+  response::Response,
+};
+
+/// Record rate, errors and duration of the requests labeled by route and status
+pub async fn track_http_metrics(req: Request, next: Next) -> Response {
+  let started = Instant::now();
+  // Unmatched paths share one label to keep the number of series bounded
+  let path = req
+    .extensions()
+    .get::<MatchedPath>()
+    .map_or("unmatched".to_string(), |path| path.as_str().to_string());
+  let method = req.method().to_string();
+
+  let response = next.run(req).await;
+
+  let status = response.status();
+  let labels = [
+    ("method", method),
+    ("path", path),
+    ("status", status.as_u16().to_string()),
+  ];
+  metrics::counter!("http_requests_total", &labels).increment(1);
+  if status.is_server_error() {
+    metrics::counter!("http_requests_errors_total", &labels).increment(1);
+  }
+  metrics::histogram!("http_request_duration_seconds", &labels)
+    .record(started.elapsed().as_secs_f64());
+
+  response
+}
+
+#[cfg(test)]
+mod tests {
+  use axum::{body::Body, middleware, routing::get, Router};
+  use metrics_exporter_prometheus::PrometheusBuilder;
+  use tower::ServiceExt;
+
+  use super::*;
+
+  #[test]
+  fn requests_are_labeled_by_route() {
+    let recorder = PrometheusBuilder::new().build_recorder();
+    let handle = recorder.handle();
+    let app = Router::new()
+      .route("/users/{id}", get(|| async {}))
+      .layer(middleware::from_fn(track_http_metrics));
+
+    metrics::with_local_recorder(&recorder, || {
+      futures::executor::block_on(async {
+        for uri in ["/users/7", "/users/8", "/missing/7"] {
+          app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        }
+      })
+    });
+
+    let rendered = handle.render();
+    assert!(
+      rendered.contains(r#"http_requests_total{method="GET",path="/users/{id}",status="200"} 2"#)
+    );
+    assert!(
+      rendered.contains(r#"http_requests_total{method="GET",path="unmatched",status="404"} 1"#)
+    );
+    assert!(!rendered.contains("/users/7"));
+  }
+}
@@ -1 +1,8 @@
+pub mod dialog_proxy;
+pub mod idempotency;
+pub mod metrics;
+pub mod overload;
+pub mod rate_limit;
+pub mod read_your_writes;
+pub mod service_auth;
 pub mod user_auth;
@@ -0,0 +1,119 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex, PoisonError},
+  time::Duration,
+};
+
+use axum::{
+  extract::{MatchedPath, Request, State},
+  http::{header::RETRY_AFTER, HeaderValue},
+  middleware::Next,
+  response::{IntoResponse, Response},
+  BoxError,
+};
+use tokio::sync::Semaphore;
+use tower::{load_shed::error::Overloaded, timeout::error::Elapsed};
+
+use crate::{
+  config::{AppConfig, RouteLimit},
+  errors::overload::OverloadError,
+};
+
+/// Seconds shed clients are asked to wait before retrying
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Limits protecting the server from more requests than it can serve in time
+#[derive(Debug, Clone)]
+pub struct OverloadOptions {
+  pub max_concurrent_requests: usize,
+  pub route_concurrency_limit: usize,
+  pub route_concurrency_limits: Vec<RouteLimit>,
+  pub request_timeout: Duration,
+  pub max_request_body_size: usize,
+}
+
+impl From<&AppConfig> for OverloadOptions {
+  fn from(config: &AppConfig) -> Self {
+    Self {
+      max_concurrent_requests: config.max_concurrent_requests,
+      route_concurrency_limit: config.route_concurrency_limit,
+      route_concurrency_limits: config.route_concurrency_limits.clone(),
+      request_timeout: Duration::from_millis(config.request_timeout),
+      max_request_body_size: config.max_request_body_size,
+    }
+  }
+}
+
+/// Permits of every route by its routed path, created on its first request
+#[derive(Debug)]
+pub struct RouteLimits {
+  default_limit: usize,
+  limits: HashMap<String, usize>,
+  permits: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl RouteLimits {
+  pub fn new(options: &OverloadOptions) -> Self {
+    Self {
+      default_limit: options.route_concurrency_limit,
+      limits: options
+        .route_concurrency_limits
+        .iter()
+        .map(|route| (route.path.clone(), route.limit))
+        .collect(),
+      permits: Mutex::default(),
+    }
+  }
+
+  fn permits(&self, path: &str) -> Arc<Semaphore> {
+    let mut permits = self.permits.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(semaphore) = permits.get(path) {
+      return semaphore.clone();
+    }
+
+    let limit = self.limits.get(path).copied().unwrap_or(self.default_limit);
+    let semaphore = Arc::new(Semaphore::new(limit));
+    permits.insert(path.to_string(), semaphore.clone());
+    semaphore
+  }
+}
+
+/// Shed requests over the concurrency limit of their route instead of queueing them, unrouted
+/// requests are only bound by the global limit
+#[axum::debug_middleware]
+pub async fn limit_route_concurrency(
+  State(limits): State<Arc<RouteLimits>>,
+  req: Request,
+  next: Next,
+) -> Response {
+  let Some(path) = req.extensions().get::<MatchedPath>() else {
+    return next.run(req).await;
+  };
+  let Ok(_permit) = limits.permits(path.as_str()).try_acquire_owned() else {
+    return shed(OverloadError::Overloaded, "overloaded");
+  };
+
+  next.run(req).await
+}
+
+/// Answer requests shed over the concurrency limits or past the timeout with `503`
+pub async fn handle_overload(error: BoxError) -> Response {
+  if error.is::<Overloaded>() {
+    shed(OverloadError::Overloaded, "overloaded")
+  } else if error.is::<Elapsed>() {
+    shed(OverloadError::TimedOut, "timed_out")
+  } else {
+    OverloadError::Unhandled(error.to_string()).into_response()
+  }
+}
+
+fn shed(error: OverloadError, reason: &'static str) -> Response {
+  metrics::counter!("http_requests_shed_total", "reason" => reason).increment(1);
+
+  let mut response = error.into_response();
+  response
+    .headers_mut()
+    .insert(RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS));
+
+  response
+}
@@ -0,0 +1,343 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+  extract::{ConnectInfo, Request, State},
+  http::{header::RETRY_AFTER, HeaderMap, HeaderValue},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::{
+  app_state::AppState,
+  config::{AppConfig, Quota},
+  dto::user::UserDto,
+  errors::rate_limit::RateLimitError,
+  services::rate_limit::{RateLimitDecision, RateLimitService},
+};
+
+/// Key of an integration listed in `API_KEYS`, its requests share one quota whatever their IP
+pub const API_KEY_HEADER: &str = "x-api-key";
+pub const RATE_LIMIT_HEADERS: [&str; 3] =
+  ["ratelimit-limit", "ratelimit-remaining", "ratelimit-reset"];
+
+/// Routes sharing a quota
+#[derive(Debug, Clone, Copy)]
+pub enum RouteGroup {
+  /// `register` and `login`, anonymous by nature
+  Auth,
+  Api,
+}
+
+impl RouteGroup {
+  fn name(&self) -> &'static str {
+    match self {
+      Self::Auth => "auth",
+      Self::Api => "api",
+    }
+  }
+
+  fn quota(&self, config: &AppConfig) -> Quota {
+    match self {
+      Self::Auth => config.rate_limit_auth,
+      Self::Api => config.rate_limit_api,
+    }
+  }
+
+  /// Time the responses of the group are replayed for retries with the same `Idempotency-Key`
+  pub fn idempotency_ttl(&self, config: &AppConfig) -> Duration {
+    Duration::from_secs(match self {
+      Self::Auth => config.idempotency_auth_ttl,
+      Self::Api => config.idempotency_ttl,
+    })
+  }
+}
+
+/// Limit requests of the route group per user, API key or client IP with token buckets in Redis.
+///
+/// Must run after the authentication to key the requests by user. Requests pass when Redis
+/// is unavailable
+#[tracing::instrument(skip(app_state, req, next))]
+#[axum::debug_middleware]
+pub async fn rate_limit(
+  State((app_state, group)): State<(Arc<AppState>, RouteGroup)>,
+  req: Request,
+  next: Next,
+) -> Response {
+  let quota = group.quota(&app_state.config);
+  if !quota.is_enabled() {
+    return next.run(req).await;
+  }
+
+  let client = client_key(&req, &app_state.config);
+  limit_requests(
+    &app_state.rate_limit_service,
+    group,
+    quota,
+    &client,
+    req,
+    next,
+  )
+  .await
+}
+
+async fn limit_requests(
+  service: &RateLimitService,
+  group: RouteGroup,
+  quota: Quota,
+  client: &str,
+  req: Request,
+  next: Next,
+) -> Response {
+  let decision = match service.check(group.name(), client, quota).await {
+    Ok(decision) => decision,
+    Err(e) => {
+      warn!("Letting the request through without rate limit: {}", e);
+      return next.run(req).await;
+    }
+  };
+
+  let mut response = if decision.allowed {
+    next.run(req).await
+  } else {
+    metrics::counter!("rate_limit_rejections_total", "group" => group.name()).increment(1);
+    let mut response = RateLimitError::TooManyRequests(decision.retry_after).into_response();
+    response
+      .headers_mut()
+      .insert(RETRY_AFTER, HeaderValue::from(decision.retry_after));
+    response
+  };
+  set_rate_limit_headers(response.headers_mut(), &decision);
+
+  response
+}
+
+/// Key of the client sending the request, its user, API key or IP
+pub fn client_key(req: &Request, config: &AppConfig) -> String {
+  let extensions = req.extensions();
+  let user = extensions
+    .get::<UserDto>()
+    .or_else(|| extensions.get::<Option<UserDto>>().and_then(Option::as_ref));
+  if let Some(user) = user {
+    return format!("user:{}", user.id);
+  }
+
+  if let Some(api_key) = req
+    .headers()
+    .get(API_KEY_HEADER)
+    .and_then(|value| value.to_str().ok())
+    .filter(|api_key| config.api_keys.iter().any(|known| known == api_key))
+  {
+    return format!("api_key:{:x}", Sha256::digest(api_key.as_bytes()));
+  }
+
+  let forwarded_for = config
+    .trust_forwarded_for
+    .then(|| req.headers().get("x-forwarded-for"))
+    .flatten()
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.split(',').next())
+    .map(|ip| ip.trim().to_string());
+  let ip = forwarded_for
+    .or_else(|| {
+      extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip().to_string())
+    })
+    .unwrap_or_else(|| "unknown".to_string());
+
+  format!("ip:{}", ip)
+}
+
+fn set_rate_limit_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+  let [limit, remaining, reset] = RATE_LIMIT_HEADERS;
+  headers.insert(limit, HeaderValue::from(decision.limit));
+  headers.insert(remaining, HeaderValue::from(decision.remaining));
+  headers.insert(reset, HeaderValue::from(decision.reset));
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use axum::{
+    body::{to_bytes, Body},
+    http::StatusCode,
+    middleware,
+    routing::get,
+    Router,
+  };
+  use clap::Parser;
+  use tower::ServiceExt;
+
+  use super::*;
+  use crate::db::{testing::FakeRedis, RedisClient, RedisOptions};
+
+  const API_KEY: &str = "integration-key";
+  const QUOTA: Quota = Quota {
+    requests: 5,
+    period: Duration::from_secs(60),
+  };
+
+  fn config(trust_forwarded_for: bool) -> AppConfig {
+    let mut args = vec![
+      "social_network",
+      "--database-url",
+      "postgres://localhost/app",
+      "--redis-url",
+      "redis://localhost",
+      "--jwt-secret",
+      "secret",
+      "--api-keys",
+      API_KEY,
+    ];
+    if trust_forwarded_for {
+      args.push("--trust-forwarded-for");
+    }
+    AppConfig::parse_from(args)
+  }
+
+  fn request(user: Option<UserDto>, api_key: Option<&str>, forwarded_for: Option<&str>) -> Request {
+    let mut builder = Request::get("/");
+    if let Some(api_key) = api_key {
+      builder = builder.header(API_KEY_HEADER, api_key);
+    }
+    if let Some(forwarded_for) = forwarded_for {
+      builder = builder.header("x-forwarded-for", forwarded_for);
+    }
+
+    let mut req = builder.body(Body::empty()).unwrap();
+    req
+      .extensions_mut()
+      .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))));
+    if let Some(user) = user {
+      req.extensions_mut().insert(Some(user));
+    }
+    req
+  }
+
+  #[test]
+  fn clients_are_keyed_by_user_then_api_key_then_ip() {
+    let config = config(true);
+    let user = UserDto {
+      id: 7,
+      ..Default::default()
+    };
+    let forwarded_for = Some("203.0.113.9, 10.0.0.2");
+
+    let by_user = request(Some(user), Some(API_KEY), forwarded_for);
+    assert_eq!(client_key(&by_user, &config), "user:7");
+
+    let by_api_key = request(None, Some(API_KEY), forwarded_for);
+    assert_eq!(
+      client_key(&by_api_key, &config),
+      format!("api_key:{:x}", Sha256::digest(API_KEY.as_bytes()))
+    );
+
+    let unknown_api_key = request(None, Some("guessed-key"), forwarded_for);
+    assert_eq!(client_key(&unknown_api_key, &config), "ip:203.0.113.9");
+
+    let by_peer = request(None, None, None);
+    assert_eq!(client_key(&by_peer, &config), "ip:10.0.0.1");
+
+    let mut unknown = request(None, None, None);
+    unknown.extensions_mut().remove::<ConnectInfo<SocketAddr>>();
+    assert_eq!(client_key(&unknown, &config), "ip:unknown");
+  }
+
+  #[test]
+  fn forwarded_for_is_ignored_unless_trusted() {
+    let req = request(None, None, Some("203.0.113.9"));
+    assert_eq!(client_key(&req, &config(false)), "ip:10.0.0.1");
+  }
+
+  async fn app(redis: &FakeRedis) -> (Router, Arc<AtomicUsize>) {
+    let client = RedisClient::connect(
+      &redis.url,
+      RedisOptions {
+        response_timeout: Duration::from_millis(100),
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+    let service = Arc::new(RateLimitService::new(client));
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let handler_calls = calls.clone();
+    let router = Router::new()
+      .route(
+        "/",
+        get(move || async move {
+          handler_calls.fetch_add(1, Ordering::SeqCst);
+          StatusCode::OK
+        }),
+      )
+      .layer(middleware::from_fn(move |req: Request, next: Next| {
+        let service = service.clone();
+        async move { limit_requests(&service, RouteGroup::Api, QUOTA, "user:1", req, next).await }
+      }));
+
+    (router, calls)
+  }
+
+  async fn send(app: &Router) -> Response {
+    app
+      .clone()
+      .oneshot(Request::get("/").body(Body::empty()).unwrap())
+      .await
+      .unwrap()
+  }
+
+  fn header<'a>(response: &'a Response, name: &str) -> &'a str {
+    response.headers()[name].to_str().unwrap()
+  }
+
+  #[tokio::test]
+  async fn allowed_requests_carry_the_quota() {
+    let redis = FakeRedis::start().await;
+    let (app, calls) = app(&redis).await;
+    redis.reply_to_scripts(Some(&[1, 4, 1000, 0]));
+
+    let response = send(&app).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "ratelimit-limit"), "5");
+    assert_eq!(header(&response, "ratelimit-remaining"), "4");
+    assert_eq!(header(&response, "ratelimit-reset"), "1");
+    assert!(response.headers().get(RETRY_AFTER).is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+  }
+
+  #[tokio::test]
+  async fn exhausted_quotas_are_rejected() {
+    let redis = FakeRedis::start().await;
+    let (app, calls) = app(&redis).await;
+    redis.reply_to_scripts(Some(&[0, 0, 60000, 2500]));
+
+    let response = send(&app).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(&response, RETRY_AFTER.as_str()), "3");
+    assert_eq!(header(&response, "ratelimit-limit"), "5");
+    assert_eq!(header(&response, "ratelimit-remaining"), "0");
+    assert_eq!(header(&response, "ratelimit-reset"), "60");
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "sn::errors::rate_limit::too_many_requests");
+    assert_eq!(body["details"], "Retry in 3 seconds");
+  }
+
+  #[tokio::test]
+  async fn requests_pass_while_redis_is_down() {
+    let redis = FakeRedis::start().await;
+    let (app, calls) = app(&redis).await;
+    redis.set_responsive(false);
+
+    let response = send(&app).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("ratelimit-limit").is_none());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+  }
+}
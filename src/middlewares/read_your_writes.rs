@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{Request, State},
+  http::HeaderValue,
+  middleware::Next,
+  response::Response,
+};
+use tracing::warn;
+
+use crate::{
+  app_state::AppState,
+  db::replicas::{Lsn, ReadConsistency},
+};
+
+/// Log position of the last write of the client, returned after writes and sent back with later requests
+pub const MIN_LSN_HEADER: &str = "x-min-lsn";
+
+/// Serve reads of the client only from replicas that have replayed its previous writes.
+///
+/// Requests that touched the primary get its current log position back in the `x-min-lsn` header
+#[tracing::instrument(skip(app_state, req, next))]
+#[axum::debug_middleware]
+pub async fn read_your_writes(
+  State(app_state): State<Arc<AppState>>,
+  req: Request,
+  next: Next,
+) -> Response {
+  let min_lsn = req
+    .headers()
+    .get(MIN_LSN_HEADER)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.parse::<Lsn>().ok());
+
+  let consistency = Arc::new(ReadConsistency::new(min_lsn));
+  let mut response = consistency.clone().scope(next.run(req)).await;
+
+  if consistency.used_primary() && response.status().is_success() {
+    match app_state.ds.pg.current_lsn().await {
+      Ok(lsn) => {
+        let lsn = min_lsn.map_or(lsn, |min_lsn| min_lsn.max(lsn));
+        if let Ok(value) = HeaderValue::from_str(&lsn.to_string()) {
+          response.headers_mut().insert(MIN_LSN_HEADER, value);
+        }
+      }
+      Err(e) => warn!("Failed to get primary log position: {}", e),
+    }
+  }
+
+  response
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use axum::{
+  extract::{Request, State},
+  http::header,
+  middleware::Next,
+  response::IntoResponse,
+};
+
+use crate::{app_state::AppState, errors::auth::AuthError};
+
+/// User on whose behalf an internal service is called
+#[derive(Debug, Clone, Copy)]
+pub struct ServiceCaller {
+  pub user_id: i32,
+}
+
+#[tracing::instrument(skip(app_state, req, next))]
+#[axum::debug_middleware]
+pub async fn require_service_authentication(
+  State(app_state): State<Arc<AppState>>,
+  mut req: Request,
+  next: Next,
+) -> Result<impl IntoResponse, AuthError> {
+  let token = req
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|auth_header| auth_header.to_str().ok())
+    .and_then(|auth_value| auth_value.strip_prefix("Bearer "))
+    .ok_or(AuthError::NoToken("service"))?;
+
+  let user_id = app_state
+    .jwt_service
+    .decode_service_token(token)
+    .map_err(|e| AuthError::InvalidToken("service", e))?;
+
+  req.extensions_mut().insert(ServiceCaller { user_id });
+  Ok(next.run(req).await)
+}
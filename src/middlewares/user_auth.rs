@@ -2,14 +2,14 @@ use std::sync::Arc;
 
 use axum::{
   extract::{Request, State},
-  http::header,
+  http::{header, HeaderMap},
   middleware::Next,
   response::IntoResponse,
 };
 
 use crate::{
   app_state::AppState,
-  dto::user::AuthTokens,
+  dto::user::{AuthTokens, UserDto},
   errors::auth::AuthError,
   services::jwt::{JwtData, TokenType},
 };
@@ -23,8 +23,41 @@ pub async fn require_user_authentication(
   mut req: Request,
   next: Next,
 ) -> Result<impl IntoResponse, AuthError> {
-  let token = req
-    .headers()
+  let (user, tokens) = authenticate(&app_state, req.headers()).await?;
+
+  req.extensions_mut().insert(user);
+  req.extensions_mut().insert(tokens);
+  Ok(next.run(req).await)
+}
+
+/// Authenticate the user if credentials are present, anonymous requests are passed through.
+///
+/// Handlers receive the viewer as `Extension<Option<UserDto>>`
+#[tracing::instrument(skip(app_state, req, next))]
+#[axum::debug_middleware]
+pub async fn optional_user_authentication(
+  State(app_state): State<Arc<AppState>>,
+  mut req: Request,
+  next: Next,
+) -> Result<impl IntoResponse, AuthError> {
+  let viewer = if req.headers().contains_key(header::AUTHORIZATION) {
+    let (user, tokens) = authenticate(&app_state, req.headers()).await?;
+    req.extensions_mut().insert(tokens);
+
+    Some(user)
+  } else {
+    None
+  };
+
+  req.extensions_mut().insert(viewer);
+  Ok(next.run(req).await)
+}
+
+async fn authenticate(
+  app_state: &AppState,
+  headers: &HeaderMap,
+) -> Result<(UserDto, AuthTokens), AuthError> {
+  let token = headers
     .get(header::AUTHORIZATION)
     .and_then(|auth_header| auth_header.to_str().ok())
     .and_then(|auth_value| {
@@ -34,8 +67,7 @@ pub async fn require_user_authentication(
     })
     .ok_or(AuthError::NoToken("user"))?;
 
-  let refresh_token = req
-    .headers()
+  let refresh_token = headers
     .get(REFRESH_AUTH_HEADER)
     .and_then(|auth_header| auth_header.to_str().ok())
     .and_then(|auth_value| {
@@ -45,11 +77,7 @@ pub async fn require_user_authentication(
     })
     .ok_or(AuthError::NoRefreshToken("user"))?;
 
-  let JwtData { user_id, kind } = app_state
-    .jwt_service
-    .decode(&token)
-    .await
-    .map_err(|e| AuthError::InvalidToken("jwt", e.to_string()))?;
+  let JwtData { user_id, kind } = app_state.jwt_service.decode(&token).await?;
 
   if kind != TokenType::Access {
     return Err(AuthError::InvalidToken(
@@ -72,7 +100,5 @@ pub async fn require_user_authentication(
     refresh_token,
   };
 
-  req.extensions_mut().insert(user);
-  req.extensions_mut().insert(tokens);
-  Ok(next.run(req).await)
+  Ok((user, tokens))
 }
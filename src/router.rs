@@ -3,6 +3,7 @@ use axum::{
   http::{header, StatusCode},
   middleware,
   response::IntoResponse,
+  routing::{get, patch, post, put},
   Router,
 };
 use std::sync::Arc;
@@ -14,14 +15,26 @@ use utoipa_axum::{router::OpenApiRouter, routes};
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
-  api::{auth, health, me, users},
-  middlewares::user_auth::{require_user_authentication, REFRESH_AUTH_HEADER},
+  api::{
+    auth, counters, dialogs, events, groups, health, internal, keys, me, metrics, posts, users,
+  },
+  dto::event::ClientCommand,
+  middlewares::{
+    dialog_proxy::proxy_dialog_requests,
+    idempotency::idempotency,
+    rate_limit::{rate_limit, RouteGroup},
+    read_your_writes::read_your_writes,
+    service_auth::require_service_authentication,
+    user_auth::{optional_user_authentication, require_user_authentication, REFRESH_AUTH_HEADER},
+  },
+  services::dialog_proxy::INTERNAL_PREFIX,
   AppState,
 };
 
 #[derive(OpenApi)]
 #[openapi(
   modifiers(&SecurityAddon),
+  components(schemas(ClientCommand)),
   tags((name = "Social Network", description = "Social Network operations"))
 )]
 pub struct ApiDoc;
@@ -46,38 +59,158 @@ impl Modify for SecurityAddon {
   }
 }
 
-#[tracing::instrument]
+#[tracing::instrument(skip(req), fields(path = %req.uri().path()))]
 #[axum::debug_handler]
 async fn handle_404(req: Request) -> impl IntoResponse {
   (StatusCode::NOT_FOUND, "{\"message\":\"Not Found\"}")
 }
 
 pub fn create_router(app_state: Arc<AppState>) -> Router {
+  let dialog_router = OpenApiRouter::new()
+    .routes(routes!(dialogs::send_message))
+    .routes(routes!(dialogs::list_messages))
+    .routes(routes!(dialogs::list_dialogs))
+    .routes(routes!(dialogs::edit_message, dialogs::delete_message))
+    .routes(routes!(dialogs::list_message_edits))
+    .routes(routes!(dialogs::set_message_ttl))
+    .route_layer(middleware::from_fn_with_state(
+      app_state.clone(),
+      proxy_dialog_requests,
+    ));
+
   let user_router = OpenApiRouter::new()
     .routes(routes!(me::get_me))
+    .routes(routes!(counters::get_counters))
+    .routes(routes!(events::events))
+    .routes(routes!(groups::create_group, groups::list_groups))
+    .routes(routes!(groups::get_group, groups::rename_group))
+    .routes(routes!(groups::add_member))
+    .routes(routes!(groups::remove_member))
+    .routes(routes!(groups::leave_group))
+    .routes(routes!(groups::send_group_message))
+    .routes(routes!(groups::list_group_messages))
+    .routes(routes!(
+      groups::edit_group_message,
+      groups::delete_group_message
+    ))
+    .routes(routes!(groups::list_group_message_edits))
+    .routes(routes!(groups::set_group_message_ttl))
+    .routes(routes!(keys::publish_device_keys, keys::delete_device_keys))
+    .routes(routes!(keys::list_device_keys))
+    .routes(routes!(dialogs::block_user, dialogs::unblock_user))
+    .merge(dialog_router)
+    .route_layer(middleware::from_fn_with_state(
+      (app_state.clone(), RouteGroup::Api),
+      idempotency,
+    ))
+    .route_layer(middleware::from_fn_with_state(
+      (app_state.clone(), RouteGroup::Api),
+      rate_limit,
+    ))
     .route_layer(middleware::from_fn_with_state(
       app_state.clone(),
       require_user_authentication,
     ));
 
-  let router = OpenApiRouter::new()
-    .routes(routes!(users::get_user))
+  let viewer_router = OpenApiRouter::new()
+    .routes(routes!(posts::list_user_posts))
+    .route_layer(middleware::from_fn_with_state(
+      (app_state.clone(), RouteGroup::Api),
+      rate_limit,
+    ))
+    .route_layer(middleware::from_fn_with_state(
+      app_state.clone(),
+      optional_user_authentication,
+    ));
+
+  // Served outside of `/api`
+  let root_router = OpenApiRouter::new()
+    .routes(routes!(posts::get_user_feed))
+    .route_layer(middleware::from_fn_with_state(
+      (app_state.clone(), RouteGroup::Api),
+      rate_limit,
+    ))
+    .with_state(app_state.clone());
+
+  let auth_router = OpenApiRouter::new()
     .routes(routes!(auth::register))
+    .route_layer(middleware::from_fn_with_state(
+      (app_state.clone(), RouteGroup::Auth),
+      idempotency,
+    ))
     .routes(routes!(auth::login))
+    .route_layer(middleware::from_fn_with_state(
+      (app_state.clone(), RouteGroup::Auth),
+      rate_limit,
+    ));
+
+  let router = OpenApiRouter::new()
+    .routes(routes!(users::get_user))
+    .route_layer(middleware::from_fn_with_state(
+      (app_state.clone(), RouteGroup::Api),
+      rate_limit,
+    ))
+    .merge(auth_router)
     .with_state(app_state.clone());
 
   let (router, api) = OpenApiRouter::with_openapi(ApiDoc::openapi())
     .nest(
       "/api",
       OpenApiRouter::new()
-        .routes(routes!(health::health))
+        .routes(routes!(health::live))
+        .routes(routes!(health::ready))
+        // Former single health check, kept for the probes still pointing at it
+        .route("/health", get(health::live))
         .merge(router)
         .merge(user_router)
+        .merge(viewer_router)
+        .layer(middleware::from_fn_with_state(
+          app_state.clone(),
+          read_your_writes,
+        ))
         .with_state(app_state),
     )
+    .merge(root_router)
     .split_for_parts();
 
   router
     .merge(SwaggerUi::new("/api-docs").url("/api-docs/openapi.json", api.clone()))
     .fallback(handle_404)
 }
+
+/// Router of the internal listener, serving the metrics apart from the public API
+pub fn create_internal_router(app_state: Arc<AppState>) -> Router {
+  Router::new()
+    .route("/metrics", get(metrics::metrics))
+    .with_state(app_state)
+    .fallback(handle_404)
+}
+
+/// Router of the dialog service binary, only serving the internal API
+pub fn create_dialog_router(app_state: Arc<AppState>) -> Router {
+  let internal_router = Router::new()
+    .route("/dialog/{user_id}/send", post(internal::send_message))
+    .route("/dialog/{user_id}/list", get(internal::list_messages))
+    .route("/dialog/{user_id}/read", post(internal::mark_read))
+    .route(
+      "/dialog/{user_id}/messages/{message_id}",
+      patch(internal::edit_message).delete(internal::delete_message),
+    )
+    .route(
+      "/dialog/{user_id}/messages/{message_id}/edits",
+      get(internal::list_message_edits),
+    )
+    .route("/dialog/{user_id}/ttl", put(internal::set_message_ttl))
+    .route("/dialogs", get(internal::list_dialogs))
+    .route_layer(middleware::from_fn_with_state(
+      app_state.clone(),
+      require_service_authentication,
+    ))
+    .route("/health/live", get(health::live))
+    .route("/health/ready", get(health::ready));
+
+  Router::new()
+    .nest(INTERNAL_PREFIX, internal_router)
+    .with_state(app_state)
+    .fallback(handle_404)
+}
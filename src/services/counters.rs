@@ -0,0 +1,297 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::LazyLock,
+  time::Duration,
+};
+
+use redis::{AsyncCommands, Script};
+
+use crate::{
+  db::RedisClient,
+  dto::counter::{Conversation, UnreadCountersDto},
+  errors::counter::{CounterError, CounterResult},
+};
+
+const COUNTERS_PREFIX: &str = "unread:";
+const TOTAL_FIELD: &str = "total";
+const RECONCILIATION_LOCK_KEY: &str = "unread_reconciliation_lock";
+
+/// Change the conversation counter by ARGV[2] keeping it and the total non-negative
+static INCREMENT_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+  Script::new(
+    r"
+      local old = tonumber(redis.call('HGET', KEYS[1], ARGV[1]) or '0')
+      local new = math.max(old + tonumber(ARGV[2]), 0)
+      if new == 0 then
+        redis.call('HDEL', KEYS[1], ARGV[1])
+      else
+        redis.call('HSET', KEYS[1], ARGV[1], new)
+      end
+      if redis.call('HINCRBY', KEYS[1], ARGV[3], new - old) <= 0 then
+        redis.call('HDEL', KEYS[1], ARGV[3])
+      end
+      return new
+    ",
+  )
+});
+
+/// Set the conversation counter to ARGV[3] and return its previous value
+static SET_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+  Script::new(
+    r"
+      local old = tonumber(redis.call('HGET', KEYS[1], ARGV[1]) or '0')
+      local new = math.max(tonumber(ARGV[3]), 0)
+      if new == 0 then
+        redis.call('HDEL', KEYS[1], ARGV[1])
+      else
+        redis.call('HSET', KEYS[1], ARGV[1], new)
+      end
+      if new ~= old and redis.call('HINCRBY', KEYS[1], ARGV[2], new - old) <= 0 then
+        redis.call('HDEL', KEYS[1], ARGV[2])
+      end
+      return old
+    ",
+  )
+});
+
+/// Replace the counters with ARGV[2..] only if they still match the snapshot in ARGV[1]
+static RECONCILE_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+  Script::new(
+    r"
+      local current = redis.call('HGETALL', KEYS[1])
+      local entries = {}
+      for i = 1, #current, 2 do
+        table.insert(entries, current[i] .. '=' .. current[i + 1])
+      end
+      table.sort(entries)
+      if table.concat(entries, ',') ~= ARGV[1] then
+        return 0
+      end
+      redis.call('DEL', KEYS[1])
+      for i = 2, #ARGV, 2 do
+        redis.call('HSET', KEYS[1], ARGV[i], ARGV[i + 1])
+      end
+      return 1
+    ",
+  )
+});
+
+/// Raw counters of a user taken before reading the message store
+#[derive(Debug, Default, Clone)]
+pub struct CounterSnapshot(HashMap<String, String>);
+
+impl CounterSnapshot {
+  /// Serialized the same way as in [`RECONCILE_SCRIPT`]
+  fn serialize(&self) -> String {
+    let mut entries = self
+      .0
+      .iter()
+      .map(|(field, value)| format!("{}={}", field, value))
+      .collect::<Vec<_>>();
+    entries.sort();
+    entries.join(",")
+  }
+
+  fn counters(&self) -> UnreadCountersDto {
+    let mut counters = UnreadCountersDto::default();
+
+    for (field, value) in &self.0 {
+      let value = value.parse::<i64>().unwrap_or_default();
+      if field == TOTAL_FIELD {
+        counters.total = value;
+      } else if let Some(conversation) = Conversation::from_field(field) {
+        counters.conversations.insert(conversation, value);
+      }
+    }
+
+    counters
+  }
+}
+
+/// Unread message counters kept in Redis.
+///
+/// Postgres is the source of truth, the counters follow it through the dialog sagas and are
+/// periodically reconciled with it
+#[derive(Clone, Debug)]
+pub struct CounterService {
+  redis: RedisClient,
+}
+
+impl CounterService {
+  pub fn new(redis: RedisClient) -> Self {
+    Self { redis }
+  }
+
+  /// Change unread count of the conversation, negative values are used by compensations
+  #[tracing::instrument(name = "increment_unread", skip(self))]
+  pub async fn increment(
+    &self,
+    user_id: i32,
+    conversation: Conversation,
+    by: i64,
+  ) -> CounterResult<i64> {
+    INCREMENT_SCRIPT
+      .key(counters_key(user_id))
+      .arg(conversation.field())
+      .arg(by)
+      .arg(TOTAL_FIELD)
+      .invoke_async(&mut self.redis.connection())
+      .await
+      .map_err(CounterError::FailedToUpdateCounters)
+  }
+
+  /// Mark the conversation as read, returns the previous unread count
+  pub async fn reset(&self, user_id: i32, conversation: Conversation) -> CounterResult<i64> {
+    self.set(user_id, conversation, 0).await
+  }
+
+  /// Set unread count of the conversation, returns the previous one
+  #[tracing::instrument(name = "set_unread", skip(self))]
+  pub async fn set(
+    &self,
+    user_id: i32,
+    conversation: Conversation,
+    unread: i64,
+  ) -> CounterResult<i64> {
+    SET_SCRIPT
+      .key(counters_key(user_id))
+      .arg(conversation.field())
+      .arg(TOTAL_FIELD)
+      .arg(unread)
+      .invoke_async(&mut self.redis.connection())
+      .await
+      .map_err(CounterError::FailedToUpdateCounters)
+  }
+
+  #[tracing::instrument(name = "get_unread", skip(self))]
+  pub async fn get(&self, user_id: i32) -> CounterResult<UnreadCountersDto> {
+    self
+      .snapshot(user_id)
+      .await
+      .map(|snapshot| snapshot.counters())
+  }
+
+  pub async fn snapshot(&self, user_id: i32) -> CounterResult<CounterSnapshot> {
+    self
+      .redis
+      .connection()
+      .hgetall(counters_key(user_id))
+      .await
+      .map(CounterSnapshot)
+      .map_err(CounterError::FailedToGetCounters)
+  }
+
+  /// Snapshots of every user having counters
+  pub async fn snapshot_all(&self) -> CounterResult<HashMap<i32, CounterSnapshot>> {
+    let keys = {
+      let mut redis = self.redis.connection();
+      let mut iter = redis
+        .scan_match::<_, String>(format!("{}*", COUNTERS_PREFIX))
+        .await
+        .map_err(CounterError::FailedToGetCounters)?;
+
+      let mut keys = Vec::new();
+      while let Some(key) = iter.next_item().await {
+        keys.push(key);
+      }
+      keys
+    };
+
+    let mut snapshots = HashMap::with_capacity(keys.len());
+    for key in keys {
+      if let Some(user_id) = key
+        .strip_prefix(COUNTERS_PREFIX)
+        .and_then(|user_id| user_id.parse::<i32>().ok())
+      {
+        snapshots.insert(user_id, self.snapshot(user_id).await?);
+      }
+    }
+
+    Ok(snapshots)
+  }
+
+  /// Set counters of the user to the expected ones, unless they changed since the snapshot.
+  ///
+  /// Returns whether the counters had drifted and whether they were fixed
+  pub async fn reconcile(
+    &self,
+    user_id: i32,
+    snapshot: &CounterSnapshot,
+    expected: &BTreeMap<Conversation, i64>,
+  ) -> CounterResult<(bool, bool)> {
+    let expected = UnreadCountersDto {
+      total: expected.values().sum(),
+      conversations: expected
+        .iter()
+        .filter(|(_, unread)| **unread > 0)
+        .map(|(conversation, unread)| (*conversation, *unread))
+        .collect(),
+    };
+    if snapshot.counters() == expected {
+      return Ok((false, false));
+    }
+
+    let mut script = RECONCILE_SCRIPT.key(counters_key(user_id));
+    script.arg(snapshot.serialize());
+    for (conversation, unread) in &expected.conversations {
+      script.arg(conversation.field()).arg(unread);
+    }
+    if expected.total > 0 {
+      script.arg(TOTAL_FIELD).arg(expected.total);
+    }
+
+    let fixed = script
+      .invoke_async::<i32>(&mut self.redis.connection())
+      .await
+      .map_err(CounterError::FailedToUpdateCounters)?;
+
+    Ok((true, fixed == 1))
+  }
+
+  /// Allow only one instance to reconcile counters during the period
+  pub async fn try_lock_reconciliation(&self, period: Duration) -> CounterResult<bool> {
+    let options = redis::SetOptions::default()
+      .conditional_set(redis::ExistenceCheck::NX)
+      .with_expiration(redis::SetExpiry::EX(period.as_secs().max(1)));
+
+    self
+      .redis
+      .connection()
+      .set_options::<_, _, Option<String>>(RECONCILIATION_LOCK_KEY, "locked", options)
+      .await
+      .map(|reply| reply.is_some())
+      .map_err(CounterError::FailedToUpdateCounters)
+  }
+}
+
+fn counters_key(user_id: i32) -> String {
+  format!("{}{}", COUNTERS_PREFIX, user_id)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn snapshot_is_serialized_in_sorted_order() {
+    let snapshot = CounterSnapshot(HashMap::from([
+      ("total".to_string(), "5".to_string()),
+      ("12".to_string(), "3".to_string()),
+      ("7".to_string(), "1".to_string()),
+      ("g:3".to_string(), "1".to_string()),
+    ]));
+
+    assert_eq!(snapshot.serialize(), "12=3,7=1,g:3=1,total=5");
+    assert_eq!(
+      snapshot.counters(),
+      UnreadCountersDto {
+        total: 5,
+        conversations: BTreeMap::from([
+          (Conversation::Dialog(7), 1),
+          (Conversation::Dialog(12), 3),
+          (Conversation::Group(3), 1),
+        ]),
+      }
+    );
+  }
+}
@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use axum::{
+  body::{to_bytes, Body},
+  extract::Request,
+  http::{header, HeaderMap, HeaderName, Uri},
+  response::Response,
+};
+
+use crate::{
+  dto::dialog::MarkReadDto,
+  errors::dialog::{DialogError, DialogResult},
+  services::jwt::JwtService,
+  telemetry, REQUEST_ID_HEADER,
+};
+
+/// Prefix of the internal API of the dialog service
+pub const INTERNAL_PREFIX: &str = "/internal";
+
+const MAX_BODY_SIZE: usize = 64 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Headers describing a single connection, never forwarded by proxies (RFC 9110, section 7.6.1)
+const HOP_BY_HOP_HEADERS: [HeaderName; 8] = [
+  header::CONNECTION,
+  HeaderName::from_static("keep-alive"),
+  header::PROXY_AUTHENTICATE,
+  header::PROXY_AUTHORIZATION,
+  header::TE,
+  header::TRAILER,
+  header::TRANSFER_ENCODING,
+  header::UPGRADE,
+];
+
+/// Forwards dialog requests to the dialog service on behalf of the authenticated user
+#[derive(Clone, Debug)]
+pub struct DialogProxy {
+  http: reqwest::Client,
+  base_url: String,
+  jwt_service: JwtService,
+}
+
+impl DialogProxy {
+  pub fn new(base_url: &str, jwt_service: JwtService) -> Self {
+    Self {
+      http: reqwest::Client::new(),
+      base_url: base_url.trim_end_matches('/').to_string(),
+      jwt_service,
+    }
+  }
+
+  #[tracing::instrument(name = "proxy_dialog_request", skip(self, req))]
+  pub async fn forward(&self, user_id: i32, req: Request) -> DialogResult<Response> {
+    let token = self
+      .jwt_service
+      .build_service_token(user_id)
+      .map_err(DialogError::DialogServiceUnavailable)?;
+
+    let (parts, body) = req.into_parts();
+    let body = to_bytes(body, MAX_BODY_SIZE)
+      .await
+      .map_err(|e| DialogError::DialogServiceUnavailable(e.to_string()))?;
+
+    let mut request = self
+      .http
+      .request(parts.method, self.remote_url(&parts.uri))
+      .timeout(REQUEST_TIMEOUT)
+      .bearer_auth(token)
+      .body(body);
+    for name in [
+      header::CONTENT_TYPE,
+      HeaderName::from_static(REQUEST_ID_HEADER),
+    ] {
+      if let Some(value) = parts.headers.get(&name) {
+        request = request.header(name, value.clone());
+      }
+    }
+
+    let response = request
+      .headers(telemetry::trace_context_headers())
+      .send()
+      .await
+      .map_err(|e| DialogError::DialogServiceUnavailable(e.to_string()))?;
+
+    let status = response.status();
+    let headers = end_to_end_headers(response.headers());
+    let body = response
+      .bytes()
+      .await
+      .map_err(|e| DialogError::DialogServiceUnavailable(e.to_string()))?;
+
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+
+    Ok(response)
+  }
+
+  /// Read receipts come from WebSocket commands rather than requests, so they are sent directly
+  #[tracing::instrument(name = "proxy_read_receipt", skip(self))]
+  pub async fn mark_read(&self, user_id: i32, peer_id: i32, message_id: i64) -> DialogResult<()> {
+    let token = self
+      .jwt_service
+      .build_service_token(user_id)
+      .map_err(DialogError::DialogServiceUnavailable)?;
+
+    let response = self
+      .http
+      .post(format!(
+        "{}{}/dialog/{}/read",
+        self.base_url, INTERNAL_PREFIX, peer_id
+      ))
+      .timeout(REQUEST_TIMEOUT)
+      .bearer_auth(token)
+      .headers(telemetry::trace_context_headers())
+      .json(&MarkReadDto { message_id })
+      .send()
+      .await
+      .map_err(|e| DialogError::DialogServiceUnavailable(e.to_string()))?;
+
+    if !response.status().is_success() {
+      return Err(DialogError::DialogServiceUnavailable(format!(
+        "read receipt rejected with {}",
+        response.status()
+      )));
+    }
+
+    Ok(())
+  }
+
+  /// Url of the internal endpoint, the request uri is relative to the `/api` prefix
+  fn remote_url(&self, uri: &Uri) -> String {
+    format!(
+      "{}{}{}",
+      self.base_url,
+      INTERNAL_PREFIX,
+      uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/")
+    )
+  }
+}
+
+/// Headers of the upstream response without the hop-by-hop ones, including those listed in its
+/// `Connection` header
+fn end_to_end_headers(headers: &HeaderMap) -> HeaderMap {
+  let connection_headers = headers
+    .get_all(header::CONNECTION)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+    .collect::<Vec<_>>();
+
+  let mut forwarded = headers.clone();
+  for name in HOP_BY_HOP_HEADERS.iter().chain(&connection_headers) {
+    forwarded.remove(name);
+  }
+
+  forwarded
+}
+
+#[cfg(test)]
+mod tests {
+  use axum::http::HeaderValue;
+
+  use super::*;
+
+  #[test]
+  fn hop_by_hop_headers_are_dropped() {
+    let mut headers = HeaderMap::new();
+    for (name, value) in [
+      ("content-type", "application/json"),
+      ("etag", "\"42\""),
+      ("x-request-id", "request"),
+      ("set-cookie", "a=1"),
+      ("set-cookie", "b=2"),
+      ("connection", "keep-alive, x-upstream-hint"),
+      ("keep-alive", "timeout=5"),
+      ("transfer-encoding", "chunked"),
+      ("x-upstream-hint", "internal"),
+    ] {
+      headers.append(name, HeaderValue::from_static(value));
+    }
+
+    let forwarded = end_to_end_headers(&headers);
+
+    assert_eq!(forwarded.len(), 5);
+    assert_eq!(forwarded.get_all(header::SET_COOKIE).iter().count(), 2);
+    for name in ["content-type", "etag", "x-request-id"] {
+      assert_eq!(forwarded.get(name), headers.get(name));
+    }
+  }
+}
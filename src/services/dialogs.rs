@@ -0,0 +1,569 @@
+use chrono::{TimeDelta, Utc};
+use sqlx::PgPool;
+use tracing::error;
+
+use crate::{
+  db::dialogs::DialogStore,
+  dto::{
+    counter::Conversation,
+    dialog::{
+      DialogKey, DialogSummaryDto, EnvelopeDto, MessageDto, MessageEditDto, MessagePageDto,
+    },
+    event::{DeletedEvent, RealtimeEvent, ReceiptEvent},
+  },
+  errors::dialog::{DialogError, DialogResult},
+  services::{counters::CounterService, events::EventService},
+};
+
+#[derive(Clone, Debug)]
+pub struct DialogService {
+  db: PgPool,
+  store: DialogStore,
+  counter_service: CounterService,
+  event_service: EventService,
+  edit_window: TimeDelta,
+}
+
+impl DialogService {
+  pub fn new(
+    db: PgPool,
+    store: DialogStore,
+    counter_service: CounterService,
+    event_service: EventService,
+    edit_window: TimeDelta,
+  ) -> Self {
+    Self {
+      db,
+      store,
+      counter_service,
+      event_service,
+      edit_window,
+    }
+  }
+
+  pub fn store(&self) -> &DialogStore {
+    &self.store
+  }
+
+  /// Store the message and count it as unread for the recipient. Encrypted messages come as an
+  /// envelope with an empty text.
+  ///
+  /// The stored message is the source of truth, a failed counter update is left to reconciliation
+  #[tracing::instrument(name = "send_message", skip(self, text, envelope))]
+  pub async fn send(
+    &self,
+    sender_id: i32,
+    recipient_id: i32,
+    text: String,
+    envelope: Option<EnvelopeDto>,
+  ) -> DialogResult<MessageDto> {
+    self.ensure_can_message(sender_id, recipient_id).await?;
+    if let Some(envelope) = &envelope {
+      Self::ensure_envelope_recipients(envelope, sender_id, recipient_id)?;
+    }
+
+    let message = self
+      .store
+      .insert_message(sender_id, recipient_id, text, envelope)
+      .await?;
+
+    if let Err(e) = self
+      .counter_service
+      .increment(recipient_id, Conversation::Dialog(sender_id), 1)
+      .await
+    {
+      error!(
+        "Failed to update unread counter of user {}: {}, left to reconciliation",
+        recipient_id, e
+      );
+    }
+    self.notify_message(&message).await;
+
+    Ok(message)
+  }
+
+  /// List messages of the dialog, newest first.
+  ///
+  /// Fetching the first page marks the dialog as read for the user
+  #[tracing::instrument(name = "list_messages", skip(self))]
+  pub async fn list(
+    &self,
+    user_id: i32,
+    peer_id: i32,
+    cursor: Option<i64>,
+    limit: i64,
+  ) -> DialogResult<MessagePageDto> {
+    if user_id == peer_id {
+      return Err(DialogError::SelfDialog);
+    }
+
+    let page = self
+      .store
+      .list_messages(DialogKey::new(user_id, peer_id), cursor, limit)
+      .await?;
+
+    if let (None, Some(newest)) = (cursor, page.messages.first()) {
+      self.mark_read(user_id, peer_id, newest.id).await?;
+    }
+
+    Ok(page)
+  }
+
+  /// Dialogs of the user with the last message and unread count, most recent first
+  #[tracing::instrument(name = "list_dialogs", skip(self))]
+  pub async fn list_dialogs(&self, user_id: i32) -> DialogResult<Vec<DialogSummaryDto>> {
+    self.store.list_dialogs(user_id).await
+  }
+
+  /// Read receipt of the user, the cursor is capped by the newest message of the dialog
+  #[tracing::instrument(name = "read_dialog", skip(self))]
+  pub async fn read(&self, user_id: i32, peer_id: i32, message_id: i64) -> DialogResult<()> {
+    if user_id == peer_id {
+      return Err(DialogError::SelfDialog);
+    }
+
+    let page = self
+      .store
+      .list_messages(DialogKey::new(user_id, peer_id), None, 1)
+      .await?;
+
+    match page.messages.first() {
+      Some(newest) => {
+        self
+          .mark_read(user_id, peer_id, message_id.min(newest.id))
+          .await
+      }
+      None => Ok(()),
+    }
+  }
+
+  /// Replace the text of the user's own message within the edit window, keeping the previous one
+  #[tracing::instrument(name = "edit_message", skip(self, text))]
+  pub async fn edit(
+    &self,
+    user_id: i32,
+    peer_id: i32,
+    message_id: i64,
+    text: String,
+  ) -> DialogResult<MessageDto> {
+    let key = DialogKey::new(user_id, peer_id);
+    let message = self.own_message(key, user_id, message_id).await?;
+    if message.envelope.is_some() {
+      return Err(DialogError::EncryptedMessage(message_id));
+    }
+    if message.created_at + self.edit_window < Utc::now() {
+      return Err(DialogError::EditWindowExpired(message_id));
+    }
+
+    let message = self
+      .store
+      .edit_message(key, message_id, text)
+      .await?
+      .ok_or(DialogError::MessageNotFound(message_id))?;
+
+    for viewer_id in [peer_id, user_id] {
+      let event = RealtimeEvent::dialog_message_edited(viewer_id, &message);
+      self.event_service.publish(&[viewer_id], &event).await;
+    }
+
+    Ok(message)
+  }
+
+  /// Previous versions of the message, oldest first
+  #[tracing::instrument(name = "list_message_edits", skip(self))]
+  pub async fn edits(
+    &self,
+    user_id: i32,
+    peer_id: i32,
+    message_id: i64,
+  ) -> DialogResult<Vec<MessageEditDto>> {
+    if user_id == peer_id {
+      return Err(DialogError::SelfDialog);
+    }
+
+    let key = DialogKey::new(user_id, peer_id);
+    if self.store.get_message(key, message_id).await?.is_none() {
+      return Err(DialogError::MessageNotFound(message_id));
+    }
+
+    self.store.message_edits(key, message_id).await
+  }
+
+  /// Delete the user's own message for both participants, taking it out of the unread counter
+  #[tracing::instrument(name = "delete_message", skip(self))]
+  pub async fn delete(&self, user_id: i32, peer_id: i32, message_id: i64) -> DialogResult<()> {
+    let key = DialogKey::new(user_id, peer_id);
+    self.own_message(key, user_id, message_id).await?;
+
+    let deleted = self
+      .store
+      .delete_message(key, message_id)
+      .await?
+      .ok_or(DialogError::MessageNotFound(message_id))?;
+
+    if deleted.unread {
+      if let Err(e) = self
+        .counter_service
+        .increment(peer_id, Conversation::Dialog(user_id), -1)
+        .await
+      {
+        error!(
+          "Failed to take deleted message out of unread counter of user {}: {}, left to reconciliation",
+          peer_id, e
+        );
+      }
+    }
+
+    for (viewer_id, conversation) in [
+      (peer_id, Conversation::Dialog(user_id)),
+      (user_id, Conversation::Dialog(peer_id)),
+    ] {
+      let event = RealtimeEvent::MessageDeleted(DeletedEvent {
+        conversation,
+        message_id,
+      });
+      self.event_service.publish(&[viewer_id], &event).await;
+    }
+
+    Ok(())
+  }
+
+  /// Set the disappearing messages timeout for messages sent from now on by either participant
+  #[tracing::instrument(name = "set_message_ttl", skip(self))]
+  pub async fn set_message_ttl(
+    &self,
+    user_id: i32,
+    peer_id: i32,
+    seconds: Option<i32>,
+  ) -> DialogResult<()> {
+    self.ensure_can_message(user_id, peer_id).await?;
+
+    self
+      .store
+      .set_message_ttl(DialogKey::new(user_id, peer_id), seconds)
+      .await
+  }
+
+  async fn own_message(
+    &self,
+    key: DialogKey,
+    user_id: i32,
+    message_id: i64,
+  ) -> DialogResult<MessageDto> {
+    if key.user_a == key.user_b {
+      return Err(DialogError::SelfDialog);
+    }
+
+    let message = self
+      .store
+      .get_message(key, message_id)
+      .await?
+      .ok_or(DialogError::MessageNotFound(message_id))?;
+    if message.sender_id != user_id {
+      return Err(DialogError::NotMessageSender(message_id));
+    }
+
+    Ok(message)
+  }
+
+  /// Move the read state and set the unread counter to the messages still newer than it. The
+  /// read state is the source of truth, a failed counter update is left to reconciliation
+  async fn mark_read(&self, user_id: i32, peer_id: i32, message_id: i64) -> DialogResult<()> {
+    let unread = self.store.mark_read(user_id, peer_id, message_id).await?;
+    if let Err(e) = self
+      .counter_service
+      .set(user_id, Conversation::Dialog(peer_id), unread)
+      .await
+    {
+      error!(
+        "Failed to update unread counter of user {}: {}, left to reconciliation",
+        user_id, e
+      );
+    }
+
+    for (viewer_id, conversation) in [
+      (peer_id, Conversation::Dialog(user_id)),
+      (user_id, Conversation::Dialog(peer_id)),
+    ] {
+      let receipt = RealtimeEvent::Read(ReceiptEvent {
+        conversation,
+        user_id,
+        message_id,
+      });
+      self.event_service.publish(&[viewer_id], &receipt).await;
+    }
+
+    Ok(())
+  }
+
+  /// Push the message to both participants, the sender may be connected from other devices
+  async fn notify_message(&self, message: &MessageDto) {
+    for viewer_id in [message.recipient_id(), message.sender_id] {
+      let event = RealtimeEvent::dialog_message(viewer_id, message);
+      self.event_service.publish(&[viewer_id], &event).await;
+    }
+    self
+      .event_service
+      .clear_typing(
+        message.sender_id,
+        Conversation::Dialog(message.recipient_id()),
+      )
+      .await;
+  }
+
+  /// Ciphertexts may only be addressed to the participants, and at least one to the recipient
+  fn ensure_envelope_recipients(
+    envelope: &EnvelopeDto,
+    sender_id: i32,
+    recipient_id: i32,
+  ) -> DialogResult<()> {
+    if let Some(ciphertext) = envelope
+      .ciphertexts
+      .iter()
+      .find(|ciphertext| ciphertext.user_id != sender_id && ciphertext.user_id != recipient_id)
+    {
+      return Err(DialogError::InvalidEnvelope(format!(
+        "user {} is not a participant of the dialog",
+        ciphertext.user_id
+      )));
+    }
+
+    if !envelope
+      .ciphertexts
+      .iter()
+      .any(|ciphertext| ciphertext.user_id == recipient_id)
+    {
+      return Err(DialogError::InvalidEnvelope(
+        "no ciphertext for the recipient".to_string(),
+      ));
+    }
+
+    Ok(())
+  }
+
+  /// Block messages between the users in both directions, blocking twice is a no-op
+  #[tracing::instrument(name = "block_user", skip(self))]
+  pub async fn block(&self, blocker_id: i32, blocked_id: i32) -> DialogResult<()> {
+    if blocker_id == blocked_id {
+      return Err(DialogError::SelfDialog);
+    }
+
+    let peer_exists = sqlx::query_scalar!(
+      r#"
+        WITH peer AS (
+          SELECT id FROM users WHERE id = $2
+        ), blocked AS (
+          INSERT INTO user_blocks (blocker_id, blocked_id)
+          SELECT $1, id FROM peer
+          ON CONFLICT DO NOTHING
+        )
+        SELECT EXISTS(SELECT 1 FROM peer) AS "peer_exists!"
+      "#,
+      blocker_id,
+      blocked_id
+    )
+    .fetch_one(&self.db)
+    .await
+    .map_err(DialogError::FailedToUpdateBlocks)?;
+
+    if !peer_exists {
+      return Err(DialogError::PeerNotFound(blocked_id));
+    }
+
+    Ok(())
+  }
+
+  /// Lift the block of the user, messages stay blocked while the peer blocks the user too
+  #[tracing::instrument(name = "unblock_user", skip(self))]
+  pub async fn unblock(&self, blocker_id: i32, blocked_id: i32) -> DialogResult<()> {
+    sqlx::query!(
+      r#"DELETE FROM user_blocks WHERE blocker_id = $1 AND blocked_id = $2"#,
+      blocker_id,
+      blocked_id
+    )
+    .execute(&self.db)
+    .await
+    .map_err(DialogError::FailedToUpdateBlocks)?;
+
+    Ok(())
+  }
+
+  /// Dialogs are checked for blocks both for messages and typing indicators
+  pub async fn ensure_can_message(&self, sender_id: i32, recipient_id: i32) -> DialogResult<()> {
+    if sender_id == recipient_id {
+      return Err(DialogError::SelfDialog);
+    }
+
+    let row = sqlx::query!(
+      r#"
+        SELECT
+          EXISTS(SELECT 1 FROM users WHERE id = $2) AS "peer_exists!",
+          EXISTS(
+            SELECT 1 FROM user_blocks
+            WHERE (blocker_id = $1 AND blocked_id = $2)
+              OR (blocker_id = $2 AND blocked_id = $1)
+          ) AS "blocked!"
+      "#,
+      sender_id,
+      recipient_id
+    )
+    .fetch_one(&self.db)
+    .await
+    .map_err(DialogError::FailedToSendMessage)?;
+
+    if !row.peer_exists {
+      return Err(DialogError::PeerNotFound(recipient_id));
+    }
+
+    if row.blocked {
+      return Err(DialogError::Blocked(recipient_id));
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::db::{
+    dialogs::memory::MemoryDialogStore,
+    testing::{create_database, FakeRedis},
+    RedisClient, RedisOptions,
+  };
+
+  /// Service over a fresh database with two users and a memory store, Redis is faked
+  async fn dialog_service(name: &str, redis: &FakeRedis) -> (DialogService, i32, i32) {
+    let db = create_database(name).await;
+    let user_ids = sqlx::query_scalar::<_, i32>(
+      r#"
+        INSERT INTO users (email, password)
+        VALUES ('alice@example.com', 'password'), ('bob@example.com', 'password')
+        RETURNING id
+      "#,
+    )
+    .fetch_all(&db)
+    .await
+    .unwrap();
+
+    let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    let store = DialogStore::Memory(MemoryDialogStore::open(&dir).await.unwrap());
+
+    let redis = RedisClient::connect(
+      &redis.url,
+      RedisOptions {
+        response_timeout: Duration::from_millis(100),
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+    let service = DialogService::new(
+      db,
+      store,
+      CounterService::new(redis.clone()),
+      EventService::new(redis),
+      TimeDelta::minutes(15),
+    );
+
+    (service, user_ids[0], user_ids[1])
+  }
+
+  #[tokio::test]
+  #[ignore = "requires postgres from docker-compose.yml"]
+  async fn messages_are_sent_while_redis_is_down() {
+    let redis = FakeRedis::start().await;
+    let (service, alice, bob) = dialog_service("sn_test_dialog_service_redis_down", &redis).await;
+    redis.set_responsive(false);
+
+    let message = service
+      .send(alice, bob, "hello".to_string(), None)
+      .await
+      .unwrap();
+
+    let dialogs = service.list_dialogs(bob).await.unwrap();
+    assert_eq!(dialogs[0].last_message.id, message.id);
+    assert_eq!(dialogs[0].unread_count, 1);
+  }
+
+  #[tokio::test]
+  #[ignore = "requires postgres from docker-compose.yml"]
+  async fn messages_are_listed_newest_first_and_read_by_the_first_page() {
+    let redis = FakeRedis::start().await;
+    let (service, alice, bob) = dialog_service("sn_test_dialog_service_list", &redis).await;
+
+    let mut sent = Vec::new();
+    for text in ["one", "two", "three"] {
+      sent.push(
+        service
+          .send(alice, bob, text.to_string(), None)
+          .await
+          .unwrap(),
+      );
+    }
+
+    let dialogs = service.list_dialogs(bob).await.unwrap();
+    assert_eq!(dialogs.len(), 1);
+    assert_eq!(dialogs[0].peer_id, alice);
+    assert_eq!(dialogs[0].last_message.text, "three");
+    assert_eq!(dialogs[0].unread_count, 3);
+    assert_eq!(
+      service.list_dialogs(alice).await.unwrap()[0].unread_count,
+      0
+    );
+
+    let page = service.list(bob, alice, None, 2).await.unwrap();
+    assert_eq!(
+      page.messages.iter().map(|m| m.id).collect::<Vec<_>>(),
+      [sent[2].id, sent[1].id]
+    );
+    assert_eq!(service.list_dialogs(bob).await.unwrap()[0].unread_count, 0);
+
+    let page = service.list(bob, alice, page.next_cursor, 2).await.unwrap();
+    assert_eq!(page.messages.len(), 1);
+    assert_eq!(page.messages[0].id, sent[0].id);
+    assert_eq!(page.next_cursor, None);
+
+    assert!(matches!(
+      service.send(alice, alice, "me".to_string(), None).await,
+      Err(DialogError::SelfDialog)
+    ));
+    assert!(matches!(
+      service
+        .send(alice, i32::MAX, "nobody".to_string(), None)
+        .await,
+      Err(DialogError::PeerNotFound(_))
+    ));
+  }
+
+  #[tokio::test]
+  #[ignore = "requires postgres from docker-compose.yml"]
+  async fn blocked_users_can_not_message_each_other() {
+    let redis = FakeRedis::start().await;
+    let (service, alice, bob) = dialog_service("sn_test_dialog_service_blocks", &redis).await;
+
+    service.block(alice, bob).await.unwrap();
+    service.block(alice, bob).await.unwrap();
+    for (sender, recipient) in [(alice, bob), (bob, alice)] {
+      assert!(matches!(
+        service
+          .send(sender, recipient, "hi".to_string(), None)
+          .await,
+        Err(DialogError::Blocked(_))
+      ));
+    }
+    assert!(matches!(
+      service.block(alice, i32::MAX).await,
+      Err(DialogError::PeerNotFound(_))
+    ));
+
+    service.unblock(alice, bob).await.unwrap();
+    service
+      .send(bob, alice, "hi again".to_string(), None)
+      .await
+      .unwrap();
+  }
+}
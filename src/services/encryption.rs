@@ -1,3 +1,17 @@
+/// Run the task on the rayon pool, tasks waiting for a thread are reported as the hashing queue depth
+pub(crate) fn spawn_hashing<F>(task: F)
+where
+  F: FnOnce() + Send + 'static,
+{
+  let queue_depth = metrics::gauge!("hashing_queue_depth");
+  queue_depth.increment(1.0);
+
+  rayon::spawn(move || {
+    queue_depth.decrement(1.0);
+    task();
+  });
+}
+
 #[derive(Clone, Debug)]
 pub struct EncryptionService;
 
@@ -13,14 +27,14 @@ impl EncryptionService {
   }
 
   /// Consume password value to make it unusable
-  #[tracing::instrument(name = "hash_password", skip(self))]
+  #[tracing::instrument(name = "hash_password", skip(self, password))]
   pub async fn hash_password(
     &self,
     password: String,
   ) -> Result<String, tokio::sync::oneshot::error::RecvError> {
     let (send, recv) = tokio::sync::oneshot::channel();
 
-    rayon::spawn(move || {
+    spawn_hashing(move || {
       let hash = password_auth::generate_hash(password);
       let _ = send.send(hash);
     });
@@ -28,12 +42,23 @@ impl EncryptionService {
     recv.await
   }
 
+  /// Run an empty task on the hashing thread pool, it answers only when a thread is free
+  pub async fn ping(&self) -> Result<(), tokio::sync::oneshot::error::RecvError> {
+    let (send, recv) = tokio::sync::oneshot::channel();
+
+    spawn_hashing(move || {
+      let _ = send.send(());
+    });
+
+    recv.await
+  }
+
   pub async fn verify_password(&self, password: &str, hash: &str) -> bool {
     let (send, recv) = tokio::sync::oneshot::channel();
     let password = password.to_string();
     let hash = hash.to_string();
 
-    rayon::spawn(move || {
+    spawn_hashing(move || {
       let verify_result = password_auth::verify_password(password, &hash);
 
       let is_valid = verify_result.is_ok();
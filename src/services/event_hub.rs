@@ -0,0 +1,209 @@
+use std::{
+  collections::HashMap,
+  fmt,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::Duration,
+};
+
+use futures::StreamExt;
+use redis::{
+  aio::{PubSubSink, PubSubStream},
+  Msg,
+};
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+use tracing::{error, info, warn};
+
+use crate::{
+  errors::event::{EventError, EventResult},
+  services::events::{events_channel, user_from_channel},
+};
+
+/// Events buffered for a connection before they are dropped as undeliverable
+const SESSION_BUFFER: usize = 64;
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+type Sessions = HashMap<i32, HashMap<u64, mpsc::Sender<Arc<str>>>>;
+
+/// Routes events published to Redis to the WebSocket connections of this instance.
+///
+/// The instance subscribes to the channel of a user while at least one of their connections is open
+#[derive(Clone)]
+pub struct EventHub {
+  inner: Arc<Inner>,
+}
+
+struct Inner {
+  client: redis::Client,
+  /// Also serializes subscription changes, so they reach Redis in the order of the session changes
+  sink: AsyncMutex<PubSubSink>,
+  sessions: Mutex<Sessions>,
+  next_session_id: AtomicU64,
+}
+
+impl fmt::Debug for EventHub {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("EventHub").finish_non_exhaustive()
+  }
+}
+
+/// Events of one connection, returned to [`EventHub::unsubscribe`] when it closes
+#[derive(Debug)]
+pub struct Subscription {
+  id: u64,
+  user_id: i32,
+  receiver: mpsc::Receiver<Arc<str>>,
+}
+
+impl Subscription {
+  /// Next serialized event, `None` once the hub dropped the connection
+  pub async fn recv(&mut self) -> Option<Arc<str>> {
+    self.receiver.recv().await
+  }
+}
+
+impl EventHub {
+  pub async fn connect(client: redis::Client) -> redis::RedisResult<Self> {
+    let (sink, stream) = client.get_async_pubsub().await?.split();
+
+    let hub = Self {
+      inner: Arc::new(Inner {
+        client,
+        sink: AsyncMutex::new(sink),
+        sessions: Mutex::new(HashMap::new()),
+        next_session_id: AtomicU64::new(0),
+      }),
+    };
+    tokio::spawn(hub.clone().run(stream));
+
+    Ok(hub)
+  }
+
+  pub async fn subscribe(&self, user_id: i32) -> EventResult<Subscription> {
+    let (sender, receiver) = mpsc::channel(SESSION_BUFFER);
+    let id = self.inner.next_session_id.fetch_add(1, Ordering::Relaxed);
+
+    let mut sink = self.inner.sink.lock().await;
+    let first = {
+      let mut sessions = self.inner.sessions.lock().unwrap();
+      let user_sessions = sessions.entry(user_id).or_default();
+      user_sessions.insert(id, sender);
+      user_sessions.len() == 1
+    };
+
+    if first {
+      if let Err(e) = sink.subscribe(events_channel(user_id)).await {
+        self.remove_session(user_id, id);
+        return Err(EventError::FailedToSubscribe(e));
+      }
+    }
+
+    Ok(Subscription {
+      id,
+      user_id,
+      receiver,
+    })
+  }
+
+  pub async fn unsubscribe(&self, subscription: Subscription) {
+    let mut sink = self.inner.sink.lock().await;
+    if self.remove_session(subscription.user_id, subscription.id) {
+      if let Err(e) = sink.unsubscribe(events_channel(subscription.user_id)).await {
+        warn!(
+          "Failed to unsubscribe from events of user {}: {}",
+          subscription.user_id, e
+        );
+      }
+    }
+  }
+
+  /// Returns true if it was the last connection of the user
+  fn remove_session(&self, user_id: i32, id: u64) -> bool {
+    let mut sessions = self.inner.sessions.lock().unwrap();
+    let Some(user_sessions) = sessions.get_mut(&user_id) else {
+      return false;
+    };
+
+    user_sessions.remove(&id);
+    if user_sessions.is_empty() {
+      sessions.remove(&user_id);
+      true
+    } else {
+      false
+    }
+  }
+
+  async fn run(self, mut stream: PubSubStream) {
+    loop {
+      while let Some(message) = stream.next().await {
+        self.dispatch(message);
+      }
+
+      warn!("Lost the event subscription connection, reconnecting");
+      stream = self.reconnect().await;
+    }
+  }
+
+  fn dispatch(&self, message: Msg) {
+    let Some(user_id) = user_from_channel(message.get_channel_name()) else {
+      return;
+    };
+    let payload: Arc<str> = match message.get_payload::<String>() {
+      Ok(payload) => payload.into(),
+      Err(e) => {
+        warn!("Failed to read event of user {}: {}", user_id, e);
+        return;
+      }
+    };
+
+    let sessions = self.inner.sessions.lock().unwrap();
+    for sender in sessions.get(&user_id).into_iter().flat_map(HashMap::values) {
+      if let Err(mpsc::error::TrySendError::Full(_)) = sender.try_send(payload.clone()) {
+        warn!(
+          "Dropped an event of user {}, the connection is too slow",
+          user_id
+        );
+      }
+    }
+  }
+
+  /// Open a new connection and resubscribe the users connected meanwhile
+  async fn reconnect(&self) -> PubSubStream {
+    let mut delay = Duration::from_secs(1);
+    loop {
+      match self.resubscribe().await {
+        Ok(stream) => {
+          info!("Restored the event subscription connection");
+          return stream;
+        }
+        Err(e) => {
+          error!("Failed to restore the event subscription connection: {}", e);
+          tokio::time::sleep(delay).await;
+          delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+      }
+    }
+  }
+
+  async fn resubscribe(&self) -> redis::RedisResult<PubSubStream> {
+    let (mut new_sink, stream) = self.inner.client.get_async_pubsub().await?.split();
+
+    let mut sink = self.inner.sink.lock().await;
+    let channels = self
+      .inner
+      .sessions
+      .lock()
+      .unwrap()
+      .keys()
+      .map(|user_id| events_channel(*user_id))
+      .collect::<Vec<_>>();
+    if !channels.is_empty() {
+      new_sink.subscribe(channels).await?;
+    }
+    *sink = new_sink;
+
+    Ok(stream)
+  }
+}
@@ -0,0 +1,133 @@
+use redis::AsyncCommands;
+use tracing::warn;
+
+use crate::{
+  db::RedisClient,
+  dto::{counter::Conversation, event::RealtimeEvent},
+  errors::event::{EventError, EventResult},
+};
+
+const EVENTS_CHANNEL_PREFIX: &str = "events:";
+const TYPING_PREFIX: &str = "typing:";
+
+/// Seconds a typing indicator lives without being renewed
+pub const TYPING_TTL: u64 = 5;
+
+/// Redis channel carrying the events of the user to the instance holding their connections
+pub fn events_channel(user_id: i32) -> String {
+  format!("{}{}", EVENTS_CHANNEL_PREFIX, user_id)
+}
+
+pub fn user_from_channel(channel: &str) -> Option<i32> {
+  channel.strip_prefix(EVENTS_CHANNEL_PREFIX)?.parse().ok()
+}
+
+/// Publishes realtime events and keeps the ephemeral typing state
+#[derive(Clone, Debug)]
+pub struct EventService {
+  redis: RedisClient,
+}
+
+impl EventService {
+  pub fn new(redis: RedisClient) -> Self {
+    Self { redis }
+  }
+
+  /// Deliver the event to every connection of the users on any instance.
+  ///
+  /// Events are best effort, the stored state stays the source of truth, so failures are only logged
+  #[tracing::instrument(name = "publish_event", skip(self, event))]
+  pub async fn publish(&self, user_ids: &[i32], event: &RealtimeEvent) {
+    if user_ids.is_empty() {
+      return;
+    }
+
+    let payload = match serde_json::to_string(event) {
+      Ok(payload) => payload,
+      Err(e) => {
+        warn!("Failed to serialize event: {}", e);
+        return;
+      }
+    };
+
+    let mut pipe = redis::pipe();
+    for user_id in user_ids {
+      pipe.publish(events_channel(*user_id), &payload).ignore();
+    }
+
+    let mut redis = self.redis.connection();
+    if let Err(e) = pipe.query_async::<()>(&mut redis).await {
+      warn!("Failed to publish event to users {:?}: {}", user_ids, e);
+    }
+  }
+
+  /// Mark the user as typing, returns false if they already were and the indicator was only renewed
+  pub async fn set_typing(&self, user_id: i32, conversation: Conversation) -> EventResult<bool> {
+    let key = typing_key(user_id, conversation);
+    let mut redis = self.redis.connection();
+
+    let started: Option<String> = redis::cmd("SET")
+      .arg(&key)
+      .arg(1)
+      .arg("NX")
+      .arg("EX")
+      .arg(TYPING_TTL)
+      .query_async(&mut redis)
+      .await
+      .map_err(EventError::FailedToUpdateTyping)?;
+    if started.is_some() {
+      return Ok(true);
+    }
+
+    let _: bool = redis
+      .expire(&key, TYPING_TTL as i64)
+      .await
+      .map_err(EventError::FailedToUpdateTyping)?;
+    Ok(false)
+  }
+
+  /// Sending a message ends typing, so the next keystroke is announced right away
+  pub async fn clear_typing(&self, user_id: i32, conversation: Conversation) {
+    let mut redis = self.redis.connection();
+    if let Err(e) = redis.del::<_, ()>(typing_key(user_id, conversation)).await {
+      warn!("Failed to clear typing state of user {}: {}", user_id, e);
+    }
+  }
+}
+
+/// Dialogs are keyed by the ordered pair of participants like in the storage
+fn typing_key(user_id: i32, conversation: Conversation) -> String {
+  match conversation {
+    Conversation::Dialog(peer_id) => format!(
+      "{}d:{}:{}:{}",
+      TYPING_PREFIX,
+      user_id.min(peer_id),
+      user_id.max(peer_id),
+      user_id
+    ),
+    Conversation::Group(group_id) => format!("{}g:{}:{}", TYPING_PREFIX, group_id, user_id),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_keys() {
+    assert_eq!(user_from_channel(&events_channel(42)), Some(42));
+    assert_eq!(user_from_channel("unread:42"), None);
+    assert_eq!(
+      typing_key(7, Conversation::Dialog(3)),
+      "typing:d:3:7:7".to_string()
+    );
+    assert_eq!(
+      typing_key(3, Conversation::Dialog(7)),
+      "typing:d:3:7:3".to_string()
+    );
+    assert_eq!(
+      typing_key(3, Conversation::Group(9)),
+      "typing:g:9:3".to_string()
+    );
+  }
+}
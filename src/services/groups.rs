@@ -0,0 +1,1128 @@
+use chrono::{TimeDelta, Utc};
+use sqlx::{PgConnection, PgPool};
+use tracing::{error, warn};
+
+use crate::{
+  dto::{
+    counter::Conversation,
+    dialog::MessageEditDto,
+    event::{DeletedEvent, GroupMessageEvent, RealtimeEvent, ReceiptEvent},
+    group::{
+      GroupDetailsDto, GroupDto, GroupMemberDto, GroupMessageDto, GroupMessageKind,
+      GroupMessagePageDto, GroupMessageResponse, GroupRole, GroupSummaryDto, GroupUnreadCount,
+      MAX_GROUP_MEMBERS,
+    },
+  },
+  errors::group::{GroupError, GroupResult},
+  services::{counters::CounterService, events::EventService},
+};
+
+const MAX_GROUPS: i64 = 100;
+/// Expired messages deleted per statement, so a sweep never holds locks for long
+const PURGE_BATCH: i64 = 1000;
+
+#[derive(Clone, Debug)]
+pub struct GroupService {
+  db: PgPool,
+  counter_service: CounterService,
+  event_service: EventService,
+  edit_window: TimeDelta,
+}
+
+impl GroupService {
+  pub fn new(
+    db: PgPool,
+    counter_service: CounterService,
+    event_service: EventService,
+    edit_window: TimeDelta,
+  ) -> Self {
+    Self {
+      db,
+      counter_service,
+      event_service,
+      edit_window,
+    }
+  }
+
+  /// Create a group administered by the creator
+  #[tracing::instrument(name = "create_group", skip(self))]
+  pub async fn create(
+    &self,
+    creator_id: i32,
+    title: String,
+    member_ids: Vec<i32>,
+  ) -> GroupResult<GroupDetailsDto> {
+    let mut member_ids = member_ids
+      .into_iter()
+      .filter(|member_id| *member_id != creator_id)
+      .collect::<Vec<_>>();
+    member_ids.sort_unstable();
+    member_ids.dedup();
+    if member_ids.len() + 1 > MAX_GROUP_MEMBERS {
+      return Err(GroupError::TooManyMembers(MAX_GROUP_MEMBERS));
+    }
+    self.ensure_users_exist(&member_ids).await?;
+
+    let mut tx = self
+      .db
+      .begin()
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+    let group_id = sqlx::query_scalar!(
+      r#"INSERT INTO group_chats (title, created_by) VALUES ($1, $2) RETURNING id"#,
+      title,
+      creator_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    sqlx::query!(
+      r#"
+        INSERT INTO group_members (group_id, user_id, role)
+        SELECT $1, member_id, CASE WHEN member_id = $2 THEN 'admin' ELSE 'member' END
+        FROM UNNEST(ARRAY_PREPEND($2, $3::INTEGER[])) AS member_id
+      "#,
+      group_id,
+      creator_id,
+      &member_ids
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    Self::insert_system_message(
+      &mut tx,
+      group_id,
+      creator_id,
+      GroupMessageKind::Created,
+      None,
+      format!("created the group \"{}\"", title),
+    )
+    .await?;
+
+    tx.commit().await.map_err(GroupError::FailedToUpdateGroup)?;
+
+    self.get(creator_id, group_id).await
+  }
+
+  /// Group with its members, only visible to the members
+  #[tracing::instrument(name = "get_group", skip(self))]
+  pub async fn get(&self, user_id: i32, group_id: i32) -> GroupResult<GroupDetailsDto> {
+    self.member_role(group_id, user_id).await?;
+
+    let group = sqlx::query_as!(
+      GroupDto,
+      r#"
+        SELECT id, title, created_by, message_ttl, created_at, updated_at
+        FROM group_chats
+        WHERE id = $1
+      "#,
+      group_id
+    )
+    .fetch_optional(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)?
+    .ok_or(GroupError::GroupNotFound(group_id))?;
+
+    let members = sqlx::query_as!(
+      GroupMemberDto,
+      r#"
+        SELECT user_id, role AS "role: GroupRole", joined_at
+        FROM group_members
+        WHERE group_id = $1
+        ORDER BY joined_at, user_id
+      "#,
+      group_id
+    )
+    .fetch_all(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)?;
+
+    Ok(GroupDetailsDto { group, members })
+  }
+
+  /// Groups of the user with the last message and unread count, most recent first
+  #[tracing::instrument(name = "list_groups", skip(self))]
+  pub async fn list(&self, user_id: i32) -> GroupResult<Vec<GroupSummaryDto>> {
+    let rows = sqlx::query!(
+      r#"
+        SELECT
+          g.id,
+          g.title,
+          g.created_by,
+          g.message_ttl,
+          g.created_at,
+          g.updated_at,
+          m.id AS "message_id?",
+          m.sender_id AS "sender_id?",
+          m.kind AS "kind?: GroupMessageKind",
+          m.text AS "text?",
+          m.target_user_id,
+          m.created_at AS "message_created_at?",
+          m.edited_at,
+          m.expires_at,
+          (
+            SELECT COUNT(*)
+            FROM group_messages u
+            WHERE u.group_id = g.id
+              AND u.id > gm.last_read_message_id
+              AND u.kind = 'text'
+              AND u.sender_id <> gm.user_id
+              AND (u.expires_at IS NULL OR u.expires_at > NOW())
+          ) AS "unread_count!"
+        FROM group_members gm
+        JOIN group_chats g ON g.id = gm.group_id
+        LEFT JOIN LATERAL (
+          SELECT id, sender_id, kind, text, target_user_id, created_at, edited_at, expires_at
+          FROM group_messages
+          WHERE group_id = g.id
+            AND (expires_at IS NULL OR expires_at > NOW())
+          ORDER BY id DESC
+          LIMIT 1
+        ) m ON TRUE
+        WHERE gm.user_id = $1
+        ORDER BY COALESCE(m.created_at, g.created_at) DESC
+        LIMIT $2
+      "#,
+      user_id,
+      MAX_GROUPS
+    )
+    .fetch_all(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)?;
+
+    Ok(
+      rows
+        .into_iter()
+        .map(|row| {
+          let last_message = match (
+            row.message_id,
+            row.sender_id,
+            row.kind,
+            row.text,
+            row.message_created_at,
+          ) {
+            (Some(id), Some(sender_id), Some(kind), Some(text), Some(created_at)) => {
+              Some(GroupMessageDto {
+                id,
+                group_id: row.id,
+                sender_id,
+                kind,
+                text,
+                target_user_id: row.target_user_id,
+                created_at,
+                edited_at: row.edited_at,
+                expires_at: row.expires_at,
+              })
+            }
+            _ => None,
+          };
+
+          GroupSummaryDto {
+            group: GroupDto {
+              id: row.id,
+              title: row.title,
+              created_by: row.created_by,
+              message_ttl: row.message_ttl,
+              created_at: row.created_at,
+              updated_at: row.updated_at,
+            },
+            last_message,
+            unread_count: row.unread_count,
+          }
+        })
+        .collect(),
+    )
+  }
+
+  #[tracing::instrument(name = "rename_group", skip(self))]
+  pub async fn rename(
+    &self,
+    user_id: i32,
+    group_id: i32,
+    title: String,
+  ) -> GroupResult<GroupDetailsDto> {
+    self.member_role(group_id, user_id).await?;
+
+    let mut tx = self
+      .db
+      .begin()
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+    sqlx::query!(
+      r#"UPDATE group_chats SET title = $2, updated_at = NOW() WHERE id = $1"#,
+      group_id,
+      title
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    Self::insert_system_message(
+      &mut tx,
+      group_id,
+      user_id,
+      GroupMessageKind::Renamed,
+      None,
+      format!("renamed the group to \"{}\"", title),
+    )
+    .await?;
+
+    tx.commit().await.map_err(GroupError::FailedToUpdateGroup)?;
+
+    self.get(user_id, group_id).await
+  }
+
+  #[tracing::instrument(name = "add_group_member", skip(self))]
+  pub async fn add_member(
+    &self,
+    admin_id: i32,
+    group_id: i32,
+    user_id: i32,
+  ) -> GroupResult<GroupDetailsDto> {
+    self.ensure_admin(group_id, admin_id).await?;
+    self.ensure_users_exist(&[user_id]).await?;
+
+    let mut tx = self
+      .db
+      .begin()
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+    // The group row lock serializes concurrent additions against the member limit
+    sqlx::query_scalar!(
+      r#"SELECT id FROM group_chats WHERE id = $1 FOR UPDATE"#,
+      group_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    let members = sqlx::query_scalar!(
+      r#"SELECT COUNT(*) AS "count!" FROM group_members WHERE group_id = $1"#,
+      group_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+    if members as usize >= MAX_GROUP_MEMBERS {
+      return Err(GroupError::TooManyMembers(MAX_GROUP_MEMBERS));
+    }
+
+    // New members start reading from the moment they joined
+    let added = sqlx::query!(
+      r#"
+        INSERT INTO group_members (group_id, user_id, last_read_message_id)
+        VALUES ($1, $2, COALESCE((SELECT MAX(id) FROM group_messages WHERE group_id = $1), 0))
+        ON CONFLICT (group_id, user_id) DO NOTHING
+      "#,
+      group_id,
+      user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?
+    .rows_affected();
+    if added == 0 {
+      return Err(GroupError::AlreadyMember(user_id));
+    }
+
+    Self::insert_system_message(
+      &mut tx,
+      group_id,
+      admin_id,
+      GroupMessageKind::MemberAdded,
+      Some(user_id),
+      "added a member".to_string(),
+    )
+    .await?;
+
+    tx.commit().await.map_err(GroupError::FailedToUpdateGroup)?;
+
+    self.get(admin_id, group_id).await
+  }
+
+  #[tracing::instrument(name = "remove_group_member", skip(self))]
+  pub async fn remove_member(
+    &self,
+    admin_id: i32,
+    group_id: i32,
+    user_id: i32,
+  ) -> GroupResult<GroupDetailsDto> {
+    if admin_id == user_id {
+      return Err(GroupError::RemoveSelf);
+    }
+    self.ensure_admin(group_id, admin_id).await?;
+
+    let mut tx = self
+      .db
+      .begin()
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+    if !Self::delete_member(&mut tx, group_id, user_id).await? {
+      return Err(GroupError::NotMember(user_id));
+    }
+
+    Self::insert_system_message(
+      &mut tx,
+      group_id,
+      admin_id,
+      GroupMessageKind::MemberRemoved,
+      Some(user_id),
+      "removed a member".to_string(),
+    )
+    .await?;
+
+    tx.commit().await.map_err(GroupError::FailedToUpdateGroup)?;
+    self.drop_counter(user_id, group_id).await;
+
+    self.get(admin_id, group_id).await
+  }
+
+  /// Leave the group, the longest member becomes the admin if the last one leaves
+  #[tracing::instrument(name = "leave_group", skip(self))]
+  pub async fn leave(&self, user_id: i32, group_id: i32) -> GroupResult<()> {
+    self.member_role(group_id, user_id).await?;
+
+    let mut tx = self
+      .db
+      .begin()
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+    if !Self::delete_member(&mut tx, group_id, user_id).await? {
+      return Err(GroupError::GroupNotFound(group_id));
+    }
+
+    sqlx::query!(
+      r#"
+        UPDATE group_members
+        SET role = 'admin'
+        WHERE group_id = $1
+          AND NOT EXISTS (SELECT 1 FROM group_members WHERE group_id = $1 AND role = 'admin')
+          AND user_id = (
+            SELECT user_id FROM group_members
+            WHERE group_id = $1
+            ORDER BY joined_at, user_id
+            LIMIT 1
+          )
+      "#,
+      group_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    Self::insert_system_message(
+      &mut tx,
+      group_id,
+      user_id,
+      GroupMessageKind::MemberLeft,
+      Some(user_id),
+      "left the group".to_string(),
+    )
+    .await?;
+
+    tx.commit().await.map_err(GroupError::FailedToUpdateGroup)?;
+    self.drop_counter(user_id, group_id).await;
+
+    Ok(())
+  }
+
+  /// Store the message and count it as unread for the other members.
+  ///
+  /// Counters go first, so a failed message write is compensated by taking them back
+  #[tracing::instrument(name = "send_group_message", skip(self, text))]
+  pub async fn send(
+    &self,
+    sender_id: i32,
+    group_id: i32,
+    text: String,
+  ) -> GroupResult<GroupMessageDto> {
+    self.member_role(group_id, sender_id).await?;
+
+    let recipient_ids = sqlx::query_scalar!(
+      r#"SELECT user_id FROM group_members WHERE group_id = $1 AND user_id <> $2"#,
+      group_id,
+      sender_id
+    )
+    .fetch_all(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)?;
+
+    let mut counted = Vec::with_capacity(recipient_ids.len());
+    for recipient_id in recipient_ids {
+      match self
+        .counter_service
+        .increment(recipient_id, Conversation::Group(group_id), 1)
+        .await
+      {
+        Ok(_) => counted.push(recipient_id),
+        Err(e) => {
+          self.compensate_counters(&counted, group_id).await;
+          return Err(e.into());
+        }
+      }
+    }
+
+    let message = sqlx::query_as!(
+      GroupMessageDto,
+      r#"
+        INSERT INTO group_messages (group_id, sender_id, kind, text, expires_at)
+        VALUES (
+          $1, $2, 'text', $3,
+          NOW() + (SELECT message_ttl FROM group_chats WHERE id = $1) * INTERVAL '1 second'
+        )
+        RETURNING
+          id,
+          group_id,
+          sender_id,
+          kind AS "kind: GroupMessageKind",
+          text,
+          target_user_id,
+          created_at,
+          edited_at,
+          expires_at
+      "#,
+      group_id,
+      sender_id,
+      text
+    )
+    .fetch_one(&self.db)
+    .await;
+
+    match message {
+      Ok(message) => {
+        let event = RealtimeEvent::GroupMessage(GroupMessageEvent {
+          conversation: Conversation::Group(group_id),
+          message: GroupMessageResponse::from(message.clone()),
+        });
+        let mut viewer_ids = counted;
+        viewer_ids.push(sender_id);
+        self.event_service.publish(&viewer_ids, &event).await;
+        self
+          .event_service
+          .clear_typing(sender_id, Conversation::Group(group_id))
+          .await;
+        Ok(message)
+      }
+      Err(e) => {
+        self.compensate_counters(&counted, group_id).await;
+        Err(GroupError::FailedToUpdateGroup(e))
+      }
+    }
+  }
+
+  /// Group history, newest first.
+  ///
+  /// Fetching the first page marks the group as read for the member
+  #[tracing::instrument(name = "list_group_messages", skip(self))]
+  pub async fn history(
+    &self,
+    user_id: i32,
+    group_id: i32,
+    cursor: Option<i64>,
+    limit: i64,
+  ) -> GroupResult<GroupMessagePageDto> {
+    self.member_role(group_id, user_id).await?;
+
+    // Fetch one extra row to know whether there is a next page
+    let mut messages = sqlx::query_as!(
+      GroupMessageDto,
+      r#"
+        SELECT
+          id,
+          group_id,
+          sender_id,
+          kind AS "kind: GroupMessageKind",
+          text,
+          target_user_id,
+          created_at,
+          edited_at,
+          expires_at
+        FROM group_messages
+        WHERE group_id = $1
+          AND ($2::BIGINT IS NULL OR id < $2)
+          AND (expires_at IS NULL OR expires_at > NOW())
+        ORDER BY id DESC
+        LIMIT $3
+      "#,
+      group_id,
+      cursor,
+      limit + 1
+    )
+    .fetch_all(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)?;
+
+    let next_cursor = if messages.len() as i64 > limit {
+      messages.truncate(limit as usize);
+      messages.last().map(|message| message.id)
+    } else {
+      None
+    };
+
+    if let (None, Some(newest)) = (cursor, messages.first()) {
+      self.mark_read(user_id, group_id, newest.id).await?;
+    }
+
+    Ok(GroupMessagePageDto {
+      messages,
+      next_cursor,
+    })
+  }
+
+  /// Read receipt of the member, the cursor is capped by the newest message of the group
+  #[tracing::instrument(name = "read_group", skip(self))]
+  pub async fn read(&self, user_id: i32, group_id: i32, message_id: i64) -> GroupResult<()> {
+    self.member_role(group_id, user_id).await?;
+
+    let newest = sqlx::query_scalar!(
+      r#"SELECT MAX(id) FROM group_messages WHERE group_id = $1"#,
+      group_id
+    )
+    .fetch_one(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)?;
+
+    match newest {
+      Some(newest) => {
+        self
+          .mark_read(user_id, group_id, message_id.min(newest))
+          .await
+      }
+      None => Ok(()),
+    }
+  }
+
+  /// Replace the text of the member's own message within the edit window, keeping the previous one
+  #[tracing::instrument(name = "edit_group_message", skip(self, text))]
+  pub async fn edit(
+    &self,
+    user_id: i32,
+    group_id: i32,
+    message_id: i64,
+    text: String,
+  ) -> GroupResult<GroupMessageDto> {
+    self.member_role(group_id, user_id).await?;
+
+    let mut tx = self
+      .db
+      .begin()
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+    // The row lock orders concurrent edits, so versions are taken one after another
+    let message = Self::lock_message(&mut tx, group_id, message_id).await?;
+    Self::ensure_own_text(&message, user_id)?;
+    if message.created_at + self.edit_window < Utc::now() {
+      return Err(GroupError::EditWindowExpired(message_id));
+    }
+
+    sqlx::query!(
+      r#"
+        INSERT INTO group_message_edits (message_id, version, text)
+        SELECT $1, COALESCE((
+          SELECT MAX(version) FROM group_message_edits WHERE message_id = $1
+        ), 0) + 1, text
+        FROM group_messages
+        WHERE id = $1
+      "#,
+      message_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    let message = sqlx::query_as!(
+      GroupMessageDto,
+      r#"
+        UPDATE group_messages
+        SET text = $2, edited_at = NOW()
+        WHERE id = $1
+        RETURNING
+          id,
+          group_id,
+          sender_id,
+          kind AS "kind: GroupMessageKind",
+          text,
+          target_user_id,
+          created_at,
+          edited_at,
+          expires_at
+      "#,
+      message_id,
+      text
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    tx.commit().await.map_err(GroupError::FailedToUpdateGroup)?;
+
+    let event = RealtimeEvent::GroupMessageEdited(GroupMessageEvent {
+      conversation: Conversation::Group(group_id),
+      message: GroupMessageResponse::from(message.clone()),
+    });
+    self.notify_members(group_id, &event).await;
+
+    Ok(message)
+  }
+
+  /// Previous versions of the message, oldest first
+  #[tracing::instrument(name = "list_group_message_edits", skip(self))]
+  pub async fn edits(
+    &self,
+    user_id: i32,
+    group_id: i32,
+    message_id: i64,
+  ) -> GroupResult<Vec<MessageEditDto>> {
+    self.member_role(group_id, user_id).await?;
+
+    let exists = sqlx::query_scalar!(
+      r#"
+        SELECT EXISTS(
+          SELECT 1 FROM group_messages
+          WHERE id = $1 AND group_id = $2
+            AND (expires_at IS NULL OR expires_at > NOW())
+        ) AS "exists!"
+      "#,
+      message_id,
+      group_id
+    )
+    .fetch_one(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)?;
+    if !exists {
+      return Err(GroupError::MessageNotFound(message_id));
+    }
+
+    sqlx::query_as!(
+      MessageEditDto,
+      r#"
+        SELECT version, text, edited_at
+        FROM group_message_edits
+        WHERE message_id = $1
+        ORDER BY version
+      "#,
+      message_id
+    )
+    .fetch_all(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)
+  }
+
+  /// Delete the member's own message for everyone, members who had not read it get their counters back
+  #[tracing::instrument(name = "delete_group_message", skip(self))]
+  pub async fn delete(&self, user_id: i32, group_id: i32, message_id: i64) -> GroupResult<()> {
+    self.member_role(group_id, user_id).await?;
+
+    let mut tx = self
+      .db
+      .begin()
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+    let message = Self::lock_message(&mut tx, group_id, message_id).await?;
+    Self::ensure_own_text(&message, user_id)?;
+
+    let unread_by = sqlx::query_scalar!(
+      r#"
+        SELECT user_id FROM group_members
+        WHERE group_id = $1 AND user_id <> $2 AND last_read_message_id < $3
+      "#,
+      group_id,
+      user_id,
+      message_id
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    sqlx::query!(r#"DELETE FROM group_messages WHERE id = $1"#, message_id)
+      .execute(&mut *tx)
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+    tx.commit().await.map_err(GroupError::FailedToUpdateGroup)?;
+
+    self.compensate_counters(&unread_by, group_id).await;
+
+    let event = RealtimeEvent::MessageDeleted(DeletedEvent {
+      conversation: Conversation::Group(group_id),
+      message_id,
+    });
+    self.notify_members(group_id, &event).await;
+
+    Ok(())
+  }
+
+  /// Set the disappearing messages timeout for messages sent from now on, any member can change it
+  #[tracing::instrument(name = "set_group_message_ttl", skip(self))]
+  pub async fn set_message_ttl(
+    &self,
+    user_id: i32,
+    group_id: i32,
+    seconds: Option<i32>,
+  ) -> GroupResult<GroupDetailsDto> {
+    self.member_role(group_id, user_id).await?;
+
+    let mut tx = self
+      .db
+      .begin()
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+    sqlx::query!(
+      r#"UPDATE group_chats SET message_ttl = $2, updated_at = NOW() WHERE id = $1"#,
+      group_id,
+      seconds
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    let text = match seconds {
+      Some(seconds) => format!("set disappearing messages to {} seconds", seconds),
+      None => "turned off disappearing messages".to_string(),
+    };
+    Self::insert_system_message(
+      &mut tx,
+      group_id,
+      user_id,
+      GroupMessageKind::TtlChanged,
+      None,
+      text,
+    )
+    .await?;
+
+    tx.commit().await.map_err(GroupError::FailedToUpdateGroup)?;
+
+    self.get(user_id, group_id).await
+  }
+
+  /// Delete expired messages in batches, returning their number and how many of them each member
+  /// had not read
+  pub async fn purge_expired(&self) -> GroupResult<(usize, Vec<GroupUnreadCount>)> {
+    let mut purged_count = 0;
+    let mut unread = Vec::new();
+
+    loop {
+      let mut tx = self
+        .db
+        .begin()
+        .await
+        .map_err(GroupError::FailedToUpdateGroup)?;
+
+      let purged = sqlx::query!(
+        r#"
+          DELETE FROM group_messages
+          WHERE id IN (
+            SELECT id FROM group_messages
+            WHERE expires_at <= NOW()
+            LIMIT $1
+          )
+          RETURNING id, group_id, sender_id, kind AS "kind: GroupMessageKind"
+        "#,
+        PURGE_BATCH
+      )
+      .fetch_all(&mut *tx)
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+      let texts = purged
+        .iter()
+        .filter(|message| message.kind == GroupMessageKind::Text)
+        .collect::<Vec<_>>();
+      let batch = sqlx::query_as!(
+        GroupUnreadCount,
+        r#"
+          SELECT gm.user_id, gm.group_id, COUNT(*) AS "unread!"
+          FROM UNNEST($1::BIGINT[], $2::INTEGER[], $3::INTEGER[]) AS m(id, group_id, sender_id)
+          JOIN group_members gm
+            ON gm.group_id = m.group_id
+            AND m.id > gm.last_read_message_id
+            AND m.sender_id <> gm.user_id
+          GROUP BY gm.user_id, gm.group_id
+        "#,
+        &texts.iter().map(|message| message.id).collect::<Vec<_>>(),
+        &texts
+          .iter()
+          .map(|message| message.group_id)
+          .collect::<Vec<_>>(),
+        &texts
+          .iter()
+          .map(|message| message.sender_id)
+          .collect::<Vec<_>>()
+      )
+      .fetch_all(&mut *tx)
+      .await
+      .map_err(GroupError::FailedToUpdateGroup)?;
+
+      tx.commit().await.map_err(GroupError::FailedToUpdateGroup)?;
+
+      purged_count += purged.len();
+      unread.extend(batch);
+      if (purged.len() as i64) < PURGE_BATCH {
+        return Ok((purged_count, unread));
+      }
+    }
+  }
+
+  /// Members of the group, available to its members only
+  pub async fn members_of(&self, user_id: i32, group_id: i32) -> GroupResult<Vec<i32>> {
+    self.member_role(group_id, user_id).await?;
+    self.member_ids(group_id).await
+  }
+
+  /// Non-zero unread counts of every member, used to rebuild the counters
+  pub async fn unread_counts(&self) -> GroupResult<Vec<GroupUnreadCount>> {
+    sqlx::query_as!(
+      GroupUnreadCount,
+      r#"
+        SELECT gm.user_id, gm.group_id, COUNT(*) AS "unread!"
+        FROM group_members gm
+        JOIN group_messages m
+          ON m.group_id = gm.group_id
+          AND m.id > gm.last_read_message_id
+          AND m.kind = 'text'
+          AND m.sender_id <> gm.user_id
+          AND (m.expires_at IS NULL OR m.expires_at > NOW())
+        GROUP BY gm.user_id, gm.group_id
+      "#
+    )
+    .fetch_all(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)
+  }
+
+  /// Move the read state and set the unread counter to the messages still newer than it. The
+  /// read state is the source of truth, a failed counter update is left to reconciliation
+  async fn mark_read(&self, user_id: i32, group_id: i32, message_id: i64) -> GroupResult<()> {
+    let unread = sqlx::query_scalar!(
+      r#"
+        WITH updated AS (
+          UPDATE group_members
+          SET last_read_message_id = GREATEST(last_read_message_id, $3)
+          WHERE group_id = $1 AND user_id = $2
+          RETURNING last_read_message_id
+        )
+        SELECT COUNT(m.id) AS "unread!"
+        FROM updated gm
+        JOIN group_messages m
+          ON m.group_id = $1
+          AND m.id > gm.last_read_message_id
+          AND m.kind = 'text'
+          AND m.sender_id <> $2
+          AND (m.expires_at IS NULL OR m.expires_at > NOW())
+      "#,
+      group_id,
+      user_id,
+      message_id
+    )
+    .fetch_one(&self.db)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    if let Err(e) = self
+      .counter_service
+      .set(user_id, Conversation::Group(group_id), unread)
+      .await
+    {
+      error!(
+        "Failed to update unread counter of user {}: {}, left to reconciliation",
+        user_id, e
+      );
+    }
+
+    match self.member_ids(group_id).await {
+      Ok(member_ids) => {
+        let receipt = RealtimeEvent::Read(ReceiptEvent {
+          conversation: Conversation::Group(group_id),
+          user_id,
+          message_id,
+        });
+        self.event_service.publish(&member_ids, &receipt).await;
+      }
+      Err(e) => warn!(
+        "Failed to notify group {} about read receipt: {}",
+        group_id, e
+      ),
+    }
+
+    Ok(())
+  }
+
+  async fn notify_members(&self, group_id: i32, event: &RealtimeEvent) {
+    match self.member_ids(group_id).await {
+      Ok(member_ids) => self.event_service.publish(&member_ids, event).await,
+      Err(e) => warn!("Failed to notify group {}: {}", group_id, e),
+    }
+  }
+
+  async fn member_ids(&self, group_id: i32) -> GroupResult<Vec<i32>> {
+    sqlx::query_scalar!(
+      r#"SELECT user_id FROM group_members WHERE group_id = $1"#,
+      group_id
+    )
+    .fetch_all(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)
+  }
+
+  async fn compensate_counters(&self, user_ids: &[i32], group_id: i32) {
+    for user_id in user_ids {
+      if let Err(e) = self
+        .counter_service
+        .increment(*user_id, Conversation::Group(group_id), -1)
+        .await
+      {
+        error!(
+          "Failed to compensate unread counter of user {}: {}, left to reconciliation",
+          user_id, e
+        );
+      }
+    }
+  }
+
+  /// Former members have nothing to read, a stale counter is fixed by the reconciliation
+  async fn drop_counter(&self, user_id: i32, group_id: i32) {
+    if let Err(e) = self
+      .counter_service
+      .reset(user_id, Conversation::Group(group_id))
+      .await
+    {
+      warn!(
+        "Failed to drop unread counter of user {} in group {}: {}",
+        user_id, group_id, e
+      );
+    }
+  }
+
+  /// Role of the member, groups of other users are reported as missing
+  async fn member_role(&self, group_id: i32, user_id: i32) -> GroupResult<GroupRole> {
+    sqlx::query_scalar!(
+      r#"
+        SELECT role AS "role: GroupRole"
+        FROM group_members
+        WHERE group_id = $1 AND user_id = $2
+      "#,
+      group_id,
+      user_id
+    )
+    .fetch_optional(&self.db)
+    .await
+    .map_err(GroupError::FailedToFindGroups)?
+    .ok_or(GroupError::GroupNotFound(group_id))
+  }
+
+  async fn ensure_admin(&self, group_id: i32, user_id: i32) -> GroupResult<()> {
+    match self.member_role(group_id, user_id).await? {
+      GroupRole::Admin => Ok(()),
+      GroupRole::Member => Err(GroupError::NotAdmin(group_id)),
+    }
+  }
+
+  async fn ensure_users_exist(&self, user_ids: &[i32]) -> GroupResult<()> {
+    let existing = sqlx::query_scalar!(r#"SELECT id FROM users WHERE id = ANY($1)"#, user_ids)
+      .fetch_all(&self.db)
+      .await
+      .map_err(GroupError::FailedToFindGroups)?;
+
+    match user_ids.iter().find(|user_id| !existing.contains(user_id)) {
+      Some(user_id) => Err(GroupError::UserNotFound(*user_id)),
+      None => Ok(()),
+    }
+  }
+
+  async fn lock_message(
+    connection: &mut PgConnection,
+    group_id: i32,
+    message_id: i64,
+  ) -> GroupResult<GroupMessageDto> {
+    sqlx::query_as!(
+      GroupMessageDto,
+      r#"
+        SELECT
+          id,
+          group_id,
+          sender_id,
+          kind AS "kind: GroupMessageKind",
+          text,
+          target_user_id,
+          created_at,
+          edited_at,
+          expires_at
+        FROM group_messages
+        WHERE id = $1 AND group_id = $2
+          AND (expires_at IS NULL OR expires_at > NOW())
+        FOR UPDATE
+      "#,
+      message_id,
+      group_id
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?
+    .ok_or(GroupError::MessageNotFound(message_id))
+  }
+
+  fn ensure_own_text(message: &GroupMessageDto, user_id: i32) -> GroupResult<()> {
+    if message.kind != GroupMessageKind::Text {
+      return Err(GroupError::SystemMessage(message.id));
+    }
+    if message.sender_id != user_id {
+      return Err(GroupError::NotMessageSender(message.id));
+    }
+
+    Ok(())
+  }
+
+  async fn delete_member(
+    connection: &mut PgConnection,
+    group_id: i32,
+    user_id: i32,
+  ) -> GroupResult<bool> {
+    let deleted = sqlx::query!(
+      r#"DELETE FROM group_members WHERE group_id = $1 AND user_id = $2"#,
+      group_id,
+      user_id
+    )
+    .execute(connection)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?
+    .rows_affected();
+
+    Ok(deleted > 0)
+  }
+
+  async fn insert_system_message(
+    connection: &mut PgConnection,
+    group_id: i32,
+    actor_id: i32,
+    kind: GroupMessageKind,
+    target_user_id: Option<i32>,
+    text: String,
+  ) -> GroupResult<()> {
+    sqlx::query!(
+      r#"
+        INSERT INTO group_messages (group_id, sender_id, kind, text, target_user_id)
+        VALUES ($1, $2, $3, $4, $5)
+      "#,
+      group_id,
+      actor_id,
+      kind as GroupMessageKind,
+      text,
+      target_user_id
+    )
+    .execute(connection)
+    .await
+    .map_err(GroupError::FailedToUpdateGroup)?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,210 @@
+use std::{future::Future, time::Duration};
+
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::{
+  db::{replicas::PgCluster, RedisClient},
+  dto::health::{DependencyHealthResponse, HealthStatus, ReadinessResponse},
+  services::encryption::EncryptionService,
+};
+
+/// Checks of the dependencies an instance needs to serve requests
+#[derive(Clone, Debug)]
+pub struct HealthService {
+  db: PgCluster,
+  redis: RedisClient,
+  encryption_service: EncryptionService,
+  timeout: Duration,
+}
+
+impl HealthService {
+  pub fn new(
+    db: PgCluster,
+    redis: RedisClient,
+    encryption_service: EncryptionService,
+    timeout: Duration,
+  ) -> Self {
+    Self {
+      db,
+      redis,
+      encryption_service,
+      timeout,
+    }
+  }
+
+  /// Check every dependency concurrently, each one within the timeout
+  #[tracing::instrument(name = "check_readiness", skip(self))]
+  pub async fn readiness(&self) -> ReadinessResponse {
+    let postgres = self.check("postgres", async {
+      sqlx::query("SELECT 1")
+        .execute(&self.db.write())
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+    });
+    let redis = self.check("redis", async {
+      redis::cmd("PING")
+        .query_async::<String>(&mut self.redis.connection())
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+    });
+    let hashing = self.check("hashing_pool", async {
+      self
+        .encryption_service
+        .ping()
+        .await
+        .map_err(|e| e.to_string())
+    });
+
+    let (postgres, mut redis, hashing) = tokio::join!(postgres, redis, hashing);
+    redis.circuit_breaker = Some(self.redis.circuit_state());
+    let dependencies = vec![postgres, redis, hashing];
+    let status = if dependencies
+      .iter()
+      .all(|dependency| dependency.status == HealthStatus::Up)
+    {
+      HealthStatus::Up
+    } else {
+      HealthStatus::Down
+    };
+
+    ReadinessResponse {
+      status,
+      dependencies,
+    }
+  }
+
+  async fn check(
+    &self,
+    name: &str,
+    probe: impl Future<Output = Result<(), String>>,
+  ) -> DependencyHealthResponse {
+    let started = Instant::now();
+    let result = tokio::time::timeout(self.timeout, probe)
+      .await
+      .unwrap_or_else(|_| Err(format!("no answer in {} ms", self.timeout.as_millis())));
+
+    // The probe is public, the reason stays in the logs
+    let status = match result {
+      Ok(_) => HealthStatus::Up,
+      Err(e) => {
+        warn!("Readiness check of {} failed: {}", name, e);
+        HealthStatus::Down
+      }
+    };
+
+    DependencyHealthResponse {
+      name: name.to_string(),
+      status,
+      latency_ms: started.elapsed().as_millis() as u64,
+      circuit_breaker: None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::future::{pending, ready};
+
+  use axum::{body::to_bytes, http::StatusCode, response::IntoResponse};
+  use sqlx::postgres::PgPoolOptions;
+
+  use super::*;
+  use crate::db::{testing::FakeRedis, RedisOptions};
+
+  const TIMEOUT: Duration = Duration::from_millis(100);
+
+  async fn health_service(redis: &FakeRedis) -> HealthService {
+    // Nothing listens there, connecting fails right away
+    let postgres = PgPoolOptions::new()
+      .connect_lazy("postgres://localhost:1/app")
+      .unwrap();
+    let redis = RedisClient::connect(
+      &redis.url,
+      RedisOptions {
+        response_timeout: TIMEOUT,
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+
+    HealthService::new(
+      PgCluster::new(postgres, vec![]),
+      redis,
+      EncryptionService::new(),
+      TIMEOUT,
+    )
+  }
+
+  #[tokio::test]
+  async fn failing_and_silent_dependencies_are_down() {
+    let service = health_service(&FakeRedis::start().await).await;
+
+    let up = service.check("up", ready(Ok(()))).await;
+    assert_eq!(up.status, HealthStatus::Up);
+
+    let failing = service
+      .check("failing", ready(Err("refused".to_string())))
+      .await;
+    assert_eq!(failing.status, HealthStatus::Down);
+
+    let silent = service.check("silent", pending()).await;
+    assert_eq!(silent.status, HealthStatus::Down);
+    assert!(silent.latency_ms >= TIMEOUT.as_millis() as u64);
+  }
+
+  #[tokio::test]
+  async fn one_dependency_down_makes_the_instance_unready() {
+    let service = health_service(&FakeRedis::start().await).await;
+
+    let report = service.readiness().await;
+    let statuses = report
+      .dependencies
+      .iter()
+      .map(|dependency| (dependency.name.as_str(), dependency.status))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      statuses,
+      [
+        ("postgres", HealthStatus::Down),
+        ("redis", HealthStatus::Up),
+        ("hashing_pool", HealthStatus::Up),
+      ]
+    );
+    assert_eq!(report.status, HealthStatus::Down);
+
+    let response = report.into_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["dependencies"][0]["status"], "down");
+  }
+
+  #[tokio::test]
+  async fn silent_redis_makes_the_instance_unready() {
+    let redis = FakeRedis::start().await;
+    let service = health_service(&redis).await;
+    redis.set_responsive(false);
+
+    let report = service.readiness().await;
+    assert_eq!(report.dependencies[1].status, HealthStatus::Down);
+    assert_eq!(report.status, HealthStatus::Down);
+    assert_eq!(
+      report.into_response().status(),
+      StatusCode::SERVICE_UNAVAILABLE
+    );
+  }
+
+  #[test]
+  fn ready_instances_answer_ok() {
+    let report = ReadinessResponse {
+      status: HealthStatus::Up,
+      dependencies: vec![],
+    };
+    assert_eq!(report.into_response().status(), StatusCode::OK);
+  }
+}
@@ -0,0 +1,269 @@
+use std::time::Duration;
+
+use axum::{
+  body::{Body, Bytes},
+  http::{response::Parts, HeaderName, HeaderValue, StatusCode},
+  response::{IntoResponse, Response},
+};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+  config::AppConfig,
+  db::RedisClient,
+  errors::idempotency::{IdempotencyError, IdempotencyResult},
+};
+
+const IDEMPOTENCY_PREFIX: &str = "idempotency:";
+/// Time past the request timeout an unfinished request keeps its key
+const IN_PROGRESS_GRACE: Duration = Duration::from_secs(1);
+
+/// Response stored for the replays of the request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredResponse {
+  status: u16,
+  headers: Vec<(String, String)>,
+  /// Base64 encoded body
+  body: String,
+}
+
+impl StoredResponse {
+  pub fn new(parts: &Parts, body: &Bytes) -> Self {
+    Self {
+      status: parts.status.as_u16(),
+      headers: parts
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect(),
+      body: BASE64_STANDARD.encode(body),
+    }
+  }
+}
+
+impl IntoResponse for StoredResponse {
+  fn into_response(self) -> Response {
+    let body = BASE64_STANDARD.decode(&self.body).unwrap_or_default();
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+    for (name, value) in self.headers {
+      if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+        response.headers_mut().append(name, value);
+      }
+    }
+
+    response
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum Record {
+  InProgress {
+    request_hash: String,
+  },
+  Completed {
+    request_hash: String,
+    response: StoredResponse,
+  },
+}
+
+impl Record {
+  fn request_hash(&self) -> &str {
+    match self {
+      Self::InProgress { request_hash } | Self::Completed { request_hash, .. } => request_hash,
+    }
+  }
+}
+
+/// Outcome of claiming an idempotency key
+#[derive(Debug)]
+pub enum Claim {
+  /// First request with the key, it has to be served and completed
+  Acquired,
+  /// The request was already served
+  Replay(StoredResponse),
+}
+
+/// Responses of the requests sent with an `Idempotency-Key`, stored in Redis per client and key
+#[derive(Clone, Debug)]
+pub struct IdempotencyService {
+  redis: RedisClient,
+  in_progress_ttl: Duration,
+}
+
+impl IdempotencyService {
+  pub fn new(redis: RedisClient, config: &AppConfig) -> Self {
+    Self {
+      redis,
+      in_progress_ttl: Duration::from_millis(config.request_timeout) + IN_PROGRESS_GRACE,
+    }
+  }
+
+  /// Claim the key for the request, or return the response of the request that claimed it
+  #[tracing::instrument(name = "idempotency_service::claim", skip(self))]
+  pub async fn claim(
+    &self,
+    client: &str,
+    key: &str,
+    request_hash: &str,
+  ) -> IdempotencyResult<Claim> {
+    let record = serde_json::to_string(&Record::InProgress {
+      request_hash: request_hash.to_string(),
+    })
+    .expect("idempotency record is serializable");
+    let options = redis::SetOptions::default()
+      .conditional_set(redis::ExistenceCheck::NX)
+      .with_expiration(redis::SetExpiry::PX(self.in_progress_ttl.as_millis() as u64));
+    let mut connection = self.redis.connection();
+    let claimed = connection
+      .set_options::<_, _, Option<String>>(idempotency_key(client, key), record, options)
+      .await
+      .map_err(IdempotencyError::FailedToStore)?;
+    if claimed.is_some() {
+      return Ok(Claim::Acquired);
+    }
+
+    // Expired since the claim failed, the other request is still treated as in progress
+    let record = connection
+      .get::<_, Option<String>>(idempotency_key(client, key))
+      .await
+      .map_err(IdempotencyError::FailedToStore)?
+      .and_then(|record| serde_json::from_str::<Record>(&record).ok())
+      .ok_or(IdempotencyError::InProgress)?;
+    if record.request_hash() != request_hash {
+      return Err(IdempotencyError::KeyReused);
+    }
+
+    match record {
+      Record::InProgress { .. } => Err(IdempotencyError::InProgress),
+      Record::Completed { response, .. } => Ok(Claim::Replay(response)),
+    }
+  }
+
+  /// Store the response of the request that claimed the key for `ttl`
+  #[tracing::instrument(name = "idempotency_service::complete", skip(self, response))]
+  pub async fn complete(
+    &self,
+    client: &str,
+    key: &str,
+    request_hash: &str,
+    response: StoredResponse,
+    ttl: Duration,
+  ) {
+    let record = serde_json::to_string(&Record::Completed {
+      request_hash: request_hash.to_string(),
+      response,
+    })
+    .expect("idempotency record is serializable");
+
+    if let Err(e) = self
+      .redis
+      .connection()
+      .set_ex::<_, _, ()>(idempotency_key(client, key), record, ttl.as_secs().max(1))
+      .await
+    {
+      warn!("Failed to store response of idempotency key {}: {}", key, e);
+    }
+  }
+
+  /// Release the key of a request that failed, so that its retry is served again
+  #[tracing::instrument(name = "idempotency_service::release", skip(self))]
+  pub async fn release(&self, client: &str, key: &str) {
+    if let Err(e) = self
+      .redis
+      .connection()
+      .del::<_, ()>(idempotency_key(client, key))
+      .await
+    {
+      warn!("Failed to release idempotency key {}: {}", key, e);
+    }
+  }
+}
+
+fn idempotency_key(client: &str, key: &str) -> String {
+  format!("{}{}:{}", IDEMPOTENCY_PREFIX, client, key)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn stored_response_is_restored() {
+    let response = Response::builder()
+      .status(StatusCode::CREATED)
+      .header("content-type", "application/json")
+      .body(())
+      .unwrap();
+    let (parts, _) = response.into_parts();
+    let stored = StoredResponse::new(&parts, &Bytes::from_static(b"{\"id\":1}"));
+    let stored: StoredResponse =
+      serde_json::from_str(&serde_json::to_string(&stored).unwrap()).unwrap();
+
+    let restored = stored.into_response();
+    assert_eq!(restored.status(), StatusCode::CREATED);
+    assert_eq!(
+      restored.headers().get("content-type").unwrap(),
+      "application/json"
+    );
+    let body = axum::body::to_bytes(restored.into_body(), usize::MAX)
+      .await
+      .unwrap();
+    assert_eq!(&body[..], b"{\"id\":1}");
+  }
+
+  #[tokio::test]
+  #[ignore = "requires redis from docker-compose.yml"]
+  async fn key_is_claimed_once_and_replayed() {
+    let service = IdempotencyService {
+      redis: RedisClient::connect(&std::env::var("REDIS_URL").unwrap(), Default::default())
+        .await
+        .unwrap(),
+      in_progress_ttl: Duration::from_secs(60),
+    };
+    let key = format!("sn_test_{}", std::process::id());
+    service.release("user:1", &key).await;
+
+    assert!(matches!(
+      service.claim("user:1", &key, "hash").await,
+      Ok(Claim::Acquired)
+    ));
+    assert!(matches!(
+      service.claim("user:1", &key, "hash").await,
+      Err(IdempotencyError::InProgress)
+    ));
+    assert!(matches!(
+      service.claim("user:1", &key, "other").await,
+      Err(IdempotencyError::KeyReused)
+    ));
+    assert!(
+      matches!(
+        service.claim("user:2", &key, "other").await,
+        Ok(Claim::Acquired)
+      ),
+      "keys are scoped by client"
+    );
+
+    let (parts, _) = Response::new(()).into_parts();
+    let stored = StoredResponse::new(&parts, &Bytes::from_static(b"done"));
+    service
+      .complete(
+        "user:1",
+        &key,
+        "hash",
+        stored.clone(),
+        Duration::from_secs(60),
+      )
+      .await;
+    match service.claim("user:1", &key, "hash").await {
+      Ok(Claim::Replay(replayed)) => assert_eq!(replayed, stored),
+      other => panic!("expected a replay, got {:?}", other),
+    }
+
+    service.release("user:1", &key).await;
+    service.release("user:2", &key).await;
+  }
+}
@@ -1,18 +1,29 @@
+use std::{
+  collections::{BTreeSet, HashMap},
+  sync::{Arc, Mutex},
+  time::Duration as StdDuration,
+};
+
 use chrono::{Duration, Utc};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
 use utoipa::ToSchema;
 
-use crate::config::AppConfigRc;
+use crate::config::{AppConfigRc, BlacklistPolicy};
 use crate::db::RedisClient;
 use crate::dto::user::AuthTokens;
+use crate::errors::auth::AuthError;
+use crate::services::encryption::spawn_hashing;
 
 #[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum TokenType {
   Access,
   Refresh,
+  Service,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -38,17 +49,87 @@ struct TokenClaims {
   pub exp: i64,
 }
 
+/// Revoked tokens with their expiry, checked while Redis is unavailable. Filled by the revocations
+/// of this instance, the blacklist hits and the periodic sync with Redis.
+///
+/// Holds up to `capacity` tokens, the expired ones and then the ones expiring first make room
+#[derive(Debug)]
+struct LocalRevocations {
+  capacity: usize,
+  state: Mutex<RevocationState>,
+}
+
+#[derive(Debug, Default)]
+struct RevocationState {
+  expiry_by_token: HashMap<String, i64>,
+  by_expiry: BTreeSet<(i64, String)>,
+}
+
+impl Default for LocalRevocations {
+  fn default() -> Self {
+    Self::with_capacity(MAX_LOCAL_REVOCATIONS)
+  }
+}
+
+impl LocalRevocations {
+  fn with_capacity(capacity: usize) -> Self {
+    Self {
+      capacity,
+      state: Mutex::default(),
+    }
+  }
+
+  fn insert(&self, token: &str, expires_at: i64) {
+    let mut state = self.state.lock().unwrap();
+    if let Some(previous) = state.expiry_by_token.remove(token) {
+      state.by_expiry.remove(&(previous, token.to_string()));
+    }
+    state.expiry_by_token.insert(token.to_string(), expires_at);
+    state.by_expiry.insert((expires_at, token.to_string()));
+
+    let now = Utc::now().timestamp();
+    while let Some((expires_at, _)) = state.by_expiry.first() {
+      if *expires_at > now && state.by_expiry.len() <= self.capacity {
+        break;
+      }
+      if let Some((_, token)) = state.by_expiry.pop_first() {
+        state.expiry_by_token.remove(&token);
+      }
+    }
+  }
+
+  fn contains(&self, token: &str) -> bool {
+    self
+      .state
+      .lock()
+      .unwrap()
+      .expiry_by_token
+      .get(token)
+      .is_some_and(|expires_at| *expires_at > Utc::now().timestamp())
+  }
+}
+
 #[derive(Clone, Debug)]
 pub struct JwtService {
   app_config: AppConfigRc,
   redis: RedisClient,
+  revocations: Arc<LocalRevocations>,
 }
 
 const BLACKLIST_PREFIX: &str = "jwt_blacklist:";
+/// Blacklist keys read from Redis per `SCAN` step of the revocation sync
+const REVOCATION_SYNC_BATCH: usize = 500;
+const SERVICE_TOKEN_EXPIRATION: i64 = 60;
+/// Size of the local mirror of the blacklist
+const MAX_LOCAL_REVOCATIONS: usize = 10_000;
 
 impl JwtService {
   pub fn new(app_config: AppConfigRc, redis: RedisClient) -> Self {
-    Self { app_config, redis }
+    Self {
+      app_config,
+      redis,
+      revocations: Arc::default(),
+    }
   }
 
   #[tracing::instrument(name = "build_access_token", skip(self))]
@@ -84,12 +165,58 @@ impl JwtService {
     .map_err(|e| e.to_string())
   }
 
+  /// Build a short-lived token for calling internal services on behalf of the user
+  #[tracing::instrument(name = "build_service_token", skip(self))]
+  pub fn build_service_token(&self, user_id: i32) -> Result<String, String> {
+    encode(
+      &Header::default(),
+      &TokenClaims {
+        user_id: user_id.to_string(),
+        kind: TokenType::Service,
+        iat: Utc::now().timestamp(),
+        exp: (Utc::now() + Duration::seconds(SERVICE_TOKEN_EXPIRATION)).timestamp(),
+      },
+      &EncodingKey::from_secret(self.service_token_secret()?),
+    )
+    .map_err(|e| e.to_string())
+  }
+
+  /// Validate a token issued by [`Self::build_service_token`] and return the user id
+  #[tracing::instrument(name = "decode_service_token", skip(self, token))]
+  pub fn decode_service_token(&self, token: &str) -> Result<i32, String> {
+    let token_data = decode::<TokenClaims>(
+      token,
+      &DecodingKey::from_secret(self.service_token_secret()?),
+      &Validation::default(),
+    )
+    .map_err(|e| e.to_string())?;
+
+    if token_data.claims.kind != TokenType::Service {
+      return Err("invalid token type".to_string());
+    }
+
+    token_data
+      .claims
+      .user_id
+      .parse::<i32>()
+      .map_err(|_| "parse token error".to_string())
+  }
+
+  fn service_token_secret(&self) -> Result<&[u8], String> {
+    self
+      .app_config
+      .service_token_secret
+      .as_deref()
+      .map(str::as_bytes)
+      .ok_or("service token secret is not configured".to_string())
+  }
+
   pub async fn build_tokens(&self, user_id: &str) -> Result<AuthTokens, String> {
     let (send, recv) = tokio::sync::oneshot::channel();
     let jwt_service = self.clone();
     let user_id = user_id.to_string();
 
-    rayon::spawn(move || {
+    spawn_hashing(move || {
       let access_token = jwt_service.build_access_token(&user_id);
       let refresh_token = jwt_service.build_refresh_token(&user_id);
 
@@ -109,31 +236,55 @@ impl JwtService {
     recv.await.map_err(|e| e.to_string())?
   }
 
-  /// Validate a JWT token and return the user id and token type if valid
-  #[tracing::instrument(name = "decode", skip(self))]
-  pub async fn decode(&self, token: &str) -> Result<JwtData, String> {
+  /// Validate a JWT token and return the user id and token type if valid.
+  ///
+  /// While Redis is unavailable the blacklist is checked according to the configured policy
+  #[tracing::instrument(name = "decode", skip(self, token))]
+  pub async fn decode(&self, token: &str) -> Result<JwtData, AuthError> {
     let token_data = decode::<TokenClaims>(
       token,
       &DecodingKey::from_secret(self.app_config.jwt_secret.as_bytes()),
       &Validation::default(),
     )
-    .map_err(|e| e.to_string())?;
+    .map_err(|e| AuthError::invalid_token("jwt", e.to_string()))?;
 
     if (Utc::now().timestamp() - token_data.claims.exp) > 0 {
-      return Err("Token expired".to_string());
+      return Err(AuthError::invalid_token("jwt", "Token expired"));
     }
 
     // Check if the token is blacklisted
     let key = format!("{}{}", BLACKLIST_PREFIX, token);
-    let is_blacklisted = self
-      .redis
-      .lock()
-      .await
-      .get::<_, String>(key)
-      .await
-      .unwrap_or("false".to_string());
-    if is_blacklisted == "true" {
-      return Err("Token blacklisted".to_string());
+    let is_blacklisted = self.redis.connection().get::<_, Option<String>>(key).await;
+    let result = match &is_blacklisted {
+      Ok(Some(value)) if value == "true" => "hit",
+      Ok(_) => "miss",
+      Err(_) => "error",
+    };
+    metrics::counter!("jwt_blacklist_checks_total", "result" => result).increment(1);
+
+    let blacklisted = match is_blacklisted {
+      Ok(_) if result == "hit" => {
+        self.remember_revocation(token, token_data.claims.exp + 60);
+        true
+      }
+      Ok(_) => false,
+      Err(e) => match self.app_config.jwt_blacklist_policy {
+        BlacklistPolicy::FailOpen => {
+          warn!("Blacklist check failed, accepting the token: {}", e);
+          false
+        }
+        BlacklistPolicy::FailClosed => {
+          warn!("Blacklist check failed, rejecting the token: {}", e);
+          return Err(AuthError::RevocationCheckUnavailable);
+        }
+        BlacklistPolicy::LocalFallback => {
+          warn!("Blacklist check failed, using local revocations: {}", e);
+          self.revocations.contains(token)
+        }
+      },
+    };
+    if blacklisted {
+      return Err(AuthError::invalid_token("jwt", "Token blacklisted"));
     }
 
     Ok(JwtData {
@@ -142,7 +293,7 @@ impl JwtService {
     })
   }
 
-  #[tracing::instrument(name = "invalidate", skip(self))]
+  #[tracing::instrument(name = "invalidate", skip(self, token))]
   pub async fn invalidate(&self, token: &str) -> Result<(), String> {
     let key = format!("{}{}", BLACKLIST_PREFIX, token);
     let token_data = decode::<TokenClaims>(
@@ -154,15 +305,156 @@ impl JwtService {
 
     // Info from the token + 1 minute
     let expiry = token_data.claims.exp + 60;
+    self.remember_revocation(token, expiry);
 
+    let ttl = (expiry - Utc::now().timestamp()).max(1);
     self
       .redis
-      .lock()
-      .await
-      .set_ex::<_, _, ()>(key, "true", expiry as u64)
+      .connection()
+      .set_ex::<_, _, ()>(key, "true", ttl as u64)
       .await
       .map_err(|e| e.to_string())?;
 
     Ok(())
   }
+
+  /// Mirror the blacklist of Redis every `interval` with the local fallback policy, so that tokens
+  /// revoked by the other instances stay rejected during an outage
+  pub fn spawn_revocation_sync(&self, interval: StdDuration) -> Option<JoinHandle<()>> {
+    if self.app_config.jwt_blacklist_policy != BlacklistPolicy::LocalFallback {
+      return None;
+    }
+    let jwt_service = self.clone();
+
+    Some(tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(interval);
+      loop {
+        ticker.tick().await;
+        if let Err(e) = jwt_service.sync_revocations().await {
+          error!("Failed to sync revoked tokens from Redis: {}", e);
+        }
+      }
+    }))
+  }
+
+  #[tracing::instrument(name = "sync_revocations", skip(self))]
+  async fn sync_revocations(&self) -> redis::RedisResult<()> {
+    let mut connection = self.redis.connection();
+    let mut cursor = 0_u64;
+    loop {
+      let (next, keys) = redis::cmd("SCAN")
+        .arg(cursor)
+        .arg("MATCH")
+        .arg(format!("{}*", BLACKLIST_PREFIX))
+        .arg("COUNT")
+        .arg(REVOCATION_SYNC_BATCH)
+        .query_async::<(u64, Vec<String>)>(&mut connection)
+        .await?;
+
+      if !keys.is_empty() {
+        let mut pipe = redis::pipe();
+        for key in &keys {
+          pipe.ttl(key);
+        }
+        let ttls = pipe.query_async::<Vec<i64>>(&mut connection).await?;
+        let now = Utc::now().timestamp();
+        for (key, ttl) in keys.iter().zip(ttls) {
+          if let (Some(token), true) = (key.strip_prefix(BLACKLIST_PREFIX), ttl > 0) {
+            self.revocations.insert(token, now + ttl);
+          }
+        }
+      }
+
+      if next == 0 {
+        return Ok(());
+      }
+      cursor = next;
+    }
+  }
+
+  fn remember_revocation(&self, token: &str, expires_at: i64) {
+    if self.app_config.jwt_blacklist_policy == BlacklistPolicy::LocalFallback {
+      self.revocations.insert(token, expires_at);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use clap::Parser;
+
+  use super::*;
+  use crate::{
+    config::AppConfig,
+    db::{testing::FakeRedis, RedisOptions},
+  };
+
+  #[test]
+  fn local_revocations_expire() {
+    let revocations = LocalRevocations::default();
+    let now = Utc::now().timestamp();
+    revocations.insert("revoked", now + 60);
+    revocations.insert("expired", now - 1);
+
+    assert!(revocations.contains("revoked"));
+    assert!(!revocations.contains("expired"));
+    assert!(!revocations.contains("unknown"));
+  }
+
+  #[test]
+  fn local_revocations_evict_tokens_expiring_first() {
+    let revocations = LocalRevocations::with_capacity(2);
+    let now = Utc::now().timestamp();
+    revocations.insert("late", now + 300);
+    revocations.insert("soon", now + 60);
+    revocations.insert("later", now + 600);
+    revocations.insert("late", now + 900);
+
+    assert!(revocations.contains("late"));
+    assert!(revocations.contains("later"));
+    assert!(!revocations.contains("soon"));
+    assert_eq!(revocations.state.lock().unwrap().by_expiry.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn tokens_revoked_elsewhere_stay_rejected_while_redis_is_down() {
+    let redis = FakeRedis::start().await;
+    let config = AppConfig::parse_from([
+      "social_network",
+      "--database-url",
+      "postgres://localhost/app",
+      "--redis-url",
+      &redis.url,
+      "--jwt-secret",
+      "secret",
+      "--jwt-blacklist-policy",
+      "local-fallback",
+    ]);
+    let client = RedisClient::connect(
+      &redis.url,
+      RedisOptions {
+        response_timeout: StdDuration::from_millis(100),
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+    let jwt_service = JwtService::new(Arc::new(config), client);
+    let revoked = jwt_service.build_access_token("1").unwrap();
+    let valid = jwt_service.build_access_token("2").unwrap();
+
+    // Revoked by another instance, seen once while Redis is up
+    redis.reply_to_get(Some("true"));
+    assert!(jwt_service.decode(&revoked).await.is_err());
+
+    redis.set_responsive(false);
+    assert!(
+      matches!(
+        jwt_service.decode(&revoked).await,
+        Err(AuthError::InvalidToken(..))
+      ),
+      "revoked token is rejected during the outage"
+    );
+    assert!(jwt_service.decode(&valid).await.is_ok());
+  }
 }
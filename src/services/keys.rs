@@ -0,0 +1,163 @@
+use sqlx::PgPool;
+
+use crate::{
+  dto::keys::{is_valid_device_id, DeviceKeysDto, PublishDeviceKeysDto, MAX_DEVICES},
+  errors::keys::{KeyError, KeyResult},
+};
+
+/// Directory of the device public keys senders encrypt messages with
+#[derive(Clone, Debug)]
+pub struct KeyService {
+  db: PgPool,
+}
+
+impl KeyService {
+  pub fn new(db: PgPool) -> Self {
+    Self { db }
+  }
+
+  /// Publish keys of a new device or rotate the signed prekey of a published one
+  #[tracing::instrument(name = "publish_device_keys", skip(self, keys))]
+  pub async fn publish(
+    &self,
+    user_id: i32,
+    device_id: String,
+    keys: PublishDeviceKeysDto,
+  ) -> KeyResult<DeviceKeysDto> {
+    if !is_valid_device_id(&device_id) {
+      return Err(KeyError::InvalidDeviceId(device_id));
+    }
+
+    let mut tx = self
+      .db
+      .begin()
+      .await
+      .map_err(KeyError::FailedToUpdateKeys)?;
+
+    // The user row lock serializes concurrent publications against the device limit
+    sqlx::query_scalar!(r#"SELECT id FROM users WHERE id = $1 FOR UPDATE"#, user_id)
+      .fetch_optional(&mut *tx)
+      .await
+      .map_err(KeyError::FailedToUpdateKeys)?
+      .ok_or(KeyError::UserNotFound(user_id))?;
+
+    let row = sqlx::query!(
+      r#"
+        SELECT
+          COUNT(*) AS "devices!",
+          MAX(identity_key) FILTER (WHERE device_id = $2) AS identity_key
+        FROM device_keys
+        WHERE user_id = $1
+      "#,
+      user_id,
+      device_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(KeyError::FailedToUpdateKeys)?;
+
+    match row.identity_key {
+      Some(identity_key) if identity_key != keys.identity_key => {
+        return Err(KeyError::IdentityKeyChanged(device_id));
+      }
+      None if row.devices as usize >= MAX_DEVICES => {
+        return Err(KeyError::TooManyDevices(MAX_DEVICES));
+      }
+      _ => {}
+    }
+
+    let published = sqlx::query_as!(
+      DeviceKeysDto,
+      r#"
+        INSERT INTO device_keys (
+          user_id, device_id, identity_key, signed_prekey_id, signed_prekey, signed_prekey_signature
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (user_id, device_id) DO UPDATE SET
+          signed_prekey_id = EXCLUDED.signed_prekey_id,
+          signed_prekey = EXCLUDED.signed_prekey,
+          signed_prekey_signature = EXCLUDED.signed_prekey_signature,
+          updated_at = NOW()
+        RETURNING
+          user_id,
+          device_id,
+          identity_key,
+          signed_prekey_id,
+          signed_prekey,
+          signed_prekey_signature,
+          created_at,
+          updated_at
+      "#,
+      user_id,
+      device_id,
+      keys.identity_key,
+      keys.signed_prekey.key_id,
+      keys.signed_prekey.public_key,
+      keys.signed_prekey.signature
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(KeyError::FailedToUpdateKeys)?;
+
+    tx.commit().await.map_err(KeyError::FailedToUpdateKeys)?;
+
+    Ok(published)
+  }
+
+  /// Published devices of the user, oldest first
+  #[tracing::instrument(name = "list_device_keys", skip(self))]
+  pub async fn list(&self, user_id: i32) -> KeyResult<Vec<DeviceKeysDto>> {
+    let exists = sqlx::query_scalar!(
+      r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = $1) AS "exists!""#,
+      user_id
+    )
+    .fetch_one(&self.db)
+    .await
+    .map_err(KeyError::FailedToFindKeys)?;
+    if !exists {
+      return Err(KeyError::UserNotFound(user_id));
+    }
+
+    sqlx::query_as!(
+      DeviceKeysDto,
+      r#"
+        SELECT
+          user_id,
+          device_id,
+          identity_key,
+          signed_prekey_id,
+          signed_prekey,
+          signed_prekey_signature,
+          created_at,
+          updated_at
+        FROM device_keys
+        WHERE user_id = $1
+        ORDER BY created_at, device_id
+      "#,
+      user_id
+    )
+    .fetch_all(&self.db)
+    .await
+    .map_err(KeyError::FailedToFindKeys)
+  }
+
+  /// Remove the device from the directory, messages are no longer encrypted for it
+  #[tracing::instrument(name = "delete_device_keys", skip(self))]
+  pub async fn delete(&self, user_id: i32, device_id: String) -> KeyResult<()> {
+    let deleted = sqlx::query!(
+      r#"DELETE FROM device_keys WHERE user_id = $1 AND device_id = $2"#,
+      user_id,
+      device_id
+    )
+    .execute(&self.db)
+    .await
+    .map_err(KeyError::FailedToUpdateKeys)?
+    .rows_affected();
+
+    if deleted == 0 {
+      return Err(KeyError::DeviceNotFound(device_id));
+    }
+
+    Ok(())
+  }
+}
@@ -0,0 +1,77 @@
+use std::sync::OnceLock;
+
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+use sqlx::PgPool;
+
+use crate::db::{replicas::PgCluster, shards::ShardMap};
+
+const HTTP_DURATION_BUCKETS: &[f64] = &[
+  0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const REDIS_DURATION_BUCKETS: &[f64] = &[
+  0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25,
+];
+
+static PROMETHEUS_HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the Prometheus recorder the `metrics` macros report to, once per process
+pub fn install_recorder() -> Result<PrometheusHandle, BuildError> {
+  if let Some(handle) = PROMETHEUS_HANDLE.get() {
+    return Ok(handle.clone());
+  }
+
+  let handle = PrometheusBuilder::new()
+    .set_buckets_for_metric(
+      Matcher::Full("http_request_duration_seconds".to_string()),
+      HTTP_DURATION_BUCKETS,
+    )?
+    .set_buckets_for_metric(
+      Matcher::Full("redis_command_duration_seconds".to_string()),
+      REDIS_DURATION_BUCKETS,
+    )?
+    .install_recorder()?;
+
+  Ok(PROMETHEUS_HANDLE.get_or_init(|| handle).clone())
+}
+
+/// Renders the metrics in Prometheus text format
+#[derive(Clone, Debug)]
+pub struct MetricsService {
+  handle: PrometheusHandle,
+  db: PgCluster,
+  dialog_shards: ShardMap,
+}
+
+impl MetricsService {
+  pub fn new(handle: PrometheusHandle, db: PgCluster, dialog_shards: ShardMap) -> Self {
+    Self {
+      handle,
+      db,
+      dialog_shards,
+    }
+  }
+
+  pub fn render(&self) -> String {
+    for (name, pool) in self.db.pools() {
+      record_pool(name, &pool);
+    }
+    for (index, pool) in self.dialog_shards.all().iter().enumerate() {
+      record_pool(format!("dialog_shard_{}", index), pool);
+    }
+
+    self.handle.run_upkeep();
+    self.handle.render()
+  }
+}
+
+/// Pool utilization is sampled at scrape time, sqlx does not report it on its own
+fn record_pool(name: String, pool: &PgPool) {
+  let size = pool.size() as f64;
+  let idle = pool.num_idle() as f64;
+
+  metrics::gauge!("db_pool_connections", "pool" => name.clone(), "state" => "active")
+    .set(size - idle);
+  metrics::gauge!("db_pool_connections", "pool" => name.clone(), "state" => "idle").set(idle);
+  metrics::gauge!("db_pool_max_connections", "pool" => name)
+    .set(pool.options().get_max_connections() as f64);
+}
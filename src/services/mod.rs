@@ -1,3 +1,18 @@
+pub mod counters;
+pub mod dialog_proxy;
+pub mod dialogs;
 pub mod encryption;
+pub mod event_hub;
+pub mod events;
+pub mod groups;
+pub mod health;
+pub mod idempotency;
 pub mod jwt;
+pub mod keys;
+pub mod metrics;
+pub mod posts;
+pub mod rate_limit;
+pub mod reconciliation;
+pub mod sweeper;
+pub mod user_cache;
 pub mod users;
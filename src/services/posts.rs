@@ -0,0 +1,100 @@
+use crate::{
+  db::replicas::PgCluster,
+  dto::post::{PostAuthorDto, PostDto, PostPageDto, PostVisibility},
+  errors::post::{PostError, PostResult},
+};
+
+#[derive(Clone, Debug)]
+pub struct PostService {
+  db: PgCluster,
+}
+
+impl PostService {
+  pub fn new(db: PgCluster) -> Self {
+    Self { db }
+  }
+
+  #[tracing::instrument(name = "get_author", skip(self))]
+  pub async fn get_author(&self, author_id: i32) -> PostResult<PostAuthorDto> {
+    sqlx::query_as!(
+      PostAuthorDto,
+      r#"SELECT id, first_name, second_name FROM users WHERE id = $1"#,
+      author_id
+    )
+    .fetch_one(&self.db.read().await)
+    .await
+    .map_err(|e| match e {
+      sqlx::Error::RowNotFound => PostError::AuthorNotFound(author_id),
+      _ => PostError::FailedToFindPosts(e),
+    })
+  }
+
+  /// List posts of the author, newest first.
+  ///
+  /// Private posts are returned only when the viewer is the author.
+  #[tracing::instrument(name = "list_by_author", skip(self))]
+  pub async fn list_by_author(
+    &self,
+    author_id: i32,
+    viewer_id: Option<i32>,
+    cursor: Option<i64>,
+    limit: i64,
+  ) -> PostResult<PostPageDto> {
+    self.get_author(author_id).await?;
+
+    self
+      .fetch_page(author_id, viewer_id == Some(author_id), cursor, limit)
+      .await
+  }
+
+  /// Latest public posts of the author for syndication feeds
+  #[tracing::instrument(name = "get_public_feed", skip(self))]
+  pub async fn get_public_feed(
+    &self,
+    author_id: i32,
+    limit: i64,
+  ) -> PostResult<(PostAuthorDto, Vec<PostDto>)> {
+    let author = self.get_author(author_id).await?;
+    let page = self.fetch_page(author_id, false, None, limit).await?;
+
+    Ok((author, page.posts))
+  }
+
+  async fn fetch_page(
+    &self,
+    author_id: i32,
+    include_private: bool,
+    cursor: Option<i64>,
+    limit: i64,
+  ) -> PostResult<PostPageDto> {
+    // Fetch one extra row to know whether there is a next page
+    let mut posts = sqlx::query_as!(
+      PostDto,
+      r#"
+        SELECT id, author_id, content, visibility as "visibility: PostVisibility", created_at, updated_at
+        FROM posts
+        WHERE author_id = $1
+          AND ($2::BIGINT IS NULL OR id < $2)
+          AND ($3 OR visibility = 'public')
+        ORDER BY id DESC
+        LIMIT $4
+      "#,
+      author_id,
+      cursor,
+      include_private,
+      limit + 1
+    )
+    .fetch_all(&self.db.read().await)
+    .await
+    .map_err(PostError::FailedToFindPosts)?;
+
+    let next_cursor = if posts.len() as i64 > limit {
+      posts.truncate(limit as usize);
+      posts.last().map(|post| post.id)
+    } else {
+      None
+    };
+
+    Ok(PostPageDto { posts, next_cursor })
+  }
+}
@@ -0,0 +1,96 @@
+use std::sync::LazyLock;
+
+use redis::Script;
+
+use crate::{
+  config::Quota,
+  db::RedisClient,
+  errors::rate_limit::{RateLimitError, RateLimitResult},
+};
+
+const RATE_LIMIT_PREFIX: &str = "rate_limit:";
+
+/// Take a token from the bucket of ARGV[1] tokens refilled one every ARGV[2] milliseconds.
+///
+/// Returns whether it was taken, tokens left, milliseconds until the bucket is full and
+/// milliseconds until the next token. Redis time keeps the instances on the same clock
+static TOKEN_BUCKET_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+  Script::new(
+    r"
+      local capacity = tonumber(ARGV[1])
+      local refill = tonumber(ARGV[2])
+      local time = redis.call('TIME')
+      local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+      local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'at')
+      local tokens = tonumber(bucket[1]) or capacity
+      local at = tonumber(bucket[2]) or now
+      tokens = math.min(capacity, tokens + math.max(now - at, 0) / refill)
+
+      local allowed = 0
+      if tokens >= 1 then
+        tokens = tokens - 1
+        allowed = 1
+      end
+
+      local full_in = math.ceil((capacity - tokens) * refill)
+      redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'at', now)
+      redis.call('PEXPIRE', KEYS[1], full_in + 1000)
+
+      local next_in = 0
+      if allowed == 0 then
+        next_in = math.ceil((1 - tokens) * refill)
+      end
+      return {allowed, math.floor(tokens), full_in, next_in}
+    ",
+  )
+});
+
+/// Outcome of taking a request from the quota
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitDecision {
+  pub allowed: bool,
+  pub limit: u32,
+  pub remaining: u32,
+  /// Seconds until the quota is fully available again
+  pub reset: u64,
+  /// Seconds until the next request is allowed, zero when this one was
+  pub retry_after: u64,
+}
+
+/// Token buckets in Redis shared by the instances
+#[derive(Clone, Debug)]
+pub struct RateLimitService {
+  redis: RedisClient,
+}
+
+impl RateLimitService {
+  pub fn new(redis: RedisClient) -> Self {
+    Self { redis }
+  }
+
+  /// Take one request from the quota of the client in the route group
+  pub async fn check(
+    &self,
+    group: &str,
+    client: &str,
+    quota: Quota,
+  ) -> RateLimitResult<RateLimitDecision> {
+    let refill_ms = (quota.period.as_millis() as f64 / f64::from(quota.requests)).max(1.0);
+    let (allowed, remaining, full_in, next_in) = TOKEN_BUCKET_SCRIPT
+      .key(format!("{}{}:{}", RATE_LIMIT_PREFIX, group, client))
+      .arg(quota.requests)
+      .arg(refill_ms)
+      .invoke_async::<(i32, u32, u64, u64)>(&mut self.redis.connection())
+      .await
+      .map_err(RateLimitError::FailedToCheck)?;
+
+    Ok(RateLimitDecision {
+      allowed: allowed == 1,
+      limit: quota.requests,
+      remaining,
+      reset: full_in.div_ceil(1000),
+      retry_after: next_in.div_ceil(1000),
+    })
+  }
+}
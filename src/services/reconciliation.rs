@@ -0,0 +1,134 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  time::Duration,
+};
+
+use tracing::{info, warn};
+
+use crate::{
+  db::dialogs::DialogStore,
+  dto::counter::{Conversation, CounterReconciliationDto},
+  errors::counter::{CounterError, CounterResult},
+  services::{counters::CounterService, groups::GroupService},
+};
+
+/// Rebuilds unread counters from the dialog and group messages, fixing the ones which drifted
+#[derive(Clone, Debug)]
+pub struct CounterReconciler {
+  counter_service: CounterService,
+  dialog_store: DialogStore,
+  group_service: GroupService,
+}
+
+impl CounterReconciler {
+  pub fn new(
+    counter_service: CounterService,
+    dialog_store: DialogStore,
+    group_service: GroupService,
+  ) -> Self {
+    Self {
+      counter_service,
+      dialog_store,
+      group_service,
+    }
+  }
+
+  /// Counters are snapshotted before reading the messages and only replaced if they did not change
+  /// meanwhile, so a concurrent saga is never overwritten and is checked again by the next run
+  #[tracing::instrument(name = "reconcile_counters", skip(self))]
+  pub async fn reconcile(&self) -> CounterResult<CounterReconciliationDto> {
+    let snapshots = self.counter_service.snapshot_all().await?;
+
+    let mut expected = HashMap::<i32, BTreeMap<Conversation, i64>>::new();
+    for count in self
+      .dialog_store
+      .unread_counts()
+      .await
+      .map_err(|e| CounterError::FailedToRebuildCounters(e.to_string()))?
+    {
+      expected
+        .entry(count.user_id)
+        .or_default()
+        .insert(Conversation::Dialog(count.peer_id), count.unread);
+    }
+    for count in self
+      .group_service
+      .unread_counts()
+      .await
+      .map_err(|e| CounterError::FailedToRebuildCounters(e.to_string()))?
+    {
+      expected
+        .entry(count.user_id)
+        .or_default()
+        .insert(Conversation::Group(count.group_id), count.unread);
+    }
+
+    let mut user_ids = snapshots.keys().chain(expected.keys()).collect::<Vec<_>>();
+    user_ids.sort_unstable();
+    user_ids.dedup();
+
+    let mut report = CounterReconciliationDto {
+      checked_users: user_ids.len(),
+      ..Default::default()
+    };
+    let empty = BTreeMap::new();
+    for user_id in user_ids {
+      let (drifted, fixed) = self
+        .counter_service
+        .reconcile(
+          *user_id,
+          &snapshots.get(user_id).cloned().unwrap_or_default(),
+          expected.get(user_id).unwrap_or(&empty),
+        )
+        .await?;
+
+      if drifted {
+        warn!(
+          "Unread counters of user {} drifted, fixed: {}",
+          user_id, fixed
+        );
+        report.drifted_users += 1;
+      }
+      if fixed {
+        report.fixed_users += 1;
+      }
+    }
+
+    Ok(report)
+  }
+
+  /// Periodically reconcile counters, the run is skipped if another instance holds the lock
+  pub fn spawn(&self, interval: Duration) {
+    let reconciler = self.clone();
+
+    tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(interval);
+      ticker.tick().await;
+
+      loop {
+        ticker.tick().await;
+
+        match reconciler
+          .counter_service
+          .try_lock_reconciliation(interval)
+          .await
+        {
+          Ok(false) => continue,
+          Ok(true) => {}
+          Err(e) => {
+            warn!("Failed to lock counter reconciliation: {}", e);
+            continue;
+          }
+        }
+
+        match reconciler.reconcile().await {
+          Ok(report) => info!(
+            "Reconciled unread counters: checked {}, drifted {}, fixed {}",
+            report.checked_users, report.drifted_users, report.fixed_users
+          ),
+          Err(e) => warn!("Failed to reconcile unread counters: {}", e),
+        }
+      }
+    });
+  }
+}
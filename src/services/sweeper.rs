@@ -0,0 +1,101 @@
+use std::{collections::HashMap, time::Duration};
+
+use tracing::{error, info, warn};
+
+use crate::{
+  db::dialogs::DialogStore,
+  dto::{counter::Conversation, dialog::MessageSweepDto},
+  services::{counters::CounterService, groups::GroupService},
+};
+
+/// Purges expired disappearing messages and takes them out of the unread counters.
+///
+/// Clients know when messages expire, so purges are not pushed as realtime events
+#[derive(Clone, Debug)]
+pub struct MessageSweeper {
+  dialog_store: DialogStore,
+  group_service: GroupService,
+  counter_service: CounterService,
+}
+
+impl MessageSweeper {
+  pub fn new(
+    dialog_store: DialogStore,
+    group_service: GroupService,
+    counter_service: CounterService,
+  ) -> Self {
+    Self {
+      dialog_store,
+      group_service,
+      counter_service,
+    }
+  }
+
+  /// Dialogs and groups are swept independently, a failed store is retried by the next sweep
+  #[tracing::instrument(name = "sweep_messages", skip(self))]
+  pub async fn sweep(&self) -> MessageSweepDto {
+    let mut report = MessageSweepDto::default();
+    let mut unread = HashMap::<(i32, Conversation), i64>::new();
+
+    match self.dialog_store.purge_expired().await {
+      Ok(purged) => {
+        report.dialog_messages = purged.len();
+        for deleted in purged.iter().filter(|deleted| deleted.unread) {
+          let conversation = Conversation::Dialog(deleted.message.sender_id);
+          *unread
+            .entry((deleted.message.recipient_id(), conversation))
+            .or_default() += 1;
+        }
+      }
+      Err(e) => warn!("Failed to purge expired dialog messages: {}", e),
+    }
+
+    match self.group_service.purge_expired().await {
+      Ok((purged, counts)) => {
+        report.group_messages = purged;
+        for count in counts {
+          *unread
+            .entry((count.user_id, Conversation::Group(count.group_id)))
+            .or_default() += count.unread;
+        }
+      }
+      Err(e) => warn!("Failed to purge expired group messages: {}", e),
+    }
+
+    for ((user_id, conversation), count) in unread {
+      if let Err(e) = self
+        .counter_service
+        .increment(user_id, conversation, -count)
+        .await
+      {
+        error!(
+          "Failed to take purged messages out of unread counter of user {}: {}, left to reconciliation",
+          user_id, e
+        );
+      }
+    }
+
+    report
+  }
+
+  /// Periodically purge expired messages, concurrent sweeps of other instances delete disjoint rows
+  pub fn spawn(&self, interval: Duration) {
+    let sweeper = self.clone();
+
+    tokio::spawn(async move {
+      let mut ticker = tokio::time::interval(interval);
+
+      loop {
+        ticker.tick().await;
+
+        let report = sweeper.sweep().await;
+        if report.dialog_messages > 0 || report.group_messages > 0 {
+          info!(
+            "Purged expired messages: {} from dialogs, {} from groups",
+            report.dialog_messages, report.group_messages
+          );
+        }
+      }
+    });
+  }
+}
@@ -0,0 +1,207 @@
+use std::{
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use hashlink::LruCache;
+use redis::AsyncCommands;
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::{config::AppConfig, db::RedisClient, dto::user::UserDto};
+
+const USER_CACHE_PREFIX: &str = "user_cache:";
+/// Postgres channel the `users` trigger notifies with the id of the changed user
+const USER_CHANGED_CHANNEL: &str = "user_changed";
+const LISTENER_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Bounded in-process tier, least recently used users are evicted first
+struct LocalCache {
+  users: LruCache<i32, (UserDto, Instant)>,
+  ttl: Duration,
+}
+
+impl LocalCache {
+  fn new(capacity: usize, ttl: Duration) -> Self {
+    Self {
+      users: LruCache::new(capacity.max(1)),
+      ttl,
+    }
+  }
+
+  fn get(&mut self, id: i32) -> Option<UserDto> {
+    match self.users.get(&id) {
+      Some((user, cached_at)) if cached_at.elapsed() < self.ttl => Some(user.clone()),
+      Some(_) => {
+        self.users.remove(&id);
+        None
+      }
+      None => None,
+    }
+  }
+
+  fn insert(&mut self, user: UserDto) {
+    self.users.insert(user.id, (user, Instant::now()));
+  }
+
+  fn remove(&mut self, id: i32) {
+    self.users.remove(&id);
+  }
+
+  fn clear(&mut self) {
+    self.users.clear();
+  }
+}
+
+/// Users by id in an in-process LRU in front of Redis shared by the instances.
+///
+/// Changes of the `users` rows evict them everywhere through Postgres `LISTEN/NOTIFY`
+#[derive(Clone)]
+pub struct UserCache {
+  local: Arc<Mutex<LocalCache>>,
+  redis: RedisClient,
+  redis_ttl: Duration,
+}
+
+impl std::fmt::Debug for UserCache {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("UserCache").finish_non_exhaustive()
+  }
+}
+
+impl UserCache {
+  pub fn new(redis: RedisClient, config: &AppConfig) -> Self {
+    Self {
+      local: Arc::new(Mutex::new(LocalCache::new(
+        config.user_cache_capacity,
+        Duration::from_secs(config.user_cache_local_ttl),
+      ))),
+      redis,
+      redis_ttl: Duration::from_secs(config.user_cache_redis_ttl),
+    }
+  }
+
+  pub async fn get(&self, id: i32) -> Option<UserDto> {
+    if let Some(user) = self.local.lock().unwrap().get(id) {
+      metrics::counter!("user_cache_hits_total", "tier" => "local").increment(1);
+      return Some(user);
+    }
+
+    let cached = self
+      .redis
+      .connection()
+      .get::<_, Option<String>>(user_key(id))
+      .await
+      .inspect_err(|e| warn!("Failed to read user {} from cache: {}", id, e))
+      .ok()
+      .flatten()
+      .and_then(|user| serde_json::from_str::<UserDto>(&user).ok());
+    match cached {
+      Some(user) => {
+        metrics::counter!("user_cache_hits_total", "tier" => "redis").increment(1);
+        self.local.lock().unwrap().insert(user.clone());
+        Some(user)
+      }
+      None => {
+        metrics::counter!("user_cache_misses_total").increment(1);
+        None
+      }
+    }
+  }
+
+  pub async fn insert(&self, user: &UserDto) {
+    self.local.lock().unwrap().insert(user.clone());
+
+    let Ok(value) = serde_json::to_string(user) else {
+      return;
+    };
+    if let Err(e) = self
+      .redis
+      .connection()
+      .set_ex::<_, _, ()>(user_key(user.id), value, self.redis_ttl.as_secs().max(1))
+      .await
+    {
+      warn!("Failed to cache user {}: {}", user.id, e);
+    }
+  }
+
+  /// Evict the user from both tiers, the other instances drop their local copies on the
+  /// notification of the same change
+  pub async fn invalidate(&self, id: i32) {
+    self.local.lock().unwrap().remove(id);
+
+    if let Err(e) = self.redis.connection().del::<_, ()>(user_key(id)).await {
+      warn!("Failed to evict user {} from cache: {}", id, e);
+    }
+  }
+
+  /// Evict users on the notifications of the `users` trigger. Notifications sent while
+  /// the listener reconnects are lost, the local tier is cleared then
+  pub fn spawn_invalidation_listener(&self, pool: PgPool) -> JoinHandle<()> {
+    let cache = self.clone();
+
+    tokio::spawn(async move {
+      loop {
+        if let Err(e) = cache.listen(&pool).await {
+          error!("User cache invalidation listener failed: {}", e);
+        }
+        cache.local.lock().unwrap().clear();
+        tokio::time::sleep(LISTENER_RETRY_DELAY).await;
+      }
+    })
+  }
+
+  async fn listen(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(USER_CHANGED_CHANNEL).await?;
+    info!("Listening for user changes to invalidate the user cache");
+
+    // `None` means the connection was lost, it is re-established by the next call
+    while let Some(notification) = listener.try_recv().await? {
+      if let Ok(id) = notification.payload().parse::<i32>() {
+        self.invalidate(id).await;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+fn user_key(id: i32) -> String {
+  format!("{}{}", USER_CACHE_PREFIX, id)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn user(id: i32) -> UserDto {
+    UserDto {
+      id,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn local_tier_evicts_least_recently_used_and_expired_users() {
+    let mut cache = LocalCache::new(2, Duration::from_millis(50));
+    cache.insert(user(1));
+    cache.insert(user(2));
+    assert!(cache.get(1).is_some());
+
+    cache.insert(user(3));
+    assert!(
+      cache.get(2).is_none(),
+      "least recently used user is evicted"
+    );
+    assert!(cache.get(1).is_some());
+    assert!(cache.get(3).is_some());
+
+    cache.remove(3);
+    assert!(cache.get(3).is_none());
+
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(cache.get(1).is_none(), "expired user is not returned");
+  }
+}
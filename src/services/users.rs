@@ -1,43 +1,67 @@
 use chrono::NaiveDate;
-use sqlx::PgPool;
 
 use crate::{
+  db::replicas::PgCluster,
   dto::user::{LoginDto, SignUpDto, UserDto, UserWithTokenDto},
   errors::user::{UserError, UserResult},
-  services::{encryption::EncryptionService, jwt::JwtService},
+  services::{encryption::EncryptionService, jwt::JwtService, user_cache::UserCache},
 };
 
 #[derive(Clone, Debug)]
 pub struct UserService {
-  db: PgPool,
+  db: PgCluster,
+  cache: UserCache,
   jwt_service: JwtService,
   encryption_service: EncryptionService,
 }
 
 impl UserService {
-  pub fn new(db: PgPool, jwt_service: JwtService, encryption_service: EncryptionService) -> Self {
+  pub fn new(
+    db: PgCluster,
+    cache: UserCache,
+    jwt_service: JwtService,
+    encryption_service: EncryptionService,
+  ) -> Self {
     Self {
       db,
+      cache,
       jwt_service,
       encryption_service,
     }
   }
 
+  pub fn cache(&self) -> &UserCache {
+    &self.cache
+  }
+
+  /// Cached user without the password hash, it is only needed to log in.
+  ///
+  /// Misses read from the primary, a lagging replica would cache the user as it was before
+  /// the change that just evicted it
   #[tracing::instrument(name = "get_by_id", skip(self))]
   pub async fn get_by_id(&self, id: i32) -> UserResult<UserDto> {
-    sqlx::query_as!(UserDto, r#"SELECT * FROM users WHERE id = $1"#, id)
-      .fetch_one(&self.db)
+    if let Some(user) = self.cache.get(id).await {
+      return Ok(user);
+    }
+
+    let mut user = sqlx::query_as!(UserDto, r#"SELECT * FROM users WHERE id = $1"#, id)
+      .fetch_one(&self.db.write())
       .await
       .map_err(|e| match e {
         sqlx::Error::RowNotFound => UserError::UserNotFound(id.to_string()),
         _ => UserError::FailedToFindUser(e),
-      })
+      })?;
+    user.password.clear();
+    self.cache.insert(&user).await;
+
+    Ok(user)
   }
 
-  #[tracing::instrument(name = "get_by_email", skip(self))]
+  /// Read from the primary, a user logging in right after signing up may be missing on replicas
+  #[tracing::instrument(name = "get_by_email", skip(self, email))]
   pub async fn get_by_email(&self, email: &str) -> UserResult<UserDto> {
     sqlx::query_as!(UserDto, r#"SELECT * FROM users WHERE email = $1"#, email)
-      .fetch_one(&self.db)
+      .fetch_one(&self.db.write())
       .await
       .map_err(|e| match e {
         sqlx::Error::RowNotFound => UserError::UserNotFound(email.to_string()),
@@ -45,7 +69,7 @@ impl UserService {
       })
   }
 
-  #[tracing::instrument(name = "sign_up", skip(self))]
+  #[tracing::instrument(name = "sign_up", skip(self, signup_dto))]
   pub async fn sign_up(&self, signup_dto: SignUpDto) -> UserResult<UserWithTokenDto> {
     let hashed_password = self.hash_password(signup_dto.password).await?;
     let user = UserDto {
@@ -68,7 +92,7 @@ impl UserService {
       user.email,
       user.password
     )
-    .fetch_one(&self.db)
+    .fetch_one(&self.db.write())
     .await
     .map_err(UserError::FailedToCreateUser)?;
 
@@ -81,7 +105,7 @@ impl UserService {
     Ok(UserWithTokenDto { user, tokens })
   }
 
-  #[tracing::instrument(name = "login", skip(self))]
+  #[tracing::instrument(name = "login", skip(self, login_dto))]
   pub async fn login(&self, login_dto: LoginDto) -> UserResult<UserWithTokenDto> {
     let user = self.get_by_email(&login_dto.email).await?;
     let is_password_valid = self
@@ -102,7 +126,7 @@ impl UserService {
     Ok(UserWithTokenDto { user, tokens })
   }
 
-  #[tracing::instrument]
+  #[tracing::instrument(skip(self, password))]
   async fn hash_password(&self, password: String) -> UserResult<String> {
     self
       .encryption_service
@@ -0,0 +1,169 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::{
+  global,
+  propagation::{Extractor, Injector},
+  trace::{TraceContextExt, TracerProvider},
+};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+  filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+use crate::{
+  config::{AppConfig, LogFormat},
+  errors::common::InitError,
+  telemetry::redact::{RedactingExporter, RedactingMakeWriter},
+};
+
+pub mod redact;
+
+pub const TRACE_ID_HEADER: &str = "x-trace-id";
+
+/// Flushes the spans not exported yet when dropped at shutdown
+pub struct TelemetryGuard {
+  provider: SdkTracerProvider,
+}
+
+impl Drop for TelemetryGuard {
+  fn drop(&mut self) {
+    let _ = self.provider.shutdown();
+  }
+}
+
+/// Log to stdout filtered by `LOG_LEVEL` and, when the endpoint is given, export spans over OTLP/HTTP.
+/// Sensitive values are masked in both.
+///
+/// Spans get trace ids and W3C trace context propagation even without the exporter
+pub fn init_tracing(config: &AppConfig) -> Result<TelemetryGuard, InitError> {
+  let service_name = &config.otel_service_name;
+  global::set_text_map_propagator(TraceContextPropagator::new());
+
+  let mut provider = SdkTracerProvider::builder().with_resource(
+    Resource::builder()
+      .with_service_name(service_name.to_string())
+      .build(),
+  );
+  if let Some(endpoint) = &config.otlp_endpoint {
+    let exporter = SpanExporter::builder()
+      .with_http()
+      .with_endpoint(endpoint)
+      .build()
+      .map_err(InitError::Tracing)?;
+    provider = provider.with_batch_exporter(RedactingExporter(exporter));
+  }
+  let provider = provider.build();
+  let tracer = provider.tracer(service_name.to_string());
+  global::set_tracer_provider(provider.clone());
+
+  let logs = tracing_subscriber::fmt::layer().with_writer(RedactingMakeWriter(std::io::stdout));
+  let logs = match config.log_format {
+    LogFormat::Text => logs.boxed(),
+    LogFormat::Json => logs.json().boxed(),
+  };
+
+  tracing_subscriber::registry()
+    .with(logs.with_filter(EnvFilter::from_env("LOG_LEVEL")))
+    .with(
+      tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(LevelFilter::INFO),
+    )
+    .try_init()?;
+
+  Ok(TelemetryGuard { provider })
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+  fn get(&self, key: &str) -> Option<&str> {
+    self.0.get(key).and_then(|value| value.to_str().ok())
+  }
+
+  fn keys(&self) -> Vec<&str> {
+    self.0.keys().map(HeaderName::as_str).collect()
+  }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+  fn set(&mut self, key: &str, value: String) {
+    if let (Ok(name), Ok(value)) = (
+      HeaderName::from_bytes(key.as_bytes()),
+      HeaderValue::from_str(&value),
+    ) {
+      self.0.insert(name, value);
+    }
+  }
+}
+
+/// Continue the trace of the caller given by the `traceparent` header, if any
+pub fn set_parent_from_headers(span: &Span, headers: &HeaderMap) {
+  let context =
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+  if context.span().span_context().is_valid() {
+    span.set_parent(context);
+  }
+}
+
+/// `traceparent` header of the current span for an outgoing request
+pub fn trace_context_headers() -> HeaderMap {
+  let context = Span::current().context();
+  let mut headers = HeaderMap::new();
+  global::get_text_map_propagator(|propagator| {
+    propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+  });
+
+  headers
+}
+
+/// Trace id of the span in hex, `None` outside of a traced span
+pub fn trace_id(span: &Span) -> Option<String> {
+  let context = span.context();
+  let span_context = context.span().span_context().clone();
+
+  span_context
+    .is_valid()
+    .then(|| span_context.trace_id().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+
+  use super::*;
+
+  #[test]
+  fn trace_context_is_extracted_and_injected() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    let mut headers = HeaderMap::new();
+    headers.insert("traceparent", HeaderValue::from_static(traceparent));
+    let context =
+      global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(&headers)));
+    let span_context = context.span().span_context().clone();
+    assert_eq!(
+      span_context.trace_id(),
+      TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+    );
+    assert!(span_context.is_remote());
+
+    let parent = opentelemetry::Context::new().with_remote_span_context(SpanContext::new(
+      span_context.trace_id(),
+      SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+      TraceFlags::SAMPLED,
+      true,
+      TraceState::default(),
+    ));
+    let mut outgoing = HeaderMap::new();
+    global::get_text_map_propagator(|propagator| {
+      propagator.inject_context(&parent, &mut HeaderInjector(&mut outgoing))
+    });
+    assert_eq!(outgoing.get("traceparent").unwrap(), traceparent);
+  }
+}